@@ -21,6 +21,16 @@ class InsufficientFunds implements Exception {
   InsufficientFunds(String s, this.needed, this.available);
 }
 
+// Bumping the fee needs more than the change output has, and no other coins cover it
+class FeeBumpChangeTooSmall implements Exception {
+  final int needed;
+  final int available;
+
+  FeeBumpChangeTooSmall(String s, this.needed, this.available);
+}
+
+class FeeRateTooLow implements Exception {}
+
 class BelowDustLimit implements Exception {}
 
 class InvalidPort implements Exception {}
@@ -31,6 +41,8 @@ class InvalidNetwork implements Exception {}
 
 class InvalidMnemonic implements Exception {}
 
+class TaskCancelled implements Exception {}
+
 // Work around for https://github.com/flutter/flutter/issues/90990
 Exception getIsolateException(String isolateError) {
   if (isolateError.contains("InvalidPort")) {
@@ -43,7 +55,13 @@ Exception getIsolateException(String isolateError) {
 }
 
 Exception _getRustException(String rustError) {
-  if (rustError.startsWith('Insufficient')) {
+  if (rustError.contains("Change can't absorb the higher fee")) {
+    int available = int.parse(captureBetween(rustError, 'either: ', ' sat'));
+    int needed = int.parse(captureBetween(rustError, 'of ', ' sat needed'));
+    return FeeBumpChangeTooSmall(rustError, needed, available);
+  } else if (rustError.startsWith('Fee rate too low')) {
+    return FeeRateTooLow();
+  } else if (rustError.startsWith('Insufficient')) {
     int available = int.parse(captureBetween(rustError, 'funds: ', ' sat'));
     int needed = int.parse(captureBetween(rustError, 'of ', ' sat needed'));
     return InsufficientFunds(rustError, needed, available);
@@ -62,6 +80,11 @@ Exception _getRustException(String rustError) {
   }
 }
 
+// The *_async calls post their error instead of setting the last one
+Exception getTaskException(String rustError) {
+  return _getRustException(rustError);
+}
+
 throwRustException(DynamicLibrary lib) {
   String rustError = _lastErrorMessage(lib);
   throw _getRustException(rustError);
@@ -78,6 +78,7 @@ class NativeLibrary {
     ffi.Pointer<ffi.Char> seed_words,
     ffi.Pointer<ffi.Char> passphrase,
     ffi.Pointer<ffi.Char> path,
+    int script_type,
     int network,
     bool init_wallet,
     ffi.Pointer<ffi.Char> data_dir,
@@ -87,6 +88,7 @@ class NativeLibrary {
       seed_words,
       passphrase,
       path,
+      script_type,
       network,
       init_wallet,
       data_dir,
@@ -101,12 +103,142 @@ class NativeLibrary {
               ffi.Pointer<ffi.Char>,
               ffi.Pointer<ffi.Char>,
               ffi.Int32,
+              ffi.Int32,
               ffi.Bool,
               ffi.Pointer<ffi.Char>,
               ffi.Bool)>>('wallet_derive');
   late final _wallet_derive = _wallet_derivePtr.asFunction<
-      Wallet Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>,
-          ffi.Pointer<ffi.Char>, int, bool, ffi.Pointer<ffi.Char>, bool)>();
+      Wallet Function(
+          ffi.Pointer<ffi.Char>,
+          ffi.Pointer<ffi.Char>,
+          ffi.Pointer<ffi.Char>,
+          int,
+          int,
+          bool,
+          ffi.Pointer<ffi.Char>,
+          bool)>();
+
+  Wallet wallet_derive_multisig(
+    int threshold,
+    int xpubs_len,
+    ffi.Pointer<ffi.Pointer<ffi.Char>> xpubs,
+    int network,
+    bool init_wallet,
+    ffi.Pointer<ffi.Char> data_dir,
+  ) {
+    return _wallet_derive_multisig(
+      threshold,
+      xpubs_len,
+      xpubs,
+      network,
+      init_wallet,
+      data_dir,
+    );
+  }
+
+  late final _wallet_derive_multisigPtr = _lookup<
+      ffi.NativeFunction<
+          Wallet Function(
+              ffi.Uint8,
+              ffi.Uint8,
+              ffi.Pointer<ffi.Pointer<ffi.Char>>,
+              ffi.Int32,
+              ffi.Bool,
+              ffi.Pointer<ffi.Char>)>>('wallet_derive_multisig');
+  late final _wallet_derive_multisig = _wallet_derive_multisigPtr.asFunction<
+      Wallet Function(int, int, ffi.Pointer<ffi.Pointer<ffi.Char>>, int, bool,
+          ffi.Pointer<ffi.Char>)>();
+
+  ImportedAccountList wallet_parse_account_export(
+    ffi.Pointer<ffi.Char> contents,
+    int network,
+  ) {
+    return _wallet_parse_account_export(
+      contents,
+      network,
+    );
+  }
+
+  late final _wallet_parse_account_exportPtr = _lookup<
+      ffi.NativeFunction<
+          ImportedAccountList Function(ffi.Pointer<ffi.Char>,
+              ffi.Int32)>>('wallet_parse_account_export');
+  late final _wallet_parse_account_export = _wallet_parse_account_exportPtr
+      .asFunction<ImportedAccountList Function(ffi.Pointer<ffi.Char>, int)>();
+
+  DescriptorInfo wallet_get_descriptor_info(
+    ffi.Pointer<ffi.Char> descriptor,
+  ) {
+    return _wallet_get_descriptor_info(
+      descriptor,
+    );
+  }
+
+  late final _wallet_get_descriptor_infoPtr = _lookup<
+          ffi.NativeFunction<DescriptorInfo Function(ffi.Pointer<ffi.Char>)>>(
+      'wallet_get_descriptor_info');
+  late final _wallet_get_descriptor_info = _wallet_get_descriptor_infoPtr
+      .asFunction<DescriptorInfo Function(ffi.Pointer<ffi.Char>)>();
+
+  XpubDescriptors wallet_get_xpub_descriptors(
+    ffi.Pointer<ffi.Char> xpub,
+    ffi.Pointer<ffi.Char> fingerprint,
+    int script_type,
+    int network,
+  ) {
+    return _wallet_get_xpub_descriptors(
+      xpub,
+      fingerprint,
+      script_type,
+      network,
+    );
+  }
+
+  late final _wallet_get_xpub_descriptorsPtr = _lookup<
+      ffi.NativeFunction<
+          XpubDescriptors Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>,
+              ffi.Int32, ffi.Int32)>>('wallet_get_xpub_descriptors');
+  late final _wallet_get_xpub_descriptors =
+      _wallet_get_xpub_descriptorsPtr.asFunction<
+          XpubDescriptors Function(
+              ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, int, int)>();
+
+  DiscoveredAccountList wallet_discover_accounts(
+    ffi.Pointer<ffi.Char> seed_words,
+    ffi.Pointer<ffi.Char> passphrase,
+    int network,
+    ffi.Pointer<ffi.Char> electrum_address,
+    int tor_port,
+    SyncConfig sync_config,
+  ) {
+    return _wallet_discover_accounts(
+      seed_words,
+      passphrase,
+      network,
+      electrum_address,
+      tor_port,
+      sync_config,
+    );
+  }
+
+  late final _wallet_discover_accountsPtr = _lookup<
+      ffi.NativeFunction<
+          DiscoveredAccountList Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>,
+              ffi.Int32,
+              ffi.Pointer<ffi.Char>,
+              ffi.Int32,
+              SyncConfig)>>('wallet_discover_accounts');
+  late final _wallet_discover_accounts =
+      _wallet_discover_accountsPtr.asFunction<
+          DiscoveredAccountList Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>,
+              int,
+              ffi.Pointer<ffi.Char>,
+              int,
+              SyncConfig)>();
 
   ffi.Pointer<ffi.Char> wallet_get_address(
     ffi.Pointer<ffi.Char> wallet,
@@ -138,6 +270,62 @@ class NativeLibrary {
   late final _wallet_get_change_address = _wallet_get_change_addressPtr
       .asFunction<ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>)>();
 
+  ffi.Pointer<ffi.Char> wallet_get_unused_address(
+    ffi.Pointer<ffi.Char> wallet,
+  ) {
+    return _wallet_get_unused_address(
+      wallet,
+    );
+  }
+
+  late final _wallet_get_unused_addressPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(
+              ffi.Pointer<ffi.Char>)>>('wallet_get_unused_address');
+  late final _wallet_get_unused_address = _wallet_get_unused_addressPtr
+      .asFunction<ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>)>();
+
+  AddressList wallet_get_addresses(
+    ffi.Pointer<ffi.Char> wallet,
+    int keychain,
+    int offset,
+    int limit,
+  ) {
+    return _wallet_get_addresses(
+      wallet,
+      keychain,
+      offset,
+      limit,
+    );
+  }
+
+  late final _wallet_get_addressesPtr = _lookup<
+      ffi.NativeFunction<
+          AddressList Function(ffi.Pointer<ffi.Char>, ffi.Int32, ffi.Uint32,
+              ffi.Uint32)>>('wallet_get_addresses');
+  late final _wallet_get_addresses = _wallet_get_addressesPtr
+      .asFunction<AddressList Function(ffi.Pointer<ffi.Char>, int, int, int)>();
+
+  AddressOwner wallet_find_address(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<ffi.Char> address_or_script,
+    int search_limit,
+  ) {
+    return _wallet_find_address(
+      wallet,
+      address_or_script,
+      search_limit,
+    );
+  }
+
+  late final _wallet_find_addressPtr = _lookup<
+      ffi.NativeFunction<
+          AddressOwner Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>,
+              ffi.Uint32)>>('wallet_find_address');
+  late final _wallet_find_address = _wallet_find_addressPtr.asFunction<
+      AddressOwner Function(
+          ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, int)>();
+
   bool wallet_sync(
     ffi.Pointer<ffi.Char> wallet,
     ffi.Pointer<ffi.Char> electrum_address,
@@ -157,6 +345,89 @@ class NativeLibrary {
   late final _wallet_sync = _wallet_syncPtr.asFunction<
       bool Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, int)>();
 
+  bool wallet_sync_with_progress(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<ffi.Char> electrum_address,
+    int tor_port,
+    int isolate_port,
+  ) {
+    return _wallet_sync_with_progress(
+      wallet,
+      electrum_address,
+      tor_port,
+      isolate_port,
+    );
+  }
+
+  late final _wallet_sync_with_progressPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Bool Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>,
+              ffi.Int32, ffi.Int64)>>('wallet_sync_with_progress');
+  late final _wallet_sync_with_progress =
+      _wallet_sync_with_progressPtr.asFunction<
+          bool Function(
+              ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, int, int)>();
+
+  bool wallet_sync_compact_filters(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<ffi.Char> peers,
+    int tor_port,
+    ffi.Pointer<ffi.Char> wallet_dir,
+    int isolate_port,
+  ) {
+    return _wallet_sync_compact_filters(
+      wallet,
+      peers,
+      tor_port,
+      wallet_dir,
+      isolate_port,
+    );
+  }
+
+  late final _wallet_sync_compact_filtersPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Bool Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>,
+              ffi.Int32,
+              ffi.Pointer<ffi.Char>,
+              ffi.Int64)>>('wallet_sync_compact_filters');
+  late final _wallet_sync_compact_filters =
+      _wallet_sync_compact_filtersPtr.asFunction<
+          bool Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, int,
+              ffi.Pointer<ffi.Char>, int)>();
+
+  bool wallet_set_sync_config(
+    ffi.Pointer<ffi.Char> wallet,
+    SyncConfig sync_config,
+  ) {
+    return _wallet_set_sync_config(
+      wallet,
+      sync_config,
+    );
+  }
+
+  late final _wallet_set_sync_configPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Bool Function(
+              ffi.Pointer<ffi.Char>, SyncConfig)>>('wallet_set_sync_config');
+  late final _wallet_set_sync_config = _wallet_set_sync_configPtr
+      .asFunction<bool Function(ffi.Pointer<ffi.Char>, SyncConfig)>();
+
+  SyncConfig wallet_get_sync_config(
+    ffi.Pointer<ffi.Char> wallet,
+  ) {
+    return _wallet_get_sync_config(
+      wallet,
+    );
+  }
+
+  late final _wallet_get_sync_configPtr =
+      _lookup<ffi.NativeFunction<SyncConfig Function(ffi.Pointer<ffi.Char>)>>(
+          'wallet_get_sync_config');
+  late final _wallet_get_sync_config = _wallet_get_sync_configPtr
+      .asFunction<SyncConfig Function(ffi.Pointer<ffi.Char>)>();
+
   int wallet_get_balance(
     ffi.Pointer<ffi.Char> wallet,
   ) {
@@ -263,11 +534,42 @@ class NativeLibrary {
       double Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, int,
           ffi.Pointer<UtxoList>, ffi.Pointer<UtxoList>)>();
 
+  int wallet_get_max_spendable(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<ffi.Char> send_to,
+    double fee_rate,
+    ffi.Pointer<UtxoList> must_spend,
+    ffi.Pointer<UtxoList> dont_spend,
+  ) {
+    return _wallet_get_max_spendable(
+      wallet,
+      send_to,
+      fee_rate,
+      must_spend,
+      dont_spend,
+    );
+  }
+
+  late final _wallet_get_max_spendablePtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Uint64 Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>,
+              ffi.Double,
+              ffi.Pointer<UtxoList>,
+              ffi.Pointer<UtxoList>)>>('wallet_get_max_spendable');
+  late final _wallet_get_max_spendable =
+      _wallet_get_max_spendablePtr.asFunction<
+          int Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, double,
+              ffi.Pointer<UtxoList>, ffi.Pointer<UtxoList>)>();
+
   Psbt wallet_create_psbt(
     ffi.Pointer<ffi.Char> wallet,
     ffi.Pointer<ffi.Char> send_to,
     int amount,
     double fee_rate,
+    bool send_max,
+    int coin_selection,
     ffi.Pointer<UtxoList> must_spend,
     ffi.Pointer<UtxoList> dont_spend,
   ) {
@@ -276,6 +578,8 @@ class NativeLibrary {
       send_to,
       amount,
       fee_rate,
+      send_max,
+      coin_selection,
       must_spend,
       dont_spend,
     );
@@ -288,12 +592,119 @@ class NativeLibrary {
               ffi.Pointer<ffi.Char>,
               ffi.Uint64,
               ffi.Double,
+              ffi.Bool,
+              ffi.Int32,
               ffi.Pointer<UtxoList>,
               ffi.Pointer<UtxoList>)>>('wallet_create_psbt');
   late final _wallet_create_psbt = _wallet_create_psbtPtr.asFunction<
       Psbt Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, int, double,
+          bool, int, ffi.Pointer<UtxoList>, ffi.Pointer<UtxoList>)>();
+
+  double wallet_get_max_feerate_batch(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<RecipientList> recipients,
+    ffi.Pointer<UtxoList> must_spend,
+    ffi.Pointer<UtxoList> dont_spend,
+  ) {
+    return _wallet_get_max_feerate_batch(
+      wallet,
+      recipients,
+      must_spend,
+      dont_spend,
+    );
+  }
+
+  late final _wallet_get_max_feerate_batchPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Double Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<RecipientList>,
+              ffi.Pointer<UtxoList>,
+              ffi.Pointer<UtxoList>)>>('wallet_get_max_feerate_batch');
+  late final _wallet_get_max_feerate_batch =
+      _wallet_get_max_feerate_batchPtr.asFunction<
+          double Function(ffi.Pointer<ffi.Char>, ffi.Pointer<RecipientList>,
+              ffi.Pointer<UtxoList>, ffi.Pointer<UtxoList>)>();
+
+  Psbt wallet_create_batch_psbt(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<RecipientList> recipients,
+    double fee_rate,
+    int coin_selection,
+    ffi.Pointer<UtxoList> must_spend,
+    ffi.Pointer<UtxoList> dont_spend,
+  ) {
+    return _wallet_create_batch_psbt(
+      wallet,
+      recipients,
+      fee_rate,
+      coin_selection,
+      must_spend,
+      dont_spend,
+    );
+  }
+
+  late final _wallet_create_batch_psbtPtr = _lookup<
+      ffi.NativeFunction<
+          Psbt Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<RecipientList>,
+              ffi.Double,
+              ffi.Int32,
+              ffi.Pointer<UtxoList>,
+              ffi.Pointer<UtxoList>)>>('wallet_create_batch_psbt');
+  late final _wallet_create_batch_psbt =
+      _wallet_create_batch_psbtPtr.asFunction<
+          Psbt Function(ffi.Pointer<ffi.Char>, ffi.Pointer<RecipientList>,
+              double, int, ffi.Pointer<UtxoList>, ffi.Pointer<UtxoList>)>();
+
+  Psbt wallet_bump_fee(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<ffi.Char> txid,
+    double fee_rate,
+    ffi.Pointer<UtxoList> must_spend,
+    ffi.Pointer<UtxoList> dont_spend,
+  ) {
+    return _wallet_bump_fee(
+      wallet,
+      txid,
+      fee_rate,
+      must_spend,
+      dont_spend,
+    );
+  }
+
+  late final _wallet_bump_feePtr = _lookup<
+      ffi.NativeFunction<
+          Psbt Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>,
+              ffi.Double,
+              ffi.Pointer<UtxoList>,
+              ffi.Pointer<UtxoList>)>>('wallet_bump_fee');
+  late final _wallet_bump_fee = _wallet_bump_feePtr.asFunction<
+      Psbt Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, double,
           ffi.Pointer<UtxoList>, ffi.Pointer<UtxoList>)>();
 
+  Psbt wallet_create_cpfp_psbt(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<ffi.Char> txid,
+    double fee_rate,
+  ) {
+    return _wallet_create_cpfp_psbt(
+      wallet,
+      txid,
+      fee_rate,
+    );
+  }
+
+  late final _wallet_create_cpfp_psbtPtr = _lookup<
+      ffi.NativeFunction<
+          Psbt Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>,
+              ffi.Double)>>('wallet_create_cpfp_psbt');
+  late final _wallet_create_cpfp_psbt = _wallet_create_cpfp_psbtPtr.asFunction<
+      Psbt Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, double)>();
+
   Psbt wallet_decode_psbt(
     ffi.Pointer<ffi.Char> wallet,
     ffi.Pointer<ffi.Char> psbt,
@@ -404,48 +815,165 @@ class NativeLibrary {
   late final _wallet_sign_psbt = _wallet_sign_psbtPtr.asFunction<
       Psbt Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>)>();
 
-  Seed wallet_generate_seed(
-    int network,
+  Psbt wallet_combine_psbts(
+    ffi.Pointer<ffi.Char> wallet,
+    int psbts_len,
+    ffi.Pointer<ffi.Pointer<ffi.Char>> psbts,
   ) {
-    return _wallet_generate_seed(
-      network,
+    return _wallet_combine_psbts(
+      wallet,
+      psbts_len,
+      psbts,
     );
   }
 
-  late final _wallet_generate_seedPtr =
-      _lookup<ffi.NativeFunction<Seed Function(ffi.Int32)>>(
-          'wallet_generate_seed');
-  late final _wallet_generate_seed =
-      _wallet_generate_seedPtr.asFunction<Seed Function(int)>();
+  late final _wallet_combine_psbtsPtr = _lookup<
+      ffi.NativeFunction<
+          Psbt Function(ffi.Pointer<ffi.Char>, ffi.Uint8,
+              ffi.Pointer<ffi.Pointer<ffi.Char>>)>>('wallet_combine_psbts');
+  late final _wallet_combine_psbts = _wallet_combine_psbtsPtr.asFunction<
+      Psbt Function(
+          ffi.Pointer<ffi.Char>, int, ffi.Pointer<ffi.Pointer<ffi.Char>>)>();
 
-  bool wallet_validate_seed(
-    ffi.Pointer<ffi.Char> seed_words,
+  bool wallet_set_label(
+    ffi.Pointer<ffi.Char> wallet,
+    int label_type,
+    ffi.Pointer<ffi.Char> reference,
+    ffi.Pointer<ffi.Char> label,
   ) {
-    return _wallet_validate_seed(
-      seed_words,
+    return _wallet_set_label(
+      wallet,
+      label_type,
+      reference,
+      label,
     );
   }
 
-  late final _wallet_validate_seedPtr =
-      _lookup<ffi.NativeFunction<ffi.Bool Function(ffi.Pointer<ffi.Char>)>>(
-          'wallet_validate_seed');
-  late final _wallet_validate_seed = _wallet_validate_seedPtr
-      .asFunction<bool Function(ffi.Pointer<ffi.Char>)>();
+  late final _wallet_set_labelPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Bool Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Int32,
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>)>>('wallet_set_label');
+  late final _wallet_set_label = _wallet_set_labelPtr.asFunction<
+      bool Function(ffi.Pointer<ffi.Char>, int, ffi.Pointer<ffi.Char>,
+          ffi.Pointer<ffi.Char>)>();
 
-  ffi.Pointer<ffi.Char> wallet_get_xpub_desc_key(
-    ffi.Pointer<ffi.Char> xprv,
-    ffi.Pointer<ffi.Char> path,
+  ffi.Pointer<ffi.Char> wallet_get_label(
+    ffi.Pointer<ffi.Char> wallet,
+    int label_type,
+    ffi.Pointer<ffi.Char> reference,
   ) {
-    return _wallet_get_xpub_desc_key(
-      xprv,
-      path,
+    return _wallet_get_label(
+      wallet,
+      label_type,
+      reference,
     );
   }
 
-  late final _wallet_get_xpub_desc_keyPtr = _lookup<
+  late final _wallet_get_labelPtr = _lookup<
       ffi.NativeFunction<
-          ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>,
-              ffi.Pointer<ffi.Char>)>>('wallet_get_xpub_desc_key');
+          ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>, ffi.Int32,
+              ffi.Pointer<ffi.Char>)>>('wallet_get_label');
+  late final _wallet_get_label = _wallet_get_labelPtr.asFunction<
+      ffi.Pointer<ffi.Char> Function(
+          ffi.Pointer<ffi.Char>, int, ffi.Pointer<ffi.Char>)>();
+
+  int wallet_import_labels(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<ffi.Char> jsonl,
+  ) {
+    return _wallet_import_labels(
+      wallet,
+      jsonl,
+    );
+  }
+
+  late final _wallet_import_labelsPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Int32 Function(ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>)>>('wallet_import_labels');
+  late final _wallet_import_labels = _wallet_import_labelsPtr
+      .asFunction<int Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>)>();
+
+  ffi.Pointer<ffi.Char> wallet_export_labels(
+    ffi.Pointer<ffi.Char> wallet,
+  ) {
+    return _wallet_export_labels(
+      wallet,
+    );
+  }
+
+  late final _wallet_export_labelsPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(
+              ffi.Pointer<ffi.Char>)>>('wallet_export_labels');
+  late final _wallet_export_labels = _wallet_export_labelsPtr
+      .asFunction<ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>)>();
+
+  ffi.Pointer<ffi.Char> wallet_export_config(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<ffi.Char> label,
+    int format,
+  ) {
+    return _wallet_export_config(
+      wallet,
+      label,
+      format,
+    );
+  }
+
+  late final _wallet_export_configPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>, ffi.Int32)>>('wallet_export_config');
+  late final _wallet_export_config = _wallet_export_configPtr.asFunction<
+      ffi.Pointer<ffi.Char> Function(
+          ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, int)>();
+
+  Seed wallet_generate_seed(
+    int network,
+  ) {
+    return _wallet_generate_seed(
+      network,
+    );
+  }
+
+  late final _wallet_generate_seedPtr =
+      _lookup<ffi.NativeFunction<Seed Function(ffi.Int32)>>(
+          'wallet_generate_seed');
+  late final _wallet_generate_seed =
+      _wallet_generate_seedPtr.asFunction<Seed Function(int)>();
+
+  bool wallet_validate_seed(
+    ffi.Pointer<ffi.Char> seed_words,
+  ) {
+    return _wallet_validate_seed(
+      seed_words,
+    );
+  }
+
+  late final _wallet_validate_seedPtr =
+      _lookup<ffi.NativeFunction<ffi.Bool Function(ffi.Pointer<ffi.Char>)>>(
+          'wallet_validate_seed');
+  late final _wallet_validate_seed = _wallet_validate_seedPtr
+      .asFunction<bool Function(ffi.Pointer<ffi.Char>)>();
+
+  ffi.Pointer<ffi.Char> wallet_get_xpub_desc_key(
+    ffi.Pointer<ffi.Char> xprv,
+    ffi.Pointer<ffi.Char> path,
+  ) {
+    return _wallet_get_xpub_desc_key(
+      xprv,
+      path,
+    );
+  }
+
+  late final _wallet_get_xpub_desc_keyPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>)>>('wallet_get_xpub_desc_key');
   late final _wallet_get_xpub_desc_key =
       _wallet_get_xpub_desc_keyPtr.asFunction<
           ffi.Pointer<ffi.Char> Function(
@@ -490,6 +1018,524 @@ class NativeLibrary {
   late final _wallet_helloPtr =
       _lookup<ffi.NativeFunction<ffi.Void Function()>>('wallet_hello');
   late final _wallet_hello = _wallet_helloPtr.asFunction<void Function()>();
+
+  ffi.Pointer<ffi.Char> wallet_sign_message(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<ffi.Char> address,
+    ffi.Pointer<ffi.Char> message,
+    int format,
+  ) {
+    return _wallet_sign_message(
+      wallet,
+      address,
+      message,
+      format,
+    );
+  }
+
+  late final _wallet_sign_messagePtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>,
+              ffi.Int32)>>('wallet_sign_message');
+  late final _wallet_sign_message = _wallet_sign_messagePtr.asFunction<
+      ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>,
+          ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, int)>();
+
+  bool wallet_verify_message(
+    ffi.Pointer<ffi.Char> address,
+    ffi.Pointer<ffi.Char> message,
+    ffi.Pointer<ffi.Char> signature,
+  ) {
+    return _wallet_verify_message(
+      address,
+      message,
+      signature,
+    );
+  }
+
+  late final _wallet_verify_messagePtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Bool Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>)>>('wallet_verify_message');
+  late final _wallet_verify_message = _wallet_verify_messagePtr.asFunction<
+      bool Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>,
+          ffi.Pointer<ffi.Char>)>();
+
+  ffi.Pointer<ffi.Char> wallet_get_bip322_psbt(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<ffi.Char> address,
+    ffi.Pointer<ffi.Char> message,
+  ) {
+    return _wallet_get_bip322_psbt(
+      wallet,
+      address,
+      message,
+    );
+  }
+
+  late final _wallet_get_bip322_psbtPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>)>>('wallet_get_bip322_psbt');
+  late final _wallet_get_bip322_psbt = _wallet_get_bip322_psbtPtr.asFunction<
+      ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>,
+          ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>)>();
+
+  ffi.Pointer<ffi.Char> wallet_get_bip322_signature(
+    ffi.Pointer<ffi.Char> psbt,
+    int format,
+  ) {
+    return _wallet_get_bip322_signature(
+      psbt,
+      format,
+    );
+  }
+
+  late final _wallet_get_bip322_signaturePtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>,
+              ffi.Int32)>>('wallet_get_bip322_signature');
+  late final _wallet_get_bip322_signature = _wallet_get_bip322_signaturePtr
+      .asFunction<ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>, int)>();
+
+  bool wallet_cancel(
+    ffi.Pointer<Task> task,
+  ) {
+    return _wallet_cancel(
+      task,
+    );
+  }
+
+  late final _wallet_cancelPtr =
+      _lookup<ffi.NativeFunction<ffi.Bool Function(ffi.Pointer<Task>)>>(
+          'wallet_cancel');
+  late final _wallet_cancel =
+      _wallet_cancelPtr.asFunction<bool Function(ffi.Pointer<Task>)>();
+
+  void wallet_task_free(
+    ffi.Pointer<Task> task,
+  ) {
+    return _wallet_task_free(
+      task,
+    );
+  }
+
+  late final _wallet_task_freePtr =
+      _lookup<ffi.NativeFunction<ffi.Void Function(ffi.Pointer<Task>)>>(
+          'wallet_task_free');
+  late final _wallet_task_free =
+      _wallet_task_freePtr.asFunction<void Function(ffi.Pointer<Task>)>();
+
+  ffi.Pointer<Task> wallet_sync_async(
+    ffi.Pointer<ffi.Char> wallet,
+    ffi.Pointer<ffi.Char> electrum_address,
+    int tor_port,
+    int isolate_port,
+  ) {
+    return _wallet_sync_async(
+      wallet,
+      electrum_address,
+      tor_port,
+      isolate_port,
+    );
+  }
+
+  late final _wallet_sync_asyncPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<Task> Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>,
+              ffi.Int32,
+              ffi.Int64)>>('wallet_sync_async');
+  late final _wallet_sync_async = _wallet_sync_asyncPtr.asFunction<
+      ffi.Pointer<Task> Function(
+          ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>, int, int)>();
+
+  ffi.Pointer<Task> wallet_get_fee_rate_async(
+    ffi.Pointer<ffi.Char> electrum_address,
+    int tor_port,
+    int target,
+    int isolate_port,
+  ) {
+    return _wallet_get_fee_rate_async(
+      electrum_address,
+      tor_port,
+      target,
+      isolate_port,
+    );
+  }
+
+  late final _wallet_get_fee_rate_asyncPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<Task> Function(ffi.Pointer<ffi.Char>, ffi.Int32,
+              ffi.Uint16, ffi.Int64)>>('wallet_get_fee_rate_async');
+  late final _wallet_get_fee_rate_async =
+      _wallet_get_fee_rate_asyncPtr.asFunction<
+          ffi.Pointer<Task> Function(ffi.Pointer<ffi.Char>, int, int, int)>();
+
+  ffi.Pointer<Task> wallet_get_server_features_async(
+    ffi.Pointer<ffi.Char> electrum_address,
+    int tor_port,
+    int isolate_port,
+  ) {
+    return _wallet_get_server_features_async(
+      electrum_address,
+      tor_port,
+      isolate_port,
+    );
+  }
+
+  late final _wallet_get_server_features_asyncPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<Task> Function(ffi.Pointer<ffi.Char>, ffi.Int32,
+              ffi.Int64)>>('wallet_get_server_features_async');
+  late final _wallet_get_server_features_async =
+      _wallet_get_server_features_asyncPtr.asFunction<
+          ffi.Pointer<Task> Function(ffi.Pointer<ffi.Char>, int, int)>();
+
+  ffi.Pointer<Task> wallet_broadcast_tx_async(
+    ffi.Pointer<ffi.Char> electrum_address,
+    int tor_port,
+    ffi.Pointer<ffi.Char> tx,
+    int isolate_port,
+  ) {
+    return _wallet_broadcast_tx_async(
+      electrum_address,
+      tor_port,
+      tx,
+      isolate_port,
+    );
+  }
+
+  late final _wallet_broadcast_tx_asyncPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<Task> Function(ffi.Pointer<ffi.Char>, ffi.Int32,
+              ffi.Pointer<ffi.Char>, ffi.Int64)>>('wallet_broadcast_tx_async');
+  late final _wallet_broadcast_tx_async =
+      _wallet_broadcast_tx_asyncPtr.asFunction<
+          ffi.Pointer<Task> Function(
+              ffi.Pointer<ffi.Char>, int, ffi.Pointer<ffi.Char>, int)>();
+
+  bool wallet_sync_with_backend(
+    ffi.Pointer<ffi.Char> wallet,
+    int backend,
+    ffi.Pointer<ffi.Char> address,
+    int tor_port,
+  ) {
+    return _wallet_sync_with_backend(
+      wallet,
+      backend,
+      address,
+      tor_port,
+    );
+  }
+
+  late final _wallet_sync_with_backendPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Bool Function(ffi.Pointer<ffi.Char>, ffi.Int32,
+              ffi.Pointer<ffi.Char>, ffi.Int32)>>('wallet_sync_with_backend');
+  late final _wallet_sync_with_backend =
+      _wallet_sync_with_backendPtr.asFunction<
+          bool Function(
+              ffi.Pointer<ffi.Char>, int, ffi.Pointer<ffi.Char>, int)>();
+
+  double wallet_get_fee_rate_with_backend(
+    int backend,
+    ffi.Pointer<ffi.Char> address,
+    int tor_port,
+    int target,
+  ) {
+    return _wallet_get_fee_rate_with_backend(
+      backend,
+      address,
+      tor_port,
+      target,
+    );
+  }
+
+  late final _wallet_get_fee_rate_with_backendPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Double Function(ffi.Int32, ffi.Pointer<ffi.Char>, ffi.Int32,
+              ffi.Uint16)>>('wallet_get_fee_rate_with_backend');
+  late final _wallet_get_fee_rate_with_backend =
+      _wallet_get_fee_rate_with_backendPtr
+          .asFunction<double Function(int, ffi.Pointer<ffi.Char>, int, int)>();
+
+  ffi.Pointer<ffi.Char> wallet_get_tx_with_backend(
+    int backend,
+    ffi.Pointer<ffi.Char> address,
+    int tor_port,
+    ffi.Pointer<ffi.Char> txid,
+  ) {
+    return _wallet_get_tx_with_backend(
+      backend,
+      address,
+      tor_port,
+      txid,
+    );
+  }
+
+  late final _wallet_get_tx_with_backendPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(ffi.Int32, ffi.Pointer<ffi.Char>,
+              ffi.Int32, ffi.Pointer<ffi.Char>)>>('wallet_get_tx_with_backend');
+  late final _wallet_get_tx_with_backend =
+      _wallet_get_tx_with_backendPtr.asFunction<
+          ffi.Pointer<ffi.Char> Function(
+              int, ffi.Pointer<ffi.Char>, int, ffi.Pointer<ffi.Char>)>();
+
+  ffi.Pointer<ffi.Char> wallet_broadcast_tx_with_backend(
+    int backend,
+    ffi.Pointer<ffi.Char> address,
+    int tor_port,
+    ffi.Pointer<ffi.Char> tx,
+  ) {
+    return _wallet_broadcast_tx_with_backend(
+      backend,
+      address,
+      tor_port,
+      tx,
+    );
+  }
+
+  late final _wallet_broadcast_tx_with_backendPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(
+              ffi.Int32,
+              ffi.Pointer<ffi.Char>,
+              ffi.Int32,
+              ffi.Pointer<ffi.Char>)>>('wallet_broadcast_tx_with_backend');
+  late final _wallet_broadcast_tx_with_backend =
+      _wallet_broadcast_tx_with_backendPtr.asFunction<
+          ffi.Pointer<ffi.Char> Function(
+              int, ffi.Pointer<ffi.Char>, int, ffi.Pointer<ffi.Char>)>();
+
+  ffi.Pointer<ffi.Char> wallet_sync_failover(
+    ffi.Pointer<ffi.Char> wallet,
+    int servers_len,
+    ffi.Pointer<ffi.Pointer<ffi.Char>> servers,
+    int tor_port,
+  ) {
+    return _wallet_sync_failover(
+      wallet,
+      servers_len,
+      servers,
+      tor_port,
+    );
+  }
+
+  late final _wallet_sync_failoverPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(
+              ffi.Pointer<ffi.Char>,
+              ffi.Uint8,
+              ffi.Pointer<ffi.Pointer<ffi.Char>>,
+              ffi.Int32)>>('wallet_sync_failover');
+  late final _wallet_sync_failover = _wallet_sync_failoverPtr.asFunction<
+      ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>, int,
+          ffi.Pointer<ffi.Pointer<ffi.Char>>, int)>();
+
+  ServedFeeRate wallet_get_fee_rate_failover(
+    int servers_len,
+    ffi.Pointer<ffi.Pointer<ffi.Char>> servers,
+    int tor_port,
+    int network,
+    int target,
+  ) {
+    return _wallet_get_fee_rate_failover(
+      servers_len,
+      servers,
+      tor_port,
+      network,
+      target,
+    );
+  }
+
+  late final _wallet_get_fee_rate_failoverPtr = _lookup<
+      ffi.NativeFunction<
+          ServedFeeRate Function(
+              ffi.Uint8,
+              ffi.Pointer<ffi.Pointer<ffi.Char>>,
+              ffi.Int32,
+              ffi.Int32,
+              ffi.Uint16)>>('wallet_get_fee_rate_failover');
+  late final _wallet_get_fee_rate_failover =
+      _wallet_get_fee_rate_failoverPtr.asFunction<
+          ServedFeeRate Function(
+              int, ffi.Pointer<ffi.Pointer<ffi.Char>>, int, int, int)>();
+
+  ServedTxid wallet_broadcast_tx_failover(
+    int servers_len,
+    ffi.Pointer<ffi.Pointer<ffi.Char>> servers,
+    int tor_port,
+    int network,
+    ffi.Pointer<ffi.Char> tx,
+  ) {
+    return _wallet_broadcast_tx_failover(
+      servers_len,
+      servers,
+      tor_port,
+      network,
+      tx,
+    );
+  }
+
+  late final _wallet_broadcast_tx_failoverPtr = _lookup<
+      ffi.NativeFunction<
+          ServedTxid Function(
+              ffi.Uint8,
+              ffi.Pointer<ffi.Pointer<ffi.Char>>,
+              ffi.Int32,
+              ffi.Int32,
+              ffi.Pointer<ffi.Char>)>>('wallet_broadcast_tx_failover');
+  late final _wallet_broadcast_tx_failover =
+      _wallet_broadcast_tx_failoverPtr.asFunction<
+          ServedTxid Function(int, ffi.Pointer<ffi.Pointer<ffi.Char>>, int, int,
+              ffi.Pointer<ffi.Char>)>();
+
+  ElectrumServerHealthList wallet_get_server_health() {
+    return _wallet_get_server_health();
+  }
+
+  late final _wallet_get_server_healthPtr =
+      _lookup<ffi.NativeFunction<ElectrumServerHealthList Function()>>(
+          'wallet_get_server_health');
+  late final _wallet_get_server_health = _wallet_get_server_healthPtr
+      .asFunction<ElectrumServerHealthList Function()>();
+
+  bool wallet_set_certificate_pinning(
+    ffi.Pointer<ffi.Char> data_dir,
+    int mode,
+  ) {
+    return _wallet_set_certificate_pinning(
+      data_dir,
+      mode,
+    );
+  }
+
+  late final _wallet_set_certificate_pinningPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Bool Function(ffi.Pointer<ffi.Char>,
+              ffi.Int32)>>('wallet_set_certificate_pinning');
+  late final _wallet_set_certificate_pinning =
+      _wallet_set_certificate_pinningPtr
+          .asFunction<bool Function(ffi.Pointer<ffi.Char>, int)>();
+
+  ffi.Pointer<ffi.Char> wallet_get_server_fingerprint(
+    ffi.Pointer<ffi.Char> electrum_address,
+    int tor_port,
+  ) {
+    return _wallet_get_server_fingerprint(
+      electrum_address,
+      tor_port,
+    );
+  }
+
+  late final _wallet_get_server_fingerprintPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>,
+              ffi.Int32)>>('wallet_get_server_fingerprint');
+  late final _wallet_get_server_fingerprint = _wallet_get_server_fingerprintPtr
+      .asFunction<ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>, int)>();
+
+  ffi.Pointer<ffi.Char> wallet_get_server_pin(
+    ffi.Pointer<ffi.Char> electrum_address,
+  ) {
+    return _wallet_get_server_pin(
+      electrum_address,
+    );
+  }
+
+  late final _wallet_get_server_pinPtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Pointer<ffi.Char> Function(
+              ffi.Pointer<ffi.Char>)>>('wallet_get_server_pin');
+  late final _wallet_get_server_pin = _wallet_get_server_pinPtr
+      .asFunction<ffi.Pointer<ffi.Char> Function(ffi.Pointer<ffi.Char>)>();
+
+  bool wallet_pin_server_certificate(
+    ffi.Pointer<ffi.Char> electrum_address,
+    ffi.Pointer<ffi.Char> fingerprint,
+  ) {
+    return _wallet_pin_server_certificate(
+      electrum_address,
+      fingerprint,
+    );
+  }
+
+  late final _wallet_pin_server_certificatePtr = _lookup<
+      ffi.NativeFunction<
+          ffi.Bool Function(ffi.Pointer<ffi.Char>,
+              ffi.Pointer<ffi.Char>)>>('wallet_pin_server_certificate');
+  late final _wallet_pin_server_certificate =
+      _wallet_pin_server_certificatePtr.asFunction<
+          bool Function(ffi.Pointer<ffi.Char>, ffi.Pointer<ffi.Char>)>();
+
+  bool wallet_forget_server_certificate(
+    ffi.Pointer<ffi.Char> electrum_address,
+  ) {
+    return _wallet_forget_server_certificate(
+      electrum_address,
+    );
+  }
+
+  late final _wallet_forget_server_certificatePtr =
+      _lookup<ffi.NativeFunction<ffi.Bool Function(ffi.Pointer<ffi.Char>)>>(
+          'wallet_forget_server_certificate');
+  late final _wallet_forget_server_certificate =
+      _wallet_forget_server_certificatePtr
+          .asFunction<bool Function(ffi.Pointer<ffi.Char>)>();
+}
+
+abstract class BackendType {
+  static const int Electrum = 0;
+  static const int Esplora = 1;
+  static const int Rpc = 2;
+}
+
+abstract class CoinSelection {
+  static const int BranchAndBound = 0;
+  static const int LargestFirst = 1;
+  static const int OldestFirst = 2;
+  static const int Privacy = 3;
+}
+
+abstract class ExportFormat {
+  static const int Descriptor = 0;
+  static const int Electrum = 1;
+  static const int Specter = 2;
+  static const int Bsms = 3;
+}
+
+abstract class KeyNetwork {
+  static const int Unknown = 0;
+  static const int Mainnet = 1;
+  static const int TestNetworks = 2;
+}
+
+abstract class Keychain {
+  static const int External = 0;
+  static const int Internal = 1;
+}
+
+abstract class LabelType {
+  static const int Tx = 0;
+  static const int Addr = 1;
+  static const int Pubkey = 2;
+  static const int Input = 3;
+  static const int Output = 4;
+  static const int Xpub = 5;
+}
+
+abstract class MessageFormat {
+  static const int Legacy = 0;
+  static const int Bip322Simple = 1;
+  static const int Bip322Full = 2;
 }
 
 abstract class NetworkType {
@@ -499,6 +1545,21 @@ abstract class NetworkType {
   static const int Regtest = 3;
 }
 
+abstract class PinningMode {
+  static const int Off = 0;
+  static const int TrustOnFirstUse = 1;
+  static const int Strict = 2;
+}
+
+abstract class ScriptType {
+  static const int P2wpkh = 0;
+  static const int P2tr = 1;
+  static const int P2pkh = 2;
+  static const int P2shP2wpkh = 3;
+}
+
+class Task extends ffi.Opaque {}
+
 class Wallet extends ffi.Struct {
   external ffi.Pointer<ffi.Char> name;
 
@@ -518,6 +1579,136 @@ class Wallet extends ffi.Struct {
 
 typedef uintptr_t = ffi.UnsignedLong;
 
+class ImportedAccount extends ffi.Struct {
+  external ffi.Pointer<ffi.Char> label;
+
+  external ffi.Pointer<ffi.Char> external_descriptor;
+
+  external ffi.Pointer<ffi.Char> internal_descriptor;
+
+  @ffi.Uint8()
+  external int key_origins_len;
+
+  external ffi.Pointer<ffi.Pointer<ffi.Char>> key_origins;
+}
+
+class ImportedAccountList extends ffi.Struct {
+  @ffi.Uint8()
+  external int accounts_len;
+
+  external ffi.Pointer<ImportedAccount> accounts;
+}
+
+class DescriptorInfo extends ffi.Struct {
+  external ffi.Pointer<ffi.Char> descriptor;
+
+  external ffi.Pointer<ffi.Char> public_descriptor;
+
+  external ffi.Pointer<ffi.Char> checksum;
+
+  @ffi.Int32()
+  external int network;
+
+  external ffi.Pointer<ffi.Char> script_type;
+
+  @ffi.Uint8()
+  external int key_origins_len;
+
+  external ffi.Pointer<ffi.Pointer<ffi.Char>> key_origins;
+
+  @ffi.Bool()
+  external bool ranged;
+
+  @ffi.Bool()
+  external bool private_;
+}
+
+class XpubDescriptors extends ffi.Struct {
+  external ffi.Pointer<ffi.Char> xpub;
+
+  @ffi.Int32()
+  external int script_type;
+
+  external ffi.Pointer<ffi.Char> derivation;
+
+  external ffi.Pointer<ffi.Char> external_descriptor;
+
+  external ffi.Pointer<ffi.Char> internal_descriptor;
+}
+
+class DiscoveredAccount extends ffi.Struct {
+  @ffi.Int32()
+  external int script_type;
+
+  @ffi.Uint32()
+  external int account;
+
+  external ffi.Pointer<ffi.Char> path;
+
+  @ffi.Uint32()
+  external int tx_count;
+}
+
+class DiscoveredAccountList extends ffi.Struct {
+  @ffi.Uint32()
+  external int accounts_len;
+
+  external ffi.Pointer<DiscoveredAccount> accounts;
+}
+
+class SyncConfig extends ffi.Struct {
+  @ffi.Uint32()
+  external int stop_gap;
+
+  @ffi.Uint8()
+  external int retry;
+
+  @ffi.Uint8()
+  external int timeout;
+
+  @ffi.Uint32()
+  external int batch_size;
+}
+
+class AddressInfo extends ffi.Struct {
+  external ffi.Pointer<ffi.Char> address;
+
+  @ffi.Int32()
+  external int keychain;
+
+  @ffi.Uint32()
+  external int index;
+
+  external ffi.Pointer<ffi.Char> path;
+
+  @ffi.Bool()
+  external bool used;
+
+  @ffi.Uint64()
+  external int received;
+
+  external ffi.Pointer<ffi.Char> label;
+}
+
+class AddressList extends ffi.Struct {
+  @ffi.Uint32()
+  external int addresses_len;
+
+  external ffi.Pointer<AddressInfo> addresses;
+}
+
+class AddressOwner extends ffi.Struct {
+  @ffi.Int32()
+  external int keychain;
+
+  @ffi.Uint32()
+  external int index;
+
+  external ffi.Pointer<ffi.Char> path;
+
+  external ffi.Pointer<ffi.Char> descriptor;
+}
+
 class Utxo extends ffi.Struct {
   external ffi.Pointer<ffi.Char> txid;
 
@@ -586,6 +1777,31 @@ class TransactionList extends ffi.Struct {
   external ffi.Pointer<Transaction> transactions;
 }
 
+class PsbtOutput extends ffi.Struct {
+  external ffi.Pointer<ffi.Char> address;
+
+  @ffi.Uint64()
+  external int amount;
+
+  @ffi.Bool()
+  external bool is_mine;
+
+  @ffi.Bool()
+  external bool is_change;
+}
+
+class PsbtInput extends ffi.Struct {
+  external ffi.Pointer<ffi.Char> txid;
+
+  @ffi.Uint32()
+  external int vout;
+
+  @ffi.Uint64()
+  external int amount;
+
+  external ffi.Pointer<ffi.Char> reason;
+}
+
 class Psbt extends ffi.Struct {
   @ffi.Uint64()
   external int sent;
@@ -601,6 +1817,41 @@ class Psbt extends ffi.Struct {
   external ffi.Pointer<ffi.Char> txid;
 
   external ffi.Pointer<ffi.Char> raw_tx;
+
+  @ffi.Uint32()
+  external int outputs_len;
+
+  external ffi.Pointer<PsbtOutput> outputs;
+
+  external ffi.Pointer<ffi.Char> coin_selection;
+
+  @ffi.Uint32()
+  external int inputs_len;
+
+  external ffi.Pointer<PsbtInput> inputs;
+
+  @ffi.Bool()
+  external bool finalized;
+
+  @ffi.Bool()
+  external bool inputs_added;
+}
+
+class Recipient extends ffi.Struct {
+  external ffi.Pointer<ffi.Char> address;
+
+  @ffi.Uint64()
+  external int amount;
+
+  @ffi.Bool()
+  external bool send_max;
+}
+
+class RecipientList extends ffi.Struct {
+  @ffi.Uint32()
+  external int recipients_len;
+
+  external ffi.Pointer<Recipient> recipients;
 }
 
 class RawTransactionOutput extends ffi.Struct {
@@ -640,6 +1891,44 @@ class Seed extends ffi.Struct {
   external ffi.Pointer<ffi.Char> fingerprint;
 }
 
+class ServedFeeRate extends ffi.Struct {
+  @ffi.Double()
+  external double fee_rate;
+
+  external ffi.Pointer<ffi.Char> server;
+}
+
+class ServedTxid extends ffi.Struct {
+  external ffi.Pointer<ffi.Char> txid;
+
+  external ffi.Pointer<ffi.Char> server;
+}
+
+class ElectrumServerHealth extends ffi.Struct {
+  external ffi.Pointer<ffi.Char> server;
+
+  @ffi.Uint32()
+  external int successes;
+
+  @ffi.Uint32()
+  external int errors;
+
+  @ffi.Uint32()
+  external int consecutive_errors;
+
+  @ffi.Uint32()
+  external int latency_ms;
+
+  external ffi.Pointer<ffi.Char> last_error;
+}
+
+class ElectrumServerHealthList extends ffi.Struct {
+  @ffi.Uint32()
+  external int servers_len;
+
+  external ffi.Pointer<ElectrumServerHealth> servers;
+}
+
 const int INT8_MIN = -128;
 
 const int INT16_MIN = -32768;
@@ -0,0 +1,231 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::descriptor::with_checksum;
+use crate::labels;
+use bdk::bitcoin::util::bip32::{ChildNumber, DerivationPath};
+use bdk::bitcoin::{Address, Script};
+use bdk::database::Database;
+use bdk::miniscript::descriptor::DescriptorPublicKey;
+use bdk::miniscript::ForEachKey;
+use bdk::wallet::AddressIndex;
+use bdk::KeychainKind;
+use sled::Tree;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+pub struct AddressEntry {
+    pub address: String,
+    pub keychain: KeychainKind,
+    pub index: u32,
+    pub path: String,
+    pub used: bool,
+    pub received: u64,
+    pub label: Option<String>,
+}
+
+pub struct AddressOwner {
+    pub keychain: KeychainKind,
+    pub index: u32,
+    pub path: String,
+    pub descriptor: String,
+}
+
+// Path of the first extended key down to the given index, e.g. m/84'/0'/0'/0/5
+fn get_address_path(wallet: &bdk::Wallet<Tree>, keychain: KeychainKind, index: u32) -> String {
+    let mut path = None;
+
+    wallet
+        .get_descriptor_for_keychain(keychain)
+        .for_each_key(|key| {
+            if let DescriptorPublicKey::XPub(xkey) = key {
+                let origin_path = match &xkey.origin {
+                    Some((_, origin_path)) => origin_path.clone(),
+                    None => DerivationPath::master(),
+                };
+
+                path = Some(
+                    origin_path
+                        .extend(&xkey.derivation_path)
+                        .child(ChildNumber::Normal { index }),
+                );
+            }
+
+            // Stop at the first extended key
+            path.is_none()
+        });
+
+    match path {
+        Some(p) => p.to_string(),
+        None => "".to_string(),
+    }
+}
+
+// Total paid to each of our scripts over the wallet's history
+fn get_received_by_script(wallet: &bdk::Wallet<Tree>) -> Result<HashMap<Script, u64>, bdk::Error> {
+    let mut received = HashMap::new();
+
+    for details in wallet.list_transactions(true)? {
+        let tx = match details.transaction {
+            Some(tx) => tx,
+            None => continue,
+        };
+
+        for output in tx.output {
+            if wallet.is_mine(&output.script_pubkey)? {
+                *received.entry(output.script_pubkey).or_insert(0) += output.value;
+            }
+        }
+    }
+
+    Ok(received)
+}
+
+// Page through the addresses handed out so far on a keychain, oldest first
+pub fn list_addresses(
+    wallet: &bdk::Wallet<Tree>,
+    keychain: KeychainKind,
+    offset: u32,
+    limit: u32,
+) -> Result<Vec<AddressEntry>, bdk::Error> {
+    let last_index = match wallet.database().get_last_index(keychain)? {
+        Some(i) => i,
+        None => return Ok(vec![]),
+    };
+
+    let received = get_received_by_script(wallet)?;
+    let end = last_index
+        .saturating_add(1)
+        .min(offset.saturating_add(limit));
+
+    let mut entries = vec![];
+    for index in offset..end {
+        let address_info = get_address(wallet, keychain, index)?;
+
+        let address = address_info.address.to_string();
+        let received = received.get(&address_info.script_pubkey()).cloned();
+        let label = labels::get_label(&wallet.database(), "addr", &address)?.and_then(|l| l.label);
+
+        entries.push(AddressEntry {
+            address,
+            keychain,
+            index,
+            path: get_address_path(wallet, keychain, index),
+            used: received.is_some(),
+            received: received.unwrap_or(0),
+            label,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn get_address(
+    wallet: &bdk::Wallet<Tree>,
+    keychain: KeychainKind,
+    index: u32,
+) -> Result<bdk::wallet::AddressInfo, bdk::Error> {
+    match keychain {
+        KeychainKind::External => wallet.get_address(AddressIndex::Peek(index)),
+        KeychainKind::Internal => wallet.get_internal_address(AddressIndex::Peek(index)),
+    }
+}
+
+// Accepts an address or a hex encoded scriptPubKey
+fn parse_script(address_or_script: &str) -> Result<Script, bdk::Error> {
+    let address_or_script = address_or_script.trim();
+
+    if let Ok(address) = Address::from_str(address_or_script) {
+        return Ok(address.script_pubkey());
+    }
+
+    match hex::decode(address_or_script) {
+        Ok(bytes) => Ok(Script::from(bytes)),
+        Err(_) => Err(bdk::Error::Generic("Not an address or script".to_string())),
+    }
+}
+
+// Find where a script was derived, looking past the revealed addresses up to search_limit
+pub fn find_address(
+    wallet: &bdk::Wallet<Tree>,
+    address_or_script: &str,
+    search_limit: u32,
+) -> Result<AddressOwner, bdk::Error> {
+    let script = parse_script(address_or_script)?;
+
+    let mut found = wallet.database().get_path_from_script_pubkey(&script)?;
+
+    if found.is_none() {
+        'search: for index in 0..search_limit {
+            for keychain in [KeychainKind::External, KeychainKind::Internal] {
+                if get_address(wallet, keychain, index)?.script_pubkey() == script {
+                    found = Some((keychain, index));
+                    break 'search;
+                }
+            }
+        }
+    }
+
+    let (keychain, index) = found
+        .ok_or_else(|| bdk::Error::Generic("Address doesn't belong to this wallet".to_string()))?;
+
+    let (descriptor, _) = with_checksum(&wallet.get_descriptor_for_keychain(keychain).to_string())?;
+
+    Ok(AddressOwner {
+        keychain,
+        index,
+        path: get_address_path(wallet, keychain, index),
+        descriptor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+    use bdk::bitcoin::Network;
+
+    #[test]
+    fn test_list_addresses() {
+        let wallet = util::get_test_wallet(Network::Testnet);
+
+        assert!(list_addresses(&wallet, KeychainKind::External, 0, 10)
+            .unwrap()
+            .is_empty());
+
+        for _ in 0..5 {
+            wallet.get_address(AddressIndex::New).unwrap();
+        }
+
+        let page = list_addresses(&wallet, KeychainKind::External, 3, 10).unwrap();
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].index, 3);
+        assert_eq!(page[0].path, "m/84'/1'/0'/0/3");
+        assert!(!page[0].used);
+        assert_eq!(page[0].received, 0);
+    }
+
+    #[test]
+    fn test_find_address() {
+        let wallet = util::get_test_wallet(Network::Testnet);
+
+        let change = wallet
+            .get_internal_address(AddressIndex::Peek(7))
+            .unwrap()
+            .address;
+
+        let owner = find_address(&wallet, &change.to_string(), 10).unwrap();
+        assert_eq!(owner.keychain, KeychainKind::Internal);
+        assert_eq!(owner.index, 7);
+        assert_eq!(owner.path, "m/84'/1'/0'/1/7");
+        assert!(owner.descriptor.starts_with("wpkh("));
+
+        // Same script given as hex
+        let script = hex::encode(change.script_pubkey().as_bytes());
+        assert_eq!(find_address(&wallet, &script, 10).unwrap().index, 7);
+
+        let far = wallet.get_address(AddressIndex::Peek(150)).unwrap().address;
+        assert!(find_address(&wallet, &far.to_string(), 10).is_err());
+    }
+}
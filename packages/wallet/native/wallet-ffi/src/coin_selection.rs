@@ -0,0 +1,363 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::{labels, CoinSelection};
+use bdk::bitcoin::{OutPoint, Script};
+use bdk::database::Database;
+use bdk::wallet::coin_selection::{
+    BranchAndBoundCoinSelection, CoinSelectionAlgorithm, CoinSelectionResult, Excess,
+    LargestFirstCoinSelection, OldestFirstCoinSelection,
+};
+use bdk::{FeeRate, WeightedUtxo};
+use sled::Tree;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::str::FromStr;
+
+const REQUIRED: &str = "required by coin control or send max";
+
+// Filled in by the strategy once BDK has run it
+#[derive(Debug, Default, Clone)]
+pub struct SelectionReport {
+    pub strategy: String,
+    pub reasons: HashMap<OutPoint, String>,
+}
+
+#[derive(Debug)]
+pub struct Strategy {
+    coin_selection: CoinSelection,
+    // Output labels double as coin tags for the privacy mode
+    tags: HashMap<OutPoint, String>,
+    report: Rc<RefCell<SelectionReport>>,
+}
+
+fn get_tags(tree: &Tree) -> Result<HashMap<OutPoint, String>, bdk::Error> {
+    let mut tags = HashMap::new();
+
+    for label in labels::list_labels(tree)? {
+        if label.label_type != "output" {
+            continue;
+        }
+
+        if let (Ok(outpoint), Some(tag)) = (OutPoint::from_str(&label.reference), label.label) {
+            tags.insert(outpoint, tag);
+        }
+    }
+
+    Ok(tags)
+}
+
+fn get_strategy_name(coin_selection: CoinSelection) -> &'static str {
+    match coin_selection {
+        CoinSelection::BranchAndBound => "branch_and_bound",
+        CoinSelection::LargestFirst => "largest_first",
+        CoinSelection::OldestFirst => "oldest_first",
+        CoinSelection::Privacy => "privacy",
+    }
+}
+
+impl Strategy {
+    // The report stays readable after the strategy is moved into the builder
+    pub fn new(
+        coin_selection: CoinSelection,
+        tree: &Tree,
+    ) -> Result<(Self, Rc<RefCell<SelectionReport>>), bdk::Error> {
+        let tags = match coin_selection {
+            CoinSelection::Privacy => get_tags(tree)?,
+            _ => HashMap::new(),
+        };
+
+        let report = Rc::new(RefCell::new(SelectionReport::default()));
+
+        Ok((
+            Strategy {
+                coin_selection,
+                tags,
+                report: report.clone(),
+            },
+            report,
+        ))
+    }
+
+    // Spend from a single tag, the smallest group that covers the payment
+    fn select_by_tag<D: Database>(
+        &self,
+        database: &D,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: u64,
+        drain_script: &Script,
+    ) -> Result<(CoinSelectionResult, String), bdk::Error> {
+        let get_tag = |utxo: &WeightedUtxo| self.tags.get(&utxo.utxo.outpoint()).cloned();
+
+        let mut required_tags: Vec<Option<String>> = required_utxos.iter().map(get_tag).collect();
+        required_tags.sort();
+        required_tags.dedup();
+
+        if required_tags.len() > 1 {
+            return Err(bdk::Error::Generic(
+                "Coins to spend carry different tags".to_string(),
+            ));
+        }
+
+        let mut groups: BTreeMap<Option<String>, Vec<WeightedUtxo>> = BTreeMap::new();
+        for utxo in optional_utxos {
+            groups.entry(get_tag(&utxo)).or_default().push(utxo);
+        }
+
+        match required_tags.first() {
+            Some(tag) => {
+                groups.retain(|t, _| t == tag);
+                groups.entry(tag.clone()).or_default();
+            }
+            // Still run once so BDK reports the shortfall
+            None if groups.is_empty() => {
+                groups.insert(None, vec![]);
+            }
+            None => {}
+        }
+
+        // Keep bigger groups together for later payments
+        let mut groups: Vec<_> = groups.into_iter().collect();
+        groups.sort_by_key(|(_, group)| {
+            group
+                .iter()
+                .map(|utxo| utxo.utxo.txout().value)
+                .sum::<u64>()
+        });
+
+        let mut last_error = None;
+        for (tag, group) in groups {
+            match BranchAndBoundCoinSelection::default().coin_select(
+                database,
+                required_utxos.clone(),
+                group,
+                fee_rate,
+                target_amount,
+                drain_script,
+            ) {
+                Ok(result) => {
+                    let reason = match tag {
+                        Some(tag) => format!("only coins tagged {tag}"),
+                        None => "only untagged coins".to_string(),
+                    };
+                    return Ok((result, reason));
+                }
+                Err(e @ bdk::Error::InsufficientFunds { .. }) => last_error = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or(bdk::Error::NoUtxosSelected))
+    }
+}
+
+impl<D: Database> CoinSelectionAlgorithm<D> for Strategy {
+    fn coin_select(
+        &self,
+        database: &D,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: u64,
+        drain_script: &Script,
+    ) -> Result<CoinSelectionResult, bdk::Error> {
+        let required: Vec<OutPoint> = required_utxos
+            .iter()
+            .map(|utxo| utxo.utxo.outpoint())
+            .collect();
+
+        let (result, reason) = match self.coin_selection {
+            CoinSelection::BranchAndBound => {
+                let result = BranchAndBoundCoinSelection::default().coin_select(
+                    database,
+                    required_utxos,
+                    optional_utxos,
+                    fee_rate,
+                    target_amount,
+                    drain_script,
+                )?;
+
+                // BDK only falls back to a random draw when no changeless match exists
+                let reason = match result.excess {
+                    Excess::NoChange { .. } => "no change output needed",
+                    Excess::Change { .. } => "random draw, no changeless match",
+                };
+                (result, reason.to_string())
+            }
+            CoinSelection::LargestFirst => (
+                LargestFirstCoinSelection.coin_select(
+                    database,
+                    required_utxos,
+                    optional_utxos,
+                    fee_rate,
+                    target_amount,
+                    drain_script,
+                )?,
+                "largest coins first".to_string(),
+            ),
+            CoinSelection::OldestFirst => (
+                OldestFirstCoinSelection.coin_select(
+                    database,
+                    required_utxos,
+                    optional_utxos,
+                    fee_rate,
+                    target_amount,
+                    drain_script,
+                )?,
+                "oldest coins first".to_string(),
+            ),
+            CoinSelection::Privacy => self.select_by_tag(
+                database,
+                required_utxos,
+                optional_utxos,
+                fee_rate,
+                target_amount,
+                drain_script,
+            )?,
+        };
+
+        let mut report = self.report.borrow_mut();
+        report.strategy = get_strategy_name(self.coin_selection).to_string();
+        report.reasons = result
+            .selected
+            .iter()
+            .map(|utxo| {
+                let outpoint = utxo.outpoint();
+                match required.contains(&outpoint) {
+                    true => (outpoint, REQUIRED.to_string()),
+                    false => (outpoint, reason.clone()),
+                }
+            })
+            .collect();
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::{TxOut, Txid};
+    use bdk::database::MemoryDatabase;
+    use bdk::{KeychainKind, LocalUtxo, Utxo};
+
+    fn get_utxo(vout: u32, value: u64) -> WeightedUtxo {
+        WeightedUtxo {
+            // P2WPKH
+            satisfaction_weight: 107,
+            utxo: Utxo::Local(LocalUtxo {
+                outpoint: OutPoint::new(
+                    Txid::from_str(
+                        "ebd9813ecebc57ff8f30797de7c205e3c7498ca950ea4341ee51a685ff2fa30a",
+                    )
+                    .unwrap(),
+                    vout,
+                ),
+                txout: TxOut {
+                    value,
+                    script_pubkey: Script::from(vec![0; 22]),
+                },
+                keychain: KeychainKind::External,
+                is_spent: false,
+            }),
+        }
+    }
+
+    fn get_strategy(coin_selection: CoinSelection) -> (Strategy, Rc<RefCell<SelectionReport>>) {
+        let report = Rc::new(RefCell::new(SelectionReport::default()));
+
+        let mut tags = HashMap::new();
+        tags.insert(get_utxo(0, 0).utxo.outpoint(), "A".to_string());
+        tags.insert(get_utxo(1, 0).utxo.outpoint(), "A".to_string());
+        tags.insert(get_utxo(2, 0).utxo.outpoint(), "B".to_string());
+
+        (
+            Strategy {
+                coin_selection,
+                tags,
+                report: report.clone(),
+            },
+            report,
+        )
+    }
+
+    fn get_utxos() -> Vec<WeightedUtxo> {
+        vec![
+            get_utxo(0, 30_000),
+            get_utxo(1, 30_000),
+            get_utxo(2, 200_000),
+            get_utxo(3, 10_000),
+        ]
+    }
+
+    #[test]
+    fn test_privacy() {
+        let (strategy, report) = get_strategy(CoinSelection::Privacy);
+
+        // Untagged coins fall short, tag A is the smallest group that covers it
+        let result = strategy
+            .coin_select(
+                &MemoryDatabase::new(),
+                vec![],
+                get_utxos(),
+                FeeRate::from_sat_per_vb(1.0),
+                50_000,
+                &Script::from(vec![0; 22]),
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 2);
+        for utxo in result.selected {
+            assert_eq!(strategy.tags[&utxo.outpoint()], "A");
+        }
+
+        let report = report.borrow();
+        assert_eq!(report.strategy, "privacy");
+        assert_eq!(
+            report.reasons[&get_utxo(0, 0).utxo.outpoint()],
+            "only coins tagged A"
+        );
+
+        // Never mixes tags, even when asked to
+        assert!(strategy
+            .coin_select(
+                &MemoryDatabase::new(),
+                vec![get_utxo(0, 30_000), get_utxo(2, 200_000)],
+                vec![],
+                FeeRate::from_sat_per_vb(1.0),
+                50_000,
+                &Script::from(vec![0; 22]),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_largest_first() {
+        let (strategy, report) = get_strategy(CoinSelection::LargestFirst);
+
+        let result = strategy
+            .coin_select(
+                &MemoryDatabase::new(),
+                vec![get_utxo(3, 10_000)],
+                get_utxos().into_iter().take(3).collect(),
+                FeeRate::from_sat_per_vb(1.0),
+                50_000,
+                &Script::from(vec![0; 22]),
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 2);
+
+        let report = report.borrow();
+        assert_eq!(report.strategy, "largest_first");
+        assert_eq!(
+            report.reasons[&get_utxo(2, 0).utxo.outpoint()],
+            "largest coins first"
+        );
+        assert_eq!(report.reasons[&get_utxo(3, 0).utxo.outpoint()], REQUIRED);
+    }
+}
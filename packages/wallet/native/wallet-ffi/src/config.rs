@@ -0,0 +1,58 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::SyncConfig;
+use sled::Tree;
+
+const SYNC_CONFIG_KEY: &str = "envoy:sync_config";
+
+// Wallets that never had one saved sync with the defaults
+pub fn get_sync_config(tree: &Tree) -> Result<SyncConfig, bdk::Error> {
+    match tree.get(SYNC_CONFIG_KEY)? {
+        None => Ok(SyncConfig::default()),
+        Some(value) => Ok(serde_json::from_slice(&value)?),
+    }
+}
+
+pub fn set_sync_config(tree: &Tree, sync_config: &SyncConfig) -> Result<(), bdk::Error> {
+    if sync_config.stop_gap == 0 {
+        return Err(bdk::Error::Generic(
+            "Stop gap must be at least 1".to_string(),
+        ));
+    }
+
+    tree.insert(SYNC_CONFIG_KEY, serde_json::to_vec(sync_config)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+
+    #[test]
+    fn test_sync_config() {
+        let tree = util::get_test_tree();
+
+        assert_eq!(get_sync_config(&tree).unwrap(), SyncConfig::default());
+
+        let sync_config = SyncConfig {
+            stop_gap: 200,
+            retry: 3,
+            timeout: 20,
+            batch_size: 100,
+        };
+        set_sync_config(&tree, &sync_config).unwrap();
+        assert_eq!(get_sync_config(&tree).unwrap(), sync_config);
+
+        assert!(set_sync_config(
+            &tree,
+            &SyncConfig {
+                stop_gap: 0,
+                ..sync_config
+            }
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,167 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::util;
+use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
+use bdk::bitcoin::{OutPoint, Txid};
+use bdk::wallet::AddressIndex;
+use bdk::{FeeRate, TransactionDetails};
+use sled::Tree;
+
+// Child spending our outputs of an unconfirmed parent back to ourselves, paying enough for the
+// parent and child together to reach fee_rate
+pub fn build_cpfp(
+    wallet: &bdk::Wallet<Tree>,
+    txid: Txid,
+    fee_rate: FeeRate,
+) -> Result<(PartiallySignedTransaction, TransactionDetails), bdk::Error> {
+    let parent = wallet
+        .get_tx(&txid, true)?
+        .ok_or(bdk::Error::TransactionNotFound)?;
+
+    if parent.confirmation_time.is_some() {
+        return Err(bdk::Error::TransactionConfirmed);
+    }
+
+    let parent_tx = parent.transaction.ok_or(bdk::Error::TransactionNotFound)?;
+    // Only known when the server gave us the transactions it spends
+    let parent_fee = parent.fee.ok_or_else(|| {
+        bdk::Error::Generic("Fee of the parent transaction is unknown".to_string())
+    })?;
+    let parent_vsize = (parent_tx.weight() + 3) / 4;
+
+    if parent_fee >= fee_rate.fee_vb(parent_vsize) {
+        return Err(bdk::Error::Generic(
+            "Parent transaction already pays the target fee rate".to_string(),
+        ));
+    }
+
+    let utxos: Vec<OutPoint> = wallet
+        .list_unspent()?
+        .into_iter()
+        .filter(|utxo| utxo.outpoint.txid == txid)
+        .map(|utxo| utxo.outpoint)
+        .collect();
+
+    if utxos.is_empty() {
+        return Err(bdk::Error::Generic(
+            "Transaction has no unspent outputs of ours".to_string(),
+        ));
+    }
+
+    let change = wallet
+        .get_internal_address(AddressIndex::LastUnused)?
+        .script_pubkey();
+
+    let build = |fee_absolute: Option<u64>| -> Result<_, bdk::Error> {
+        let mut builder = wallet.build_tx();
+        builder
+            .add_utxos(&utxos)?
+            .manually_selected_only()
+            .drain_to(change.clone())
+            .enable_rbf();
+
+        if util::is_segwit(wallet) {
+            builder.only_witness_utxo();
+        }
+
+        match fee_absolute {
+            None => builder.fee_rate(fee_rate),
+            Some(fee) => builder.fee_absolute(fee),
+        };
+
+        builder.finish()
+    };
+
+    // At the target rate on its own the child's fee gives away its size, signatures included
+    let (_, details) = build(None)?;
+    let child_fee = details.fee.unwrap_or(0);
+
+    build(Some(fee_rate.fee_vb(parent_vsize) + child_fee - parent_fee))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::{
+        Network, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut, Witness,
+    };
+    use bdk::database::{BatchOperations, Database};
+    use bdk::{KeychainKind, LocalUtxo};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_build_cpfp() {
+        let wallet = util::get_test_wallet(Network::Testnet);
+        let mut tree_handle = wallet.database().clone();
+
+        let address = wallet.get_address(AddressIndex::New).unwrap();
+
+        // 1 in 1 out P2WPKH, 110 vB paying 1 sat/vB
+        let parent = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(
+                    Txid::from_str(
+                        "a9a6b4c5e1f2d3c4b5a6978877665544332211ffeeddccbbaa99887766554433",
+                    )
+                    .unwrap(),
+                    0,
+                ),
+                script_sig: Script::new(),
+                sequence: Sequence(0xFFFFFFFD),
+                witness: Witness::from_vec(vec![vec![0; 72], vec![0; 33]]),
+            }],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        let txid = parent.txid();
+
+        tree_handle
+            .set_tx(&TransactionDetails {
+                transaction: Some(parent.clone()),
+                txid,
+                received: 100_000,
+                sent: 0,
+                fee: Some(110),
+                confirmation_time: None,
+            })
+            .unwrap();
+        tree_handle
+            .set_utxo(&LocalUtxo {
+                outpoint: OutPoint::new(txid, 0),
+                txout: parent.output[0].clone(),
+                keychain: KeychainKind::External,
+                is_spent: false,
+            })
+            .unwrap();
+
+        let (psbt, details) = build_cpfp(&wallet, txid, FeeRate::from_sat_per_vb(10.0)).unwrap();
+
+        assert_eq!(
+            psbt.unsigned_tx.input[0].previous_output,
+            OutPoint::new(txid, 0)
+        );
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            wallet
+                .database()
+                .get_path_from_script_pubkey(&psbt.unsigned_tx.output[0].script_pubkey)
+                .unwrap()
+                .unwrap()
+                .0,
+            KeychainKind::Internal
+        );
+
+        // About 10 sat/vB over the parent's 110 vB and the child's ~110 vB
+        let fee = details.fee.unwrap();
+        assert!((2070..=2110).contains(&fee), "child fee {fee}");
+
+        // Nothing to do once the parent pays enough by itself
+        assert!(build_cpfp(&wallet, txid, FeeRate::from_sat_per_vb(1.0)).is_err());
+    }
+}
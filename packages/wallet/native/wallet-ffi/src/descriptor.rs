@@ -0,0 +1,156 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::import::key_origin;
+use crate::KeyNetwork;
+use bdk::bitcoin::secp256k1::Secp256k1;
+use bdk::bitcoin::Network;
+use bdk::descriptor::calc_checksum;
+use bdk::miniscript::descriptor::{
+    Descriptor, DescriptorPublicKey, DescriptorSecretKey, DescriptorType,
+};
+use bdk::miniscript::ForEachKey;
+
+pub struct DescriptorSummary {
+    pub descriptor: String,
+    pub public_descriptor: String,
+    pub checksum: String,
+    // Unknown when no key says which network it belongs to, e.g. bare public keys
+    pub network: KeyNetwork,
+    pub script_type: String,
+    pub key_origins: Vec<String>,
+    pub ranged: bool,
+    pub private: bool,
+}
+
+fn script_type_name(desc_type: DescriptorType) -> &'static str {
+    match desc_type {
+        DescriptorType::Bare => "bare",
+        DescriptorType::Sh => "sh",
+        DescriptorType::Pkh => "pkh",
+        DescriptorType::Wpkh => "wpkh",
+        DescriptorType::Wsh => "wsh",
+        DescriptorType::ShWsh => "sh-wsh",
+        DescriptorType::ShWpkh => "sh-wpkh",
+        DescriptorType::ShSortedMulti => "sh-sortedmulti",
+        DescriptorType::WshSortedMulti => "wsh-sortedmulti",
+        DescriptorType::ShWshSortedMulti => "sh-wsh-sortedmulti",
+        DescriptorType::Tr => "tr",
+    }
+}
+
+// Append a freshly computed BIP-380 checksum, replacing any that was there
+pub fn with_checksum(descriptor: &str) -> Result<(String, String), bdk::Error> {
+    let descriptor = descriptor.split('#').next().unwrap_or("");
+    let checksum = calc_checksum(descriptor)?;
+
+    Ok((format!("{descriptor}#{checksum}"), checksum))
+}
+
+fn merge_network(network: KeyNetwork, key_network: Network) -> Result<KeyNetwork, bdk::Error> {
+    let key_network = match key_network {
+        Network::Bitcoin => KeyNetwork::Mainnet,
+        _ => KeyNetwork::TestNetworks,
+    };
+
+    match network {
+        KeyNetwork::Unknown => Ok(key_network),
+        n if n != key_network => Err(bdk::Error::Generic(
+            "Descriptor mixes mainnet and test network keys".to_string(),
+        )),
+        n => Ok(n),
+    }
+}
+
+pub fn get_descriptor_summary(descriptor: &str) -> Result<DescriptorSummary, bdk::Error> {
+    let secp = Secp256k1::new();
+    let (parsed, key_map) =
+        Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor.trim())
+            .map_err(|e| bdk::Error::Generic(e.to_string()))?;
+
+    let mut network = KeyNetwork::Unknown;
+    let mut key_origins = vec![];
+    let mut key_networks = vec![];
+
+    parsed.for_each_key(|key| {
+        if let Some(origin) = key_origin(key) {
+            key_origins.push(origin);
+        }
+        if let DescriptorPublicKey::XPub(xpub) = key {
+            key_networks.push(xpub.xkey.network);
+        }
+        true
+    });
+
+    for secret in key_map.values() {
+        key_networks.push(match secret {
+            DescriptorSecretKey::Single(single) => single.key.network,
+            DescriptorSecretKey::XPrv(xprv) => xprv.xkey.network,
+        });
+    }
+
+    for key_network in key_networks {
+        network = merge_network(network, key_network)?;
+    }
+
+    let (public_descriptor, checksum) = with_checksum(&parsed.to_string())?;
+    let (descriptor, _) = with_checksum(&parsed.to_string_with_secret(&key_map))?;
+
+    Ok(DescriptorSummary {
+        descriptor,
+        public_descriptor,
+        checksum,
+        network,
+        script_type: script_type_name(parsed.desc_type()).to_string(),
+        key_origins,
+        ranged: parsed.has_wildcard(),
+        private: !key_map.is_empty(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_descriptor_summary() {
+        let summary = get_descriptor_summary("wpkh([ab88de89/84'/0'/0']xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/0/*)").unwrap();
+
+        assert_eq!(summary.script_type, "wpkh");
+        assert_eq!(summary.network, KeyNetwork::Mainnet);
+        assert_eq!(summary.key_origins, vec!["[ab88de89/84'/0'/0']"]);
+        assert!(summary.ranged);
+        assert!(!summary.private);
+        assert_eq!(summary.checksum.len(), 8);
+        assert_eq!(summary.descriptor, summary.public_descriptor);
+
+        // Same descriptor with its checksum is accepted and gives the same canonical form
+        assert_eq!(
+            get_descriptor_summary(&summary.descriptor)
+                .unwrap()
+                .descriptor,
+            summary.descriptor
+        );
+    }
+
+    #[test]
+    fn test_descriptor_network() {
+        // Could be testnet, signet or regtest
+        let summary = get_descriptor_summary("wpkh([ab88de89/84'/1'/0']tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp/0/*)").unwrap();
+        assert_eq!(summary.network, KeyNetwork::TestNetworks);
+
+        let summary = get_descriptor_summary(
+            "wpkh(02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443)",
+        )
+        .unwrap();
+        assert_eq!(summary.network, KeyNetwork::Unknown);
+
+        assert!(get_descriptor_summary("wsh(sortedmulti(1,tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp/0/*,xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/0/*))").is_err());
+    }
+
+    #[test]
+    fn test_descriptor_bad_checksum() {
+        assert!(get_descriptor_summary("wpkh([ab88de89/84'/0'/0']xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/0/*)#00000000").is_err());
+    }
+}
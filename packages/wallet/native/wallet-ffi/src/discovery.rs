@@ -0,0 +1,261 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::electrum::HistorySource;
+use crate::{import, util, ScriptType, SyncConfig};
+use bdk::bitcoin::secp256k1::{All, Secp256k1};
+use bdk::bitcoin::util::bip32::ExtendedPrivKey;
+use bdk::bitcoin::{Network, Script, Txid};
+use bdk::database::MemoryDatabase;
+use bdk::wallet::AddressIndex;
+use std::collections::HashSet;
+
+const SCRIPT_TYPES: [ScriptType; 4] = [
+    ScriptType::P2wpkh,
+    ScriptType::P2tr,
+    ScriptType::P2shP2wpkh,
+    ScriptType::P2pkh,
+];
+
+// Hard stop in case a server reports history for every account
+const MAX_ACCOUNTS: u32 = 100;
+
+pub struct DiscoveredAccount {
+    pub script_type: ScriptType,
+    pub account: u32,
+    pub path: String,
+    pub tx_count: u32,
+}
+
+// First gap_limit scripts of both keychains
+fn get_account_scripts(
+    external_descriptor: &str,
+    internal_descriptor: &str,
+    network: Network,
+    gap_limit: u32,
+) -> Result<Vec<Script>, bdk::Error> {
+    let wallet = bdk::Wallet::new(
+        external_descriptor,
+        Some(internal_descriptor),
+        network,
+        MemoryDatabase::new(),
+    )?;
+
+    let mut scripts = vec![];
+    for index in 0..gap_limit {
+        scripts.push(
+            wallet
+                .get_address(AddressIndex::Peek(index))?
+                .script_pubkey(),
+        );
+        scripts.push(
+            wallet
+                .get_internal_address(AddressIndex::Peek(index))?
+                .script_pubkey(),
+        );
+    }
+
+    Ok(scripts)
+}
+
+// Distinct transactions touching any of the scripts, asked for batch_size at a time
+fn get_tx_count<H: HistorySource>(
+    history: &H,
+    scripts: &[Script],
+    batch_size: usize,
+) -> Result<u32, bdk::Error> {
+    let mut txids: HashSet<Txid> = HashSet::new();
+
+    for chunk in scripts.chunks(batch_size.max(1)) {
+        let histories = history.get_histories(chunk)?;
+        txids.extend(histories.into_iter().flatten().map(|(txid, _)| txid));
+    }
+
+    Ok(txids.len() as u32)
+}
+
+// BIP44 account discovery: scan accounts of each script type until one has no history
+pub fn discover_accounts<H: HistorySource>(
+    history: &H,
+    secp: &Secp256k1<All>,
+    xprv: &ExtendedPrivKey,
+    network: Network,
+    sync_config: &SyncConfig,
+) -> Result<Vec<DiscoveredAccount>, bdk::Error> {
+    let mut accounts = vec![];
+
+    for script_type in SCRIPT_TYPES {
+        for account in 0..MAX_ACCOUNTS {
+            let path = import::get_account_path(script_type, network, account)?;
+            let descriptors = util::get_descriptors(secp, xprv, &path, script_type)?;
+
+            let scripts = get_account_scripts(
+                &descriptors.external_pub,
+                &descriptors.internal_pub,
+                network,
+                sync_config.stop_gap,
+            )?;
+
+            let tx_count = get_tx_count(history, &scripts, sync_config.batch_size as usize)?;
+            if tx_count == 0 {
+                break;
+            }
+
+            accounts.push(DiscoveredAccount {
+                script_type,
+                account,
+                path: path.to_string(),
+                tx_count,
+            });
+        }
+    }
+
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::electrum::Histories;
+    use crate::NetworkType;
+    use bdk::bitcoin::hashes::Hash;
+    use bdk::bitcoin::Transaction;
+    use std::cell::Cell;
+
+    // One transaction for each script has_history picks, counting the lookups
+    struct MockHistory<F: Fn(&Script) -> bool> {
+        has_history: F,
+        lookups: Cell<u32>,
+    }
+
+    impl<F: Fn(&Script) -> bool> HistorySource for MockHistory<F> {
+        fn get_histories(&self, scripts: &[Script]) -> Result<Histories, bdk::Error> {
+            self.lookups.set(self.lookups.get() + 1);
+            Ok(scripts
+                .iter()
+                .map(|script| match (self.has_history)(script) {
+                    true => vec![(Txid::hash(script.as_bytes()), None)],
+                    false => vec![],
+                })
+                .collect())
+        }
+
+        fn get_transactions(&self, _: &[Txid]) -> Result<Vec<Transaction>, bdk::Error> {
+            Ok(vec![])
+        }
+
+        fn get_block_times(&self, _: &[u32]) -> Result<Vec<u32>, bdk::Error> {
+            Ok(vec![])
+        }
+
+        fn get_tip(&self) -> Result<u32, bdk::Error> {
+            Ok(0)
+        }
+    }
+
+    fn get_xprv() -> ExtendedPrivKey {
+        util::get_master_xprv(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            None,
+            NetworkType::Testnet,
+        )
+        .unwrap()
+    }
+
+    fn get_sync_config(stop_gap: u32) -> SyncConfig {
+        SyncConfig {
+            stop_gap,
+            ..SyncConfig::default()
+        }
+    }
+
+    fn get_scripts(
+        secp: &Secp256k1<All>,
+        xprv: &ExtendedPrivKey,
+        script_type: ScriptType,
+        account: u32,
+    ) -> Vec<Script> {
+        let path = import::get_account_path(script_type, Network::Testnet, account).unwrap();
+        let descriptors = util::get_descriptors(secp, xprv, &path, script_type).unwrap();
+
+        get_account_scripts(
+            &descriptors.external_pub,
+            &descriptors.internal_pub,
+            Network::Testnet,
+            2,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_discover_accounts() {
+        let secp = Secp256k1::new();
+        let xprv = get_xprv();
+
+        // A change address of account 0 and both receive addresses of account 1
+        let mut used = HashSet::new();
+        for script_type in SCRIPT_TYPES {
+            let account_0 = get_scripts(&secp, &xprv, script_type, 0);
+            let account_1 = get_scripts(&secp, &xprv, script_type, 1);
+            used.insert(account_0[1].clone());
+            used.insert(account_1[0].clone());
+            used.insert(account_1[2].clone());
+        }
+
+        let history = MockHistory {
+            has_history: |script: &Script| used.contains(script),
+            lookups: Cell::new(0),
+        };
+        let accounts = discover_accounts(
+            &history,
+            &secp,
+            &xprv,
+            Network::Testnet,
+            &get_sync_config(2),
+        )
+        .unwrap();
+
+        let found: Vec<(ScriptType, u32, String, u32)> = accounts
+            .into_iter()
+            .map(|a| (a.script_type, a.account, a.path, a.tx_count))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (ScriptType::P2wpkh, 0, "m/84'/1'/0'".to_string(), 1),
+                (ScriptType::P2wpkh, 1, "m/84'/1'/1'".to_string(), 2),
+                (ScriptType::P2tr, 0, "m/86'/1'/0'".to_string(), 1),
+                (ScriptType::P2tr, 1, "m/86'/1'/1'".to_string(), 2),
+                (ScriptType::P2shP2wpkh, 0, "m/49'/1'/0'".to_string(), 1),
+                (ScriptType::P2shP2wpkh, 1, "m/49'/1'/1'".to_string(), 2),
+                (ScriptType::P2pkh, 0, "m/44'/1'/0'".to_string(), 1),
+                (ScriptType::P2pkh, 1, "m/44'/1'/1'".to_string(), 2),
+            ]
+        );
+
+        // Each script type stops at account 2, the first without history
+        assert_eq!(history.lookups.get(), 12);
+    }
+
+    #[test]
+    fn test_discover_accounts_max() {
+        let secp = Secp256k1::new();
+        let history = MockHistory {
+            has_history: |_: &Script| true,
+            lookups: Cell::new(0),
+        };
+
+        let accounts = discover_accounts(
+            &history,
+            &secp,
+            &get_xprv(),
+            Network::Testnet,
+            &get_sync_config(1),
+        )
+        .unwrap();
+        assert_eq!(accounts.len(), 4 * MAX_ACCOUNTS as usize);
+        assert_eq!(accounts.last().unwrap().account, MAX_ACCOUNTS - 1);
+        assert_eq!(history.lookups.get(), 4 * MAX_ACCOUNTS);
+    }
+}
@@ -0,0 +1,899 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Wallet sync against an Electrum server. Follows BDK's own script sync, but we drive the
+// batches so there's somewhere to report progress from between them
+
+use crate::SyncConfig;
+use bdk::bitcoin::{OutPoint, Script, Transaction, Txid};
+use bdk::database::{BatchDatabase, BatchOperations, Database, SyncTime};
+use bdk::descriptor::ExtendedDescriptor;
+use bdk::electrum_client::raw_client::{ElectrumSslStream, RawClient};
+use bdk::electrum_client::{
+    Batch, Client, ClientType, ElectrumApi, Error, GetBalanceRes, GetHeadersRes, GetHistoryRes,
+    GetMerkleRes, ListUnspentRes, Param, RawHeaderNotification, ScriptStatus, ServerFeaturesRes,
+};
+use bdk::{BlockTime, KeychainKind, LocalUtxo, TransactionDetails};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Txids and confirmation heights, one list per script
+pub type Histories = Vec<Vec<(Txid, Option<u32>)>>;
+
+// Called with scripts checked, scripts to check and transactions seen after every batch
+type OnBatch<'a> = Box<dyn Fn(usize, usize, usize) + 'a>;
+
+// What a sync needs from the server. Any Electrum client will do, tests stand in for one
+pub trait HistorySource {
+    // In the order the scripts were asked for
+    fn get_histories(&self, scripts: &[Script]) -> Result<Histories, bdk::Error>;
+    fn get_transactions(&self, txids: &[Txid]) -> Result<Vec<Transaction>, bdk::Error>;
+    fn get_block_times(&self, heights: &[u32]) -> Result<Vec<u32>, bdk::Error>;
+    fn get_tip(&self) -> Result<u32, bdk::Error>;
+}
+
+impl<C: ElectrumApi> HistorySource for C {
+    fn get_histories(&self, scripts: &[Script]) -> Result<Histories, bdk::Error> {
+        Ok(self
+            .batch_script_get_history(scripts)?
+            .into_iter()
+            .map(|history| {
+                history
+                    .into_iter()
+                    // Mempool transactions come back at 0, or -1 with unconfirmed parents
+                    .map(|tx| (tx.tx_hash, Some(tx.height as u32).filter(|_| tx.height > 0)))
+                    .collect()
+            })
+            .collect())
+    }
+
+    fn get_transactions(&self, txids: &[Txid]) -> Result<Vec<Transaction>, bdk::Error> {
+        Ok(self.batch_transaction_get(txids)?)
+    }
+
+    fn get_block_times(&self, heights: &[u32]) -> Result<Vec<u32>, bdk::Error> {
+        Ok(self
+            .batch_block_header(heights.iter().copied())?
+            .into_iter()
+            .map(|header| header.time)
+            .collect())
+    }
+
+    fn get_tip(&self) -> Result<u32, bdk::Error> {
+        Ok(self.block_headers_subscribe()?.height as u32)
+    }
+}
+
+// Answers from the histories and transactions it's given, in place of an Electrum server
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockServer {
+    pub histories: HashMap<Script, Vec<(Txid, Option<u32>)>>,
+    pub transactions: HashMap<Txid, Transaction>,
+}
+
+#[cfg(test)]
+impl HistorySource for MockServer {
+    fn get_histories(&self, scripts: &[Script]) -> Result<Histories, bdk::Error> {
+        Ok(scripts
+            .iter()
+            .map(|script| self.histories.get(script).cloned().unwrap_or_default())
+            .collect())
+    }
+
+    fn get_transactions(&self, txids: &[Txid]) -> Result<Vec<Transaction>, bdk::Error> {
+        txids
+            .iter()
+            .map(|txid| {
+                self.transactions
+                    .get(txid)
+                    .cloned()
+                    .ok_or(bdk::Error::TransactionNotFound)
+            })
+            .collect()
+    }
+
+    fn get_block_times(&self, heights: &[u32]) -> Result<Vec<u32>, bdk::Error> {
+        Ok(heights
+            .iter()
+            .map(|height| 1_600_000_000 + height)
+            .collect())
+    }
+
+    fn get_tip(&self) -> Result<u32, bdk::Error> {
+        Ok(1000)
+    }
+}
+
+// electrum-client's own Client, or a raw one over a TLS connection whose certificate we
+// checked against the pins during the handshake. Over Tor it's a raw one too, on a stream we
+// opened so it could have a timeout, which electrum-client won't set along with a proxy
+pub enum ElectrumClient {
+    Client(Client),
+    Pinned(RawClient<ElectrumSslStream>),
+    Proxied(RawClient<TcpStream>),
+    ProxiedSsl(RawClient<ElectrumSslStream>),
+}
+
+macro_rules! delegate {
+    ($self:ident, $method:ident $(, $arg:expr)*) => {
+        match $self {
+            ElectrumClient::Client(client) => client.$method($($arg),*),
+            ElectrumClient::Pinned(client) => client.$method($($arg),*),
+            ElectrumClient::Proxied(client) => client.$method($($arg),*),
+            ElectrumClient::ProxiedSsl(client) => client.$method($($arg),*),
+        }
+    };
+}
+
+// BDK's ElectrumBlockchain only takes electrum-client's Client, which can wrap the raw ones
+impl From<ElectrumClient> for Client {
+    fn from(client: ElectrumClient) -> Self {
+        match client {
+            ElectrumClient::Client(client) => client,
+            ElectrumClient::Pinned(client) => ClientType::SSL(client).into(),
+            ElectrumClient::Proxied(client) => ClientType::TCP(client).into(),
+            ElectrumClient::ProxiedSsl(client) => ClientType::SSL(client).into(),
+        }
+    }
+}
+
+impl ElectrumApi for ElectrumClient {
+    fn raw_call(
+        &self,
+        method_name: &str,
+        params: impl IntoIterator<Item = Param>,
+    ) -> Result<serde_json::Value, Error> {
+        delegate!(self, raw_call, method_name, params)
+    }
+
+    fn batch_call(&self, batch: &Batch) -> Result<Vec<serde_json::Value>, Error> {
+        delegate!(self, batch_call, batch)
+    }
+
+    fn block_headers_subscribe_raw(&self) -> Result<RawHeaderNotification, Error> {
+        delegate!(self, block_headers_subscribe_raw)
+    }
+
+    fn block_headers_pop_raw(&self) -> Result<Option<RawHeaderNotification>, Error> {
+        delegate!(self, block_headers_pop_raw)
+    }
+
+    fn block_header_raw(&self, height: usize) -> Result<Vec<u8>, Error> {
+        delegate!(self, block_header_raw, height)
+    }
+
+    fn block_headers(&self, start_height: usize, count: usize) -> Result<GetHeadersRes, Error> {
+        delegate!(self, block_headers, start_height, count)
+    }
+
+    fn estimate_fee(&self, number: usize) -> Result<f64, Error> {
+        delegate!(self, estimate_fee, number)
+    }
+
+    fn relay_fee(&self) -> Result<f64, Error> {
+        delegate!(self, relay_fee)
+    }
+
+    fn script_subscribe(&self, script: &Script) -> Result<Option<ScriptStatus>, Error> {
+        delegate!(self, script_subscribe, script)
+    }
+
+    fn script_unsubscribe(&self, script: &Script) -> Result<bool, Error> {
+        delegate!(self, script_unsubscribe, script)
+    }
+
+    fn script_pop(&self, script: &Script) -> Result<Option<ScriptStatus>, Error> {
+        delegate!(self, script_pop, script)
+    }
+
+    fn script_get_balance(&self, script: &Script) -> Result<GetBalanceRes, Error> {
+        delegate!(self, script_get_balance, script)
+    }
+
+    fn batch_script_get_balance<'s, I>(&self, scripts: I) -> Result<Vec<GetBalanceRes>, Error>
+    where
+        I: IntoIterator<Item = &'s Script> + Clone,
+    {
+        delegate!(self, batch_script_get_balance, scripts)
+    }
+
+    fn script_get_history(&self, script: &Script) -> Result<Vec<GetHistoryRes>, Error> {
+        delegate!(self, script_get_history, script)
+    }
+
+    fn batch_script_get_history<'s, I>(&self, scripts: I) -> Result<Vec<Vec<GetHistoryRes>>, Error>
+    where
+        I: IntoIterator<Item = &'s Script> + Clone,
+    {
+        delegate!(self, batch_script_get_history, scripts)
+    }
+
+    fn script_list_unspent(&self, script: &Script) -> Result<Vec<ListUnspentRes>, Error> {
+        delegate!(self, script_list_unspent, script)
+    }
+
+    fn batch_script_list_unspent<'s, I>(
+        &self,
+        scripts: I,
+    ) -> Result<Vec<Vec<ListUnspentRes>>, Error>
+    where
+        I: IntoIterator<Item = &'s Script> + Clone,
+    {
+        delegate!(self, batch_script_list_unspent, scripts)
+    }
+
+    fn transaction_get_raw(&self, txid: &Txid) -> Result<Vec<u8>, Error> {
+        delegate!(self, transaction_get_raw, txid)
+    }
+
+    fn batch_transaction_get_raw<'t, I>(&self, txids: I) -> Result<Vec<Vec<u8>>, Error>
+    where
+        I: IntoIterator<Item = &'t Txid> + Clone,
+    {
+        delegate!(self, batch_transaction_get_raw, txids)
+    }
+
+    fn batch_block_header_raw<I>(&self, heights: I) -> Result<Vec<Vec<u8>>, Error>
+    where
+        I: IntoIterator<Item = u32> + Clone,
+    {
+        delegate!(self, batch_block_header_raw, heights)
+    }
+
+    fn batch_estimate_fee<I>(&self, numbers: I) -> Result<Vec<f64>, Error>
+    where
+        I: IntoIterator<Item = usize> + Clone,
+    {
+        delegate!(self, batch_estimate_fee, numbers)
+    }
+
+    fn transaction_broadcast_raw(&self, raw_tx: &[u8]) -> Result<Txid, Error> {
+        delegate!(self, transaction_broadcast_raw, raw_tx)
+    }
+
+    fn transaction_get_merkle(&self, txid: &Txid, height: usize) -> Result<GetMerkleRes, Error> {
+        delegate!(self, transaction_get_merkle, txid, height)
+    }
+
+    fn server_features(&self) -> Result<ServerFeaturesRes, Error> {
+        delegate!(self, server_features)
+    }
+
+    fn ping(&self) -> Result<(), Error> {
+        delegate!(self, ping)
+    }
+}
+
+// BDK caches this many of each keychain's scripts before it first asks the server
+const CACHE_ADDR_BATCH_SIZE: u32 = 100;
+
+pub struct ElectrumSync<'a, C> {
+    client: C,
+    // To derive past what the wallet has cached when activity runs up to the end
+    descriptors: HashMap<KeychainKind, ExtendedDescriptor>,
+    stop_gap: usize,
+    batch_size: usize,
+    on_batch: Option<OnBatch<'a>>,
+    // Checked between batches so a cancelled sync stops without committing anything
+    cancelled: Option<&'a AtomicBool>,
+}
+
+// What the server had for the wallet. Fetched without touching the wallet, then applied
+// to its database in one batch while the wallet is locked
+pub struct SyncUpdate {
+    // Derived during the sync, the database doesn't have them yet
+    scripts: Vec<(Script, KeychainKind, u32)>,
+    txs: Vec<TransactionDetails>,
+    last_active: HashMap<KeychainKind, u32>,
+    tip: u32,
+}
+
+impl<'a, C: HistorySource> ElectrumSync<'a, C> {
+    pub fn new<D: BatchDatabase>(
+        client: C,
+        wallet: &bdk::Wallet<D>,
+        sync_config: &SyncConfig,
+    ) -> Result<Self, bdk::Error> {
+        let mut descriptors = HashMap::new();
+        for keychain in [KeychainKind::External, KeychainKind::Internal] {
+            if let Some(descriptor) = wallet.public_descriptor(keychain)? {
+                descriptors.insert(keychain, descriptor);
+            }
+        }
+
+        Ok(ElectrumSync {
+            client,
+            descriptors,
+            stop_gap: sync_config.stop_gap.max(1) as usize,
+            batch_size: sync_config.batch_size.max(1) as usize,
+            on_batch: None,
+            cancelled: None,
+        })
+    }
+
+    pub fn on_batch(mut self, on_batch: impl Fn(usize, usize, usize) + 'a) -> Self {
+        self.on_batch = Some(Box::new(on_batch));
+        self
+    }
+
+    pub fn cancel_on(mut self, cancelled: &'a AtomicBool) -> Self {
+        self.cancelled = Some(cancelled);
+        self
+    }
+
+    fn check_cancelled(&self) -> Result<(), bdk::Error> {
+        match self.cancelled.is_some_and(|c| c.load(Ordering::Relaxed)) {
+            true => Err(bdk::Error::Generic("Sync cancelled".to_string())),
+            false => Ok(()),
+        }
+    }
+
+    // Derives the scripts BDK's sync would have cached before asking the server
+    fn top_up(&self, keychain: KeychainKind, scripts: &mut Vec<Script>) {
+        if let Some(descriptor) = self.descriptors.get(&keychain) {
+            let cached = match descriptor.has_wildcard() {
+                true => CACHE_ADDR_BATCH_SIZE,
+                false => 1,
+            };
+            for child in scripts.len() as u32..cached {
+                scripts.push(descriptor.at_derivation_index(child).script_pubkey());
+            }
+        }
+    }
+
+    // Loads what the database already has and asks the server for the rest
+    fn fetch_transactions<D: Database>(
+        &self,
+        database: &D,
+        cache: &mut HashMap<Txid, Transaction>,
+        txids: &[Txid],
+    ) -> Result<(), bdk::Error> {
+        let mut missing = BTreeSet::new();
+        for txid in txids {
+            if cache.contains_key(txid) {
+                continue;
+            }
+
+            match database.get_raw_tx(txid)? {
+                Some(tx) => {
+                    cache.insert(*txid, tx);
+                }
+                None => {
+                    missing.insert(*txid);
+                }
+            }
+        }
+
+        let missing: Vec<Txid> = missing.into_iter().collect();
+        for batch in missing.chunks(self.batch_size) {
+            for tx in self.client.get_transactions(batch)? {
+                cache.insert(tx.txid(), tx);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_details(
+        ours: &HashSet<Script>,
+        cache: &HashMap<Txid, Transaction>,
+        txid: &Txid,
+    ) -> Result<TransactionDetails, bdk::Error> {
+        let tx = cache.get(txid).ok_or_else(server_misbehaving)?;
+
+        let mut sent = 0;
+        let mut received = 0;
+        let mut inputs_sum: u64 = 0;
+        let mut outputs_sum: u64 = 0;
+
+        // Coinbase inputs have nothing to look up
+        for input in tx.input.iter().filter(|i| !i.previous_output.is_null()) {
+            let previous = cache
+                .get(&input.previous_output.txid)
+                .and_then(|tx| tx.output.get(input.previous_output.vout as usize))
+                .ok_or_else(server_misbehaving)?;
+
+            inputs_sum += previous.value;
+            if ours.contains(&previous.script_pubkey) {
+                sent += previous.value;
+            }
+        }
+
+        for output in &tx.output {
+            outputs_sum += output.value;
+            if ours.contains(&output.script_pubkey) {
+                received += output.value;
+            }
+        }
+
+        Ok(TransactionDetails {
+            txid: *txid,
+            transaction: Some(tx.clone()),
+            received,
+            sent,
+            // Filled in once we have the block times
+            confirmation_time: None,
+            // Saturating so coinbase transactions come out at zero
+            fee: Some(inputs_sum.saturating_sub(outputs_sum)),
+        })
+    }
+
+    // Only reads the database, so it can run while the wallet is in use
+    pub fn fetch<D: Database>(&self, database: &D) -> Result<SyncUpdate, bdk::Error> {
+        let tip = self.client.get_tip()?;
+
+        let keychains = [KeychainKind::External, KeychainKind::Internal];
+        let mut scripts = vec![];
+        let mut cached = vec![];
+        for keychain in keychains {
+            let mut keychain_scripts = database.iter_script_pubkeys(Some(keychain))?;
+            cached.push(keychain_scripts.len());
+            self.top_up(keychain, &mut keychain_scripts);
+            scripts.push(keychain_scripts);
+        }
+        let mut total = scripts.iter().map(Vec::len).sum();
+
+        let mut heights: HashMap<Txid, Option<u32>> = HashMap::new();
+        let mut last_active = HashMap::new();
+        let mut checked = 0;
+
+        for (keychain, scripts) in keychains.iter().zip(&mut scripts) {
+            let mut last = None;
+            let mut index = 0;
+            while index < scripts.len() {
+                self.check_cancelled()?;
+
+                let batch = &scripts[index..scripts.len().min(index + self.batch_size)];
+                let histories = self.client.get_histories(batch)?;
+                if histories.len() != batch.len() {
+                    return Err(server_misbehaving());
+                }
+
+                for (i, history) in histories.into_iter().enumerate() {
+                    if !history.is_empty() {
+                        last = Some(index + i);
+                    }
+                    heights.extend(history);
+                }
+
+                index += batch.len();
+                checked += batch.len();
+
+                // Keep going until stop_gap unused scripts in a row
+                let wanted = last.map_or(0, |last| last + 1 + self.stop_gap);
+                let descriptor = self.descriptors.get(keychain);
+                if index == scripts.len() && wanted > index {
+                    if let Some(descriptor) = descriptor.filter(|d| d.has_wildcard()) {
+                        for child in index as u32..wanted as u32 {
+                            scripts.push(descriptor.at_derivation_index(child).script_pubkey());
+                        }
+                        total += wanted - index;
+                    }
+                }
+
+                if let Some(on_batch) = &self.on_batch {
+                    on_batch(checked, total, heights.len());
+                }
+            }
+
+            if let Some(last) = last {
+                last_active.insert(*keychain, last as u32);
+            }
+        }
+
+        let ours: HashSet<Script> = scripts.iter().flatten().cloned().collect();
+        let mut new_scripts = vec![];
+        for ((keychain, scripts), cached) in keychains.iter().zip(&scripts).zip(cached) {
+            for (child, script) in scripts.iter().enumerate().skip(cached) {
+                new_scripts.push((script.clone(), *keychain, child as u32));
+            }
+        }
+
+        let mut finished = vec![];
+        let mut needed = vec![];
+        let mut missing_conftime = vec![];
+
+        for (txid, height) in &heights {
+            match database.get_tx(txid, true)? {
+                Some(mut details) if details.transaction.is_some() => {
+                    let known = details.confirmation_time.as_ref().map(|time| time.height);
+                    match (known, height) {
+                        // Confirmed since we last saw it, or moved by a reorg
+                        (None, Some(height)) => missing_conftime.push((details, *height)),
+                        (Some(known), Some(height)) if known != *height => {
+                            missing_conftime.push((details, *height))
+                        }
+                        // Back in the mempool after a reorg
+                        (Some(_), None) => {
+                            details.confirmation_time = None;
+                            finished.push(details);
+                        }
+                        _ => finished.push(details),
+                    }
+                }
+                _ => needed.push((*txid, *height)),
+            }
+        }
+
+        let mut cache = HashMap::new();
+        for batch in needed.chunks(self.batch_size) {
+            self.check_cancelled()?;
+
+            let txids: Vec<Txid> = batch.iter().map(|(txid, _)| *txid).collect();
+            self.fetch_transactions(database, &mut cache, &txids)?;
+
+            // Previous outputs, for the fee and what we sent
+            let previous: Vec<Txid> = txids
+                .iter()
+                .filter_map(|txid| cache.get(txid))
+                .flat_map(|tx| &tx.input)
+                .filter(|input| !input.previous_output.is_null())
+                .map(|input| input.previous_output.txid)
+                .collect();
+            self.fetch_transactions(database, &mut cache, &previous)?;
+
+            for (txid, height) in batch {
+                let details = Self::get_details(&ours, &cache, txid)?;
+                match height {
+                    Some(height) => missing_conftime.push((details, *height)),
+                    None => finished.push(details),
+                }
+            }
+        }
+
+        let needed_heights: Vec<u32> = missing_conftime
+            .iter()
+            .map(|(_, height)| *height)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let mut block_times = HashMap::new();
+        for batch in needed_heights.chunks(self.batch_size) {
+            let times = self.client.get_block_times(batch)?;
+            block_times.extend(batch.iter().copied().zip(times));
+        }
+
+        for (mut details, height) in missing_conftime {
+            details.confirmation_time = Some(BlockTime {
+                height,
+                timestamp: *block_times.get(&height).ok_or_else(server_misbehaving)? as u64,
+            });
+            finished.push(details);
+        }
+
+        self.check_cancelled()?;
+
+        Ok(SyncUpdate {
+            scripts: new_scripts,
+            txs: finished,
+            last_active,
+            tip,
+        })
+    }
+}
+
+impl SyncUpdate {
+    pub fn apply<D: BatchDatabase>(&self, database: &mut D) -> Result<(), bdk::Error> {
+        let batch = get_db_update(database, self)?;
+        database.commit_batch(batch)
+    }
+}
+
+fn server_misbehaving() -> bdk::Error {
+    bdk::Error::Generic("Electrum server misbehaving".to_string())
+}
+
+// Everything gathered for the update has its transaction, this keeps a slip from panicking
+fn get_transaction(details: &TransactionDetails) -> Result<&Transaction, bdk::Error> {
+    details
+        .transaction
+        .as_ref()
+        .ok_or(bdk::Error::TransactionNotFound)
+}
+
+// Of two transactions spending the same output keep the one paying more, as BDK does
+fn make_txs_consistent(txs: &[TransactionDetails]) -> Result<Vec<&TransactionDetails>, bdk::Error> {
+    let mut spends: HashMap<OutPoint, &TransactionDetails> = HashMap::new();
+    for details in txs {
+        for input in &get_transaction(details)?.input {
+            spends
+                .entry(input.previous_output)
+                .and_modify(|existing| match (details.fee, existing.fee) {
+                    (Some(fee), Some(existing_fee)) if fee > existing_fee => *existing = details,
+                    (Some(_), None) => *existing = details,
+                    _ => {}
+                })
+                .or_insert(details);
+        }
+    }
+
+    let kept: HashMap<Txid, &TransactionDetails> = spends
+        .into_values()
+        .map(|details| (details.txid, details))
+        .collect();
+    Ok(kept.into_values().collect())
+}
+
+fn get_db_update<D: BatchDatabase>(
+    database: &D,
+    update: &SyncUpdate,
+) -> Result<D::Batch, bdk::Error> {
+    let txs = make_txs_consistent(&update.txs)?;
+    let seen: HashSet<Txid> = txs.iter().map(|details| details.txid).collect();
+
+    let mut batch = database.begin_batch();
+
+    let mut new_scripts = HashMap::new();
+    for (script, keychain, child) in &update.scripts {
+        batch.set_script_pubkey(script, *keychain, *child)?;
+        new_scripts.insert(script, *keychain);
+    }
+
+    // Whatever the server no longer knows about goes, with its outputs
+    for details in database.iter_txs(false)? {
+        if seen.contains(&details.txid) {
+            continue;
+        }
+
+        if let Some(tx) = database.get_raw_tx(&details.txid)? {
+            for vout in 0..tx.output.len() as u32 {
+                batch.del_utxo(&OutPoint {
+                    txid: details.txid,
+                    vout,
+                })?;
+            }
+        }
+        batch.del_tx(&details.txid, true)?;
+    }
+
+    let mut spent: HashSet<OutPoint> = HashSet::new();
+    for details in &txs {
+        spent.extend(
+            get_transaction(details)?
+                .input
+                .iter()
+                .map(|input| input.previous_output),
+        );
+    }
+
+    for details in &txs {
+        let tx = get_transaction(details)?;
+        for (vout, output) in tx.output.iter().enumerate() {
+            let keychain = match database.get_path_from_script_pubkey(&output.script_pubkey)? {
+                Some((keychain, _)) => Some(keychain),
+                None => new_scripts.get(&output.script_pubkey).copied(),
+            };
+
+            if let Some(keychain) = keychain {
+                let outpoint = OutPoint {
+                    txid: details.txid,
+                    vout: vout as u32,
+                };
+
+                batch.set_utxo(&LocalUtxo {
+                    outpoint,
+                    txout: output.clone(),
+                    keychain,
+                    is_spent: spent.contains(&outpoint),
+                })?;
+            }
+        }
+
+        batch.set_tx(details)?;
+    }
+
+    // Never move the last used index backwards
+    for (keychain, index) in &update.last_active {
+        let index = match database.get_last_index(*keychain)? {
+            Some(current) => current.max(*index),
+            None => *index,
+        };
+        batch.set_last_index(*keychain, index)?;
+    }
+
+    batch.set_sync_time(SyncTime {
+        block_time: BlockTime {
+            height: update.tip,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+        },
+    })?;
+
+    Ok(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+    use bdk::bitcoin::{Network, PackedLockTime, TxIn, TxOut};
+    use bdk::wallet::AddressIndex;
+    use sled::Tree;
+
+    fn get_script(wallet: &bdk::Wallet<Tree>, index: u32) -> Script {
+        wallet
+            .get_address(AddressIndex::Peek(index))
+            .unwrap()
+            .script_pubkey()
+    }
+
+    fn get_tx(input: OutPoint, lock_time: u32, script_pubkey: Script) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime(lock_time),
+            input: vec![TxIn {
+                previous_output: input,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey,
+            }],
+        }
+    }
+
+    // Each transaction in the history of the scripts it pays
+    fn get_server(txs: &[(&Transaction, Option<u32>)]) -> MockServer {
+        let mut server = MockServer::default();
+        for (tx, height) in txs {
+            for output in &tx.output {
+                server
+                    .histories
+                    .entry(output.script_pubkey.clone())
+                    .or_default()
+                    .push((tx.txid(), *height));
+            }
+            server.transactions.insert(tx.txid(), (*tx).clone());
+        }
+        server
+    }
+
+    fn fetch(wallet: &bdk::Wallet<Tree>, server: MockServer) -> SyncUpdate {
+        ElectrumSync::new(server, wallet, &SyncConfig::default())
+            .unwrap()
+            .fetch(&*wallet.database())
+            .unwrap()
+    }
+
+    fn get_details(tx: &Transaction, fee: Option<u64>) -> TransactionDetails {
+        TransactionDetails {
+            txid: tx.txid(),
+            transaction: Some(tx.clone()),
+            received: 0,
+            sent: 0,
+            fee,
+            confirmation_time: None,
+        }
+    }
+
+    fn count_scripts(wallet: &bdk::Wallet<Tree>, keychain: KeychainKind) -> usize {
+        wallet
+            .database()
+            .iter_script_pubkeys(Some(keychain))
+            .unwrap()
+            .len()
+    }
+
+    #[test]
+    fn test_fetch_extends_stop_gap() {
+        let wallet = util::get_test_wallet(Network::Testnet);
+
+        // 130 is only looked at once the payment to 90 has pushed the stop gap past it
+        let first = get_tx(OutPoint::null(), 0, get_script(&wallet, 90));
+        let second = get_tx(OutPoint::null(), 1, get_script(&wallet, 130));
+        let update = fetch(&wallet, get_server(&[(&first, Some(500)), (&second, None)]));
+
+        // Nothing is written until the update is applied
+        assert_eq!(count_scripts(&wallet, KeychainKind::External), 0);
+        assert_eq!(
+            update.last_active,
+            HashMap::from([(KeychainKind::External, 130)])
+        );
+        assert_eq!(update.txs.len(), 2);
+
+        update.apply(&mut wallet.database().clone()).unwrap();
+        assert_eq!(count_scripts(&wallet, KeychainKind::External), 181);
+        assert_eq!(count_scripts(&wallet, KeychainKind::Internal), 100);
+        assert_eq!(wallet.list_unspent().unwrap().len(), 2);
+        assert_eq!(
+            wallet
+                .database()
+                .get_last_index(KeychainKind::External)
+                .unwrap(),
+            Some(130)
+        );
+        assert_eq!(
+            wallet
+                .database()
+                .get_sync_time()
+                .unwrap()
+                .unwrap()
+                .block_time
+                .height,
+            1000
+        );
+    }
+
+    #[test]
+    fn test_apply_reorg() {
+        let wallet = util::get_test_wallet(Network::Testnet);
+        let first = get_tx(OutPoint::null(), 0, get_script(&wallet, 0));
+        let second = get_tx(OutPoint::null(), 1, get_script(&wallet, 1));
+
+        fetch(
+            &wallet,
+            get_server(&[(&first, Some(500)), (&second, Some(600))]),
+        )
+        .apply(&mut wallet.database().clone())
+        .unwrap();
+        assert_eq!(wallet.list_transactions(false).unwrap().len(), 2);
+
+        // The first is back in the mempool and the second is gone along with its output
+        fetch(&wallet, get_server(&[(&first, None)]))
+            .apply(&mut wallet.database().clone())
+            .unwrap();
+
+        let txs = wallet.list_transactions(false).unwrap();
+        assert_eq!(txs.len(), 1);
+        assert_eq!(txs[0].txid, first.txid());
+        assert!(txs[0].confirmation_time.is_none());
+
+        let utxos = wallet.list_unspent().unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].outpoint.txid, first.txid());
+    }
+
+    #[test]
+    fn test_make_txs_consistent() {
+        let wallet = util::get_test_wallet(Network::Testnet);
+        let script = get_script(&wallet, 0);
+        let spent = OutPoint::new(get_tx(OutPoint::null(), 0, script.clone()).txid(), 0);
+
+        let low = get_details(&get_tx(spent, 1, script.clone()), Some(1_000));
+        let high = get_details(&get_tx(spent, 2, script.clone()), Some(2_000));
+        let unknown = get_details(&get_tx(spent, 3, script.clone()), None);
+        let other = get_details(&get_tx(OutPoint::new(spent.txid, 1), 4, script), Some(500));
+
+        // The one paying more wins whichever order they come in
+        for txs in [
+            vec![low.clone(), high.clone(), unknown.clone(), other.clone()],
+            vec![unknown, high.clone(), low, other.clone()],
+        ] {
+            let kept: HashSet<Txid> = make_txs_consistent(&txs)
+                .unwrap()
+                .into_iter()
+                .map(|details| details.txid)
+                .collect();
+            assert_eq!(kept, HashSet::from([high.txid, other.txid]));
+        }
+    }
+
+    #[test]
+    fn test_last_index_never_decreases() {
+        let wallet = util::get_test_wallet(Network::Testnet);
+        let mut tree = wallet.database().clone();
+        tree.set_last_index(KeychainKind::External, 150).unwrap();
+
+        let update = SyncUpdate {
+            scripts: vec![],
+            txs: vec![],
+            last_active: HashMap::from([(KeychainKind::External, 90), (KeychainKind::Internal, 5)]),
+            tip: 1000,
+        };
+        update.apply(&mut tree).unwrap();
+
+        assert_eq!(
+            tree.get_last_index(KeychainKind::External).unwrap(),
+            Some(150)
+        );
+        assert_eq!(
+            tree.get_last_index(KeychainKind::Internal).unwrap(),
+            Some(5)
+        );
+    }
+}
@@ -0,0 +1,332 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::descriptor::with_checksum;
+use crate::ExportFormat;
+use bdk::bitcoin::util::base58;
+use bdk::bitcoin::util::bip32::ExtendedPubKey;
+use bdk::bitcoin::Network;
+use bdk::database::BatchDatabase;
+use bdk::descriptor::ExtendedDescriptor;
+use bdk::miniscript::descriptor::{
+    Descriptor, DescriptorPublicKey, DescriptorType, DescriptorXKey, ShInner, WshInner,
+};
+use bdk::miniscript::ForEachKey;
+use bdk::wallet::AddressIndex;
+use bdk::KeychainKind;
+use serde_json::{json, Value};
+
+// SLIP-132 version bytes Electrum uses to tell script types apart
+fn slip132_version(network: Network, desc_type: DescriptorType) -> Option<[u8; 4]> {
+    let mainnet = network == Network::Bitcoin;
+
+    match (desc_type, mainnet) {
+        (DescriptorType::Pkh | DescriptorType::ShSortedMulti, true) => {
+            Some([0x04, 0x88, 0xb2, 0x1e])
+        }
+        (DescriptorType::Pkh | DescriptorType::ShSortedMulti, false) => {
+            Some([0x04, 0x35, 0x87, 0xcf])
+        }
+        (DescriptorType::ShWpkh, true) => Some([0x04, 0x9d, 0x7c, 0xb2]),
+        (DescriptorType::ShWpkh, false) => Some([0x04, 0x4a, 0x52, 0x62]),
+        (DescriptorType::Wpkh, true) => Some([0x04, 0xb2, 0x47, 0x46]),
+        (DescriptorType::Wpkh, false) => Some([0x04, 0x5f, 0x1c, 0xf6]),
+        (DescriptorType::ShWshSortedMulti, true) => Some([0x02, 0x95, 0xb4, 0x3f]),
+        (DescriptorType::ShWshSortedMulti, false) => Some([0x02, 0x42, 0x89, 0xef]),
+        (DescriptorType::WshSortedMulti, true) => Some([0x02, 0xaa, 0x7e, 0xd3]),
+        (DescriptorType::WshSortedMulti, false) => Some([0x02, 0x57, 0x54, 0x83]),
+        _ => None,
+    }
+}
+
+fn is_multisig(desc_type: DescriptorType) -> bool {
+    matches!(
+        desc_type,
+        DescriptorType::ShSortedMulti
+            | DescriptorType::WshSortedMulti
+            | DescriptorType::ShWshSortedMulti
+    )
+}
+
+fn get_xkeys(descriptor: &ExtendedDescriptor) -> Vec<DescriptorXKey<ExtendedPubKey>> {
+    let mut xkeys = vec![];
+
+    descriptor.for_each_key(|key| {
+        if let DescriptorPublicKey::XPub(xkey) = key {
+            xkeys.push(xkey.clone());
+        }
+        true
+    });
+
+    xkeys
+}
+
+fn get_threshold(descriptor: &ExtendedDescriptor) -> Option<usize> {
+    let multi = match descriptor {
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::SortedMulti(multi) => return Some(multi.k),
+            ShInner::Wsh(wsh) => wsh.as_inner(),
+            _ => return None,
+        },
+        Descriptor::Wsh(wsh) => wsh.as_inner(),
+        _ => return None,
+    };
+
+    match multi {
+        WshInner::SortedMulti(multi) => Some(multi.k),
+        WshInner::Ms(_) => None,
+    }
+}
+
+fn electrum_keystore(xkey: &DescriptorXKey<ExtendedPubKey>, version: [u8; 4]) -> Value {
+    let mut data = xkey.xkey.encode();
+    data[0..4].copy_from_slice(&version);
+    let xpub = base58::check_encode_slice(&data);
+
+    match &xkey.origin {
+        None => json!({
+            "type": "bip32",
+            "xpub": xpub,
+        }),
+        Some((fingerprint, path)) => json!({
+            "type": "bip32",
+            "xpub": xpub,
+            "derivation": path.to_string(),
+            "root_fingerprint": fingerprint.to_string(),
+            "label": fingerprint.to_string(),
+        }),
+    }
+}
+
+fn export_electrum<T: BatchDatabase>(
+    wallet: &bdk::Wallet<T>,
+    descriptor: &ExtendedDescriptor,
+) -> Result<String, bdk::Error> {
+    let desc_type = descriptor.desc_type();
+    let version = slip132_version(wallet.network(), desc_type).ok_or_else(|| {
+        bdk::Error::Generic("Electrum doesn't support this script type".to_string())
+    })?;
+
+    let xkeys = get_xkeys(descriptor);
+    let mut json = json!({
+        "use_encryption": false,
+        "seed_version": 17,
+    });
+
+    if is_multisig(desc_type) {
+        let threshold = get_threshold(descriptor)
+            .ok_or_else(|| bdk::Error::Generic("Can't find multisig threshold".to_string()))?;
+
+        json["wallet_type"] = json!(format!("{}of{}", threshold, xkeys.len()));
+        for (i, xkey) in xkeys.iter().enumerate() {
+            json[format!("x{}/", i + 1)] = electrum_keystore(xkey, version);
+        }
+    } else {
+        let xkey = xkeys
+            .first()
+            .ok_or_else(|| bdk::Error::Generic("No extended key in descriptor".to_string()))?;
+
+        json["wallet_type"] = json!("standard");
+        json["keystore"] = electrum_keystore(xkey, version);
+    }
+
+    serde_json::to_string_pretty(&json).map_err(|e| e.into())
+}
+
+fn export_specter(
+    label: &str,
+    descriptor: &ExtendedDescriptor,
+    external_descriptor: &str,
+) -> Result<String, bdk::Error> {
+    let devices: Vec<Value> = get_xkeys(descriptor)
+        .iter()
+        .filter_map(|xkey| xkey.origin.as_ref())
+        .map(|(fingerprint, _)| {
+            json!({
+                "type": "other",
+                "label": fingerprint.to_string(),
+            })
+        })
+        .collect();
+
+    let json = json!({
+        "label": label,
+        "blockheight": 0,
+        "descriptor": external_descriptor,
+        "devices": devices,
+    });
+
+    serde_json::to_string_pretty(&json).map_err(|e| e.into())
+}
+
+// BIP-129 descriptor record, shared with the other cosigners to verify the setup
+fn export_bsms<T: BatchDatabase>(
+    wallet: &bdk::Wallet<T>,
+    descriptor: &ExtendedDescriptor,
+    external_descriptor: &str,
+) -> Result<String, bdk::Error> {
+    if !is_multisig(descriptor.desc_type()) {
+        return Err(bdk::Error::Generic(
+            "BSMS is only used for multisig wallets".to_string(),
+        ));
+    }
+
+    let template = external_descriptor
+        .split('#')
+        .next()
+        .unwrap_or("")
+        .replace("/0/*", "/**");
+    let (template, _) = with_checksum(&template)?;
+
+    let first_address = wallet.get_address(AddressIndex::Peek(0))?.address;
+
+    Ok(format!(
+        "BSMS 1.0\n{template}\n/0/*,/1/*\n{first_address}\n"
+    ))
+}
+
+pub fn export_wallet<T: BatchDatabase>(
+    wallet: &bdk::Wallet<T>,
+    label: &str,
+    format: ExportFormat,
+) -> Result<String, bdk::Error> {
+    // These hold public keys only, private keys live with the signers
+    let descriptor = wallet.get_descriptor_for_keychain(KeychainKind::External);
+    let (external_descriptor, _) = with_checksum(&descriptor.to_string())?;
+    let (internal_descriptor, _) = with_checksum(
+        &wallet
+            .get_descriptor_for_keychain(KeychainKind::Internal)
+            .to_string(),
+    )?;
+
+    match format {
+        ExportFormat::Descriptor => Ok(format!(
+            "# {label}\n# Receive\n{external_descriptor}\n# Change\n{internal_descriptor}\n"
+        )),
+        ExportFormat::Electrum => export_electrum(wallet, descriptor),
+        ExportFormat::Specter => export_specter(label, descriptor, &external_descriptor),
+        ExportFormat::Bsms => export_bsms(wallet, descriptor, &external_descriptor),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{get_parsed_account, parse_account_export, ParsedAccount};
+    use bdk::database::MemoryDatabase;
+
+    const XPUB_1: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+    const XPUB_2: &str = "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB";
+
+    fn get_single_sig_wallet() -> bdk::Wallet<MemoryDatabase> {
+        let descriptor = format!("wpkh([ab88de89/84'/0'/0']{XPUB_1}/0/*)");
+
+        bdk::Wallet::new(
+            &descriptor,
+            Some(&descriptor.replace("/0/*", "/1/*")),
+            Network::Bitcoin,
+            MemoryDatabase::new(),
+        )
+        .unwrap()
+    }
+
+    fn get_multisig_wallet() -> bdk::Wallet<MemoryDatabase> {
+        let descriptor = format!(
+            "wsh(sortedmulti(2,[ab88de89/48'/0'/0'/2']{XPUB_1}/0/*,[0f056943/48'/0'/0'/2']{XPUB_2}/0/*))"
+        );
+
+        bdk::Wallet::new(
+            &descriptor,
+            Some(&descriptor.replace("/0/*", "/1/*")),
+            Network::Bitcoin,
+            MemoryDatabase::new(),
+        )
+        .unwrap()
+    }
+
+    fn assert_same_descriptors(wallet: &bdk::Wallet<MemoryDatabase>, account: &ParsedAccount) {
+        assert_eq!(
+            account.external_descriptor,
+            wallet
+                .get_descriptor_for_keychain(KeychainKind::External)
+                .to_string()
+        );
+        assert_eq!(
+            account.internal_descriptor,
+            wallet
+                .get_descriptor_for_keychain(KeychainKind::Internal)
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_get_threshold() {
+        let wallet = get_multisig_wallet();
+        let descriptor = wallet.get_descriptor_for_keychain(KeychainKind::External);
+        assert_eq!(get_threshold(descriptor), Some(2));
+
+        let wallet = get_single_sig_wallet();
+        let descriptor = wallet.get_descriptor_for_keychain(KeychainKind::External);
+        assert_eq!(get_threshold(descriptor), None);
+    }
+
+    #[test]
+    fn test_export_descriptor() {
+        for wallet in [get_single_sig_wallet(), get_multisig_wallet()] {
+            let export = export_wallet(&wallet, "Test", ExportFormat::Descriptor).unwrap();
+            let lines: Vec<&str> = export.lines().collect();
+            assert_eq!(lines[0], "# Test");
+
+            let account = get_parsed_account("Test", lines[2], Network::Bitcoin).unwrap();
+            assert_same_descriptors(&wallet, &account);
+            assert_eq!(lines[4], account.internal_descriptor);
+        }
+    }
+
+    #[test]
+    fn test_export_electrum() {
+        for wallet in [get_single_sig_wallet(), get_multisig_wallet()] {
+            let export = export_wallet(&wallet, "Test", ExportFormat::Electrum).unwrap();
+
+            let accounts = parse_account_export(&export, Network::Bitcoin).unwrap();
+            assert_eq!(accounts.len(), 1);
+            assert_same_descriptors(&wallet, &accounts[0]);
+        }
+
+        let export = export_wallet(&get_multisig_wallet(), "Test", ExportFormat::Electrum).unwrap();
+        let json: Value = serde_json::from_str(&export).unwrap();
+        assert_eq!(json["wallet_type"], "2of2");
+    }
+
+    #[test]
+    fn test_export_specter() {
+        for wallet in [get_single_sig_wallet(), get_multisig_wallet()] {
+            let export = export_wallet(&wallet, "Test", ExportFormat::Specter).unwrap();
+
+            let accounts = parse_account_export(&export, Network::Bitcoin).unwrap();
+            assert_eq!(accounts[0].label, "Test");
+            assert_same_descriptors(&wallet, &accounts[0]);
+        }
+    }
+
+    #[test]
+    fn test_export_bsms() {
+        assert!(export_wallet(&get_single_sig_wallet(), "Test", ExportFormat::Bsms).is_err());
+
+        let wallet = get_multisig_wallet();
+        let export = export_wallet(&wallet, "Test", ExportFormat::Bsms).unwrap();
+        let lines: Vec<&str> = export.lines().collect();
+        assert_eq!(lines[0], "BSMS 1.0");
+        assert_eq!(lines[2], "/0/*,/1/*");
+
+        // The template covers both keychains
+        let template = lines[1].split('#').next().unwrap();
+        let account =
+            get_parsed_account("Test", &template.replace("/**", "/0/*"), Network::Bitcoin).unwrap();
+        assert_same_descriptors(&wallet, &account);
+
+        let first_address = wallet.get_address(AddressIndex::Peek(0)).unwrap().address;
+        assert_eq!(lines[3], first_address.to_string());
+    }
+}
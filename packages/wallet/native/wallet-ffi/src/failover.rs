@@ -0,0 +1,367 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::electrum::ElectrumClient;
+use crate::{util, SyncConfig};
+use bdk::bitcoin::blockdata::constants::genesis_block;
+use bdk::bitcoin::Network;
+use bdk::electrum_client;
+use bdk::electrum_client::ElectrumApi;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Servers failing this many times in a row are tried after the healthy ones
+const MAX_CONSECUTIVE_ERRORS: u32 = 3;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerHealth {
+    pub successes: u32,
+    pub errors: u32,
+    pub consecutive_errors: u32,
+    // Ping round trip when last connected to successfully
+    pub latency_ms: u32,
+    pub last_error: Option<String>,
+}
+
+lazy_static! {
+    static ref SERVER_HEALTH: Mutex<HashMap<String, ServerHealth>> = Mutex::new(HashMap::new());
+}
+
+fn record_success(server: &str, latency_ms: u32) {
+    if let Ok(mut health) = SERVER_HEALTH.lock() {
+        let entry = health.entry(server.to_string()).or_default();
+        entry.successes += 1;
+        entry.consecutive_errors = 0;
+        entry.latency_ms = latency_ms;
+    }
+}
+
+fn record_error(server: &str, error: &bdk::Error) {
+    if let Ok(mut health) = SERVER_HEALTH.lock() {
+        let entry = health.entry(server.to_string()).or_default();
+        entry.errors += 1;
+        entry.consecutive_errors += 1;
+        entry.last_error = Some(error.to_string());
+    }
+}
+
+pub fn get_server_health() -> Vec<(String, ServerHealth)> {
+    match SERVER_HEALTH.lock() {
+        Ok(health) => {
+            let mut servers: Vec<_> = health
+                .iter()
+                .map(|(server, h)| (server.clone(), h.clone()))
+                .collect();
+            servers.sort_by(|a, b| a.0.cmp(&b.0));
+            servers
+        }
+        Err(_) => vec![],
+    }
+}
+
+// Keep the given order, only moving servers that keep failing to the back
+fn order_servers<'a>(servers: &[&'a str]) -> Vec<&'a str> {
+    let health = SERVER_HEALTH.lock().map(|h| h.clone()).unwrap_or_default();
+    let is_failing = |server: &str| {
+        health
+            .get(server)
+            .map(|h| h.consecutive_errors >= MAX_CONSECUTIVE_ERRORS)
+            .unwrap_or(false)
+    };
+
+    let mut ordered: Vec<&str> = servers.iter().cloned().filter(|s| !is_failing(s)).collect();
+    ordered.extend(servers.iter().cloned().filter(|s| is_failing(s)));
+    ordered
+}
+
+// A server for another chain would happily serve us wrong history
+fn check_genesis_hash(client: &ElectrumClient, network: Network) -> Result<(), bdk::Error> {
+    let features = client.server_features()?;
+    let expected = genesis_block(network).block_hash().to_string();
+
+    if hex::encode(features.genesis_hash) != expected {
+        return Err(bdk::Error::Generic(format!(
+            "Server is not on {network}, genesis hash {}",
+            hex::encode(features.genesis_hash)
+        )));
+    }
+
+    Ok(())
+}
+
+// Round trip of a request that costs the server nothing, comparable across servers
+fn ping(client: &ElectrumClient) -> Result<u32, bdk::Error> {
+    let start = Instant::now();
+    client.ping()?;
+
+    Ok(start.elapsed().as_millis() as u32)
+}
+
+// The server couldn't be reached or stopped answering. Anything else, like a broadcast the
+// network rejects or an error in our own database, would be the same with any server
+fn is_connection_error(error: &bdk::Error) -> bool {
+    match error {
+        bdk::Error::Electrum(e) => is_electrum_connection_error(e),
+        _ => false,
+    }
+}
+
+fn is_electrum_connection_error(error: &electrum_client::Error) -> bool {
+    match error {
+        electrum_client::Error::IOError(_)
+        | electrum_client::Error::SharedIOError(_)
+        | electrum_client::Error::CouldntLockReader
+        | electrum_client::Error::Mpsc => true,
+        electrum_client::Error::AllAttemptsErrored(errors) => {
+            errors.iter().all(is_electrum_connection_error)
+        }
+        _ => false,
+    }
+}
+
+// A connection to the server that answers and is on our chain, and its ping
+fn connect_checked(
+    server: &str,
+    tor_port: i32,
+    network: Network,
+    sync_config: &SyncConfig,
+) -> Result<(ElectrumClient, u32), bdk::Error> {
+    let client = util::get_electrum_client(tor_port, server, sync_config)?;
+    let latency_ms = ping(&client)?;
+    check_genesis_hash(&client, network)?;
+
+    Ok((client, latency_ms))
+}
+
+// Run the request against each server in turn until one succeeds, returning its result and
+// which server gave it. The request gets the connection the genesis hash was checked on.
+// Only connection errors move on to the next server, the error of the last server tried is
+// returned if all of them fail
+pub fn with_failover<T, F>(
+    servers: &[&str],
+    tor_port: i32,
+    network: Network,
+    sync_config: &SyncConfig,
+    mut request: F,
+) -> Result<(T, String), bdk::Error>
+where
+    F: FnMut(ElectrumClient) -> Result<T, bdk::Error>,
+{
+    let mut last_error = bdk::Error::Generic("No Electrum servers given".to_string());
+
+    for server in order_servers(servers) {
+        // Failing the checks is down to the server, whatever the error
+        let (client, latency_ms) = match connect_checked(server, tor_port, network, sync_config) {
+            Ok(checked) => checked,
+            Err(e) => {
+                warn!("Electrum server {} failed: {}", server, e);
+                record_error(server, &e);
+                last_error = e;
+                continue;
+            }
+        };
+
+        match request(client) {
+            Err(e) if is_connection_error(&e) => {
+                warn!("Electrum server {} failed: {}", server, e);
+                record_error(server, &e);
+                last_error = e;
+            }
+            result => {
+                record_success(server, latency_ms);
+                return result.map(|value| (value, server.to_string()));
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    // Regtest Electrum server answering only what failover needs, counting connections
+    fn start_mock_server() -> (String, Arc<AtomicU32>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("tcp://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicU32::new(0));
+        let counter = connections.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut stream = stream.unwrap();
+
+                thread::spawn(move || {
+                    let reader = BufReader::new(stream.try_clone().unwrap());
+                    for line in reader.lines() {
+                        let request: serde_json::Value =
+                            serde_json::from_str(&line.unwrap()).unwrap();
+                        let result = match request["method"].as_str().unwrap() {
+                            "server.features" => serde_json::json!({
+                                "server_version": "mock",
+                                "genesis_hash": genesis_block(Network::Regtest).block_hash().to_string(),
+                                "protocol_min": "1.4",
+                                "protocol_max": "1.4",
+                                "hash_function": "sha256",
+                                "pruning": null,
+                            }),
+                            "blockchain.estimatefee" => serde_json::json!(0.0002),
+                            _ => serde_json::Value::Null,
+                        };
+
+                        let response = serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "result": result,
+                        });
+                        writeln!(stream, "{response}").unwrap();
+                    }
+                });
+            }
+        });
+
+        (address, connections)
+    }
+
+    #[test]
+    fn test_with_failover() {
+        let (server, connections) = start_mock_server();
+        let sync_config = SyncConfig {
+            retry: 0,
+            ..SyncConfig::default()
+        };
+
+        let (fee_rate, used) = with_failover(
+            &["tcp://127.0.0.1:1", &server],
+            0,
+            Network::Regtest,
+            &sync_config,
+            |client| Ok(client.estimate_fee(6)?),
+        )
+        .unwrap();
+
+        assert_eq!(fee_rate, 0.0002);
+        assert_eq!(used, server);
+
+        // The request ran on the connection that was checked
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+        let health = get_server_health();
+        let (_, refused) = health
+            .iter()
+            .find(|(s, _)| s == "tcp://127.0.0.1:1")
+            .unwrap();
+        assert!(refused.errors >= 1);
+        let (_, served) = health.iter().find(|(s, _)| *s == server).unwrap();
+        assert_eq!(served.successes, 1);
+        assert!(served.latency_ms < 1000);
+
+        // Wrong chain
+        assert!(with_failover(&[&server], 0, Network::Bitcoin, &sync_config, |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_with_failover_rejection() {
+        let (first, _) = start_mock_server();
+        let (second, second_connections) = start_mock_server();
+        let mut requests = 0;
+
+        // What the server says when the network won't take a broadcast
+        let error = with_failover(
+            &[&first, &second],
+            0,
+            Network::Regtest,
+            &SyncConfig::default(),
+            |_| -> Result<(), bdk::Error> {
+                requests += 1;
+                Err(
+                    electrum_client::Error::Protocol(serde_json::json!("min relay fee not met"))
+                        .into(),
+                )
+            },
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            error,
+            bdk::Error::Electrum(electrum_client::Error::Protocol(_))
+        ));
+        assert_eq!(requests, 1);
+        assert_eq!(second_connections.load(Ordering::SeqCst), 0);
+
+        let health = get_server_health();
+        let (_, served) = health.iter().find(|(s, _)| *s == first).unwrap();
+        assert_eq!(served.errors, 0);
+
+        // Same for our own errors
+        let error = with_failover(
+            &[&first, &second],
+            0,
+            Network::Regtest,
+            &SyncConfig::default(),
+            |_| -> Result<(), bdk::Error> { Err(bdk::Error::Generic("Database".to_string())) },
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Generic error: Database");
+        assert_eq!(second_connections.load(Ordering::SeqCst), 0);
+
+        // A dropped connection moves on
+        let (_, used) = with_failover(
+            &[&first, &second],
+            0,
+            Network::Regtest,
+            &SyncConfig::default(),
+            |client| {
+                if requests == 1 {
+                    requests += 1;
+                    return Err(electrum_client::Error::IOError(std::io::Error::from(
+                        std::io::ErrorKind::ConnectionReset,
+                    ))
+                    .into());
+                }
+                Ok(client.estimate_fee(6)?)
+            },
+        )
+        .unwrap();
+        assert_eq!(used, second);
+    }
+
+    #[test]
+    fn test_order_servers() {
+        let error = bdk::Error::Generic("Connection refused".to_string());
+        for _ in 0..MAX_CONSECUTIVE_ERRORS {
+            record_error("ssl://failing.example:50002", &error);
+        }
+        record_success("ssl://healthy.example:50002", 120);
+
+        assert_eq!(
+            order_servers(&[
+                "ssl://failing.example:50002",
+                "ssl://healthy.example:50002",
+                "ssl://unknown.example:50002"
+            ]),
+            vec![
+                "ssl://healthy.example:50002",
+                "ssl://unknown.example:50002",
+                "ssl://failing.example:50002"
+            ]
+        );
+
+        // One success is enough to bring it back
+        record_success("ssl://failing.example:50002", 300);
+        assert_eq!(
+            order_servers(&["ssl://failing.example:50002", "ssl://healthy.example:50002"])[0],
+            "ssl://failing.example:50002"
+        );
+    }
+}
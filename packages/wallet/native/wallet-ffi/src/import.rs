@@ -0,0 +1,610 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::ScriptType;
+use bdk::bitcoin::secp256k1::Secp256k1;
+use bdk::bitcoin::util::base58;
+use bdk::bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey};
+use bdk::bitcoin::Network;
+use bdk::descriptor::IntoWalletDescriptor;
+use bdk::miniscript::descriptor::DescriptorPublicKey;
+use bdk::miniscript::ForEachKey;
+use serde_json::Value;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MultisigType {
+    P2sh,
+    P2shP2wsh,
+    P2wsh,
+}
+
+impl MultisigType {
+    pub fn wrap(&self, threshold: usize, keys: &[String]) -> String {
+        let multi = format!("sortedmulti({},{})", threshold, keys.join(","));
+
+        match self {
+            MultisigType::P2sh => format!("sh({multi})"),
+            MultisigType::P2shP2wsh => format!("sh(wsh({multi}))"),
+            MultisigType::P2wsh => format!("wsh({multi})"),
+        }
+    }
+}
+
+pub struct Slip132Key {
+    pub xpub: ExtendedPubKey,
+    pub script_type: ScriptType,
+    pub multisig_type: MultisigType,
+    // Capitalised prefixes (Ypub, Zpub...) are used for multisig cosigner keys
+    pub multisig: bool,
+}
+
+pub struct XpubAccount {
+    pub xpub: ExtendedPubKey,
+    pub script_type: ScriptType,
+    pub derivation: DerivationPath,
+    pub account: ParsedAccount,
+}
+
+pub struct ParsedAccount {
+    pub label: String,
+    pub external_descriptor: String,
+    pub internal_descriptor: String,
+    pub key_origins: Vec<String>,
+}
+
+// Network, single-sig and multisig script types implied by SLIP-132 version bytes
+fn slip132_version_info(version: &[u8]) -> Option<(Network, ScriptType, MultisigType, bool)> {
+    match version {
+        // xpub
+        [0x04, 0x88, 0xb2, 0x1e] => Some((
+            Network::Bitcoin,
+            ScriptType::P2pkh,
+            MultisigType::P2sh,
+            false,
+        )),
+        // ypub
+        [0x04, 0x9d, 0x7c, 0xb2] => Some((
+            Network::Bitcoin,
+            ScriptType::P2shP2wpkh,
+            MultisigType::P2shP2wsh,
+            false,
+        )),
+        // zpub
+        [0x04, 0xb2, 0x47, 0x46] => Some((
+            Network::Bitcoin,
+            ScriptType::P2wpkh,
+            MultisigType::P2wsh,
+            false,
+        )),
+        // Ypub
+        [0x02, 0x95, 0xb4, 0x3f] => Some((
+            Network::Bitcoin,
+            ScriptType::P2shP2wpkh,
+            MultisigType::P2shP2wsh,
+            true,
+        )),
+        // Zpub
+        [0x02, 0xaa, 0x7e, 0xd3] => Some((
+            Network::Bitcoin,
+            ScriptType::P2wpkh,
+            MultisigType::P2wsh,
+            true,
+        )),
+        // tpub
+        [0x04, 0x35, 0x87, 0xcf] => Some((
+            Network::Testnet,
+            ScriptType::P2pkh,
+            MultisigType::P2sh,
+            false,
+        )),
+        // upub
+        [0x04, 0x4a, 0x52, 0x62] => Some((
+            Network::Testnet,
+            ScriptType::P2shP2wpkh,
+            MultisigType::P2shP2wsh,
+            false,
+        )),
+        // vpub
+        [0x04, 0x5f, 0x1c, 0xf6] => Some((
+            Network::Testnet,
+            ScriptType::P2wpkh,
+            MultisigType::P2wsh,
+            false,
+        )),
+        // Upub
+        [0x02, 0x42, 0x89, 0xef] => Some((
+            Network::Testnet,
+            ScriptType::P2shP2wpkh,
+            MultisigType::P2shP2wsh,
+            true,
+        )),
+        // Vpub
+        [0x02, 0x57, 0x54, 0x83] => Some((
+            Network::Testnet,
+            ScriptType::P2wpkh,
+            MultisigType::P2wsh,
+            true,
+        )),
+        _ => None,
+    }
+}
+
+// Decode an extended public key with any SLIP-132 prefix into a standard xpub/tpub
+pub fn parse_slip132(key: &str) -> Result<Slip132Key, bdk::Error> {
+    let mut data =
+        base58::from_check(key.trim()).map_err(|e| bdk::Error::Generic(e.to_string()))?;
+
+    if data.len() != 78 {
+        return Err(bdk::Error::Generic(format!(
+            "Invalid extended public key length: {}",
+            data.len()
+        )));
+    }
+
+    let (network, script_type, multisig_type, multisig) = slip132_version_info(&data[0..4])
+        .ok_or_else(|| bdk::Error::Generic(format!("Unknown extended key version: {key}")))?;
+
+    let version: [u8; 4] = match network {
+        Network::Bitcoin => [0x04, 0x88, 0xb2, 0x1e],
+        _ => [0x04, 0x35, 0x87, 0xcf],
+    };
+    data[0..4].copy_from_slice(&version);
+
+    let xpub = ExtendedPubKey::decode(&data).map_err(|e| bdk::Error::Generic(e.to_string()))?;
+
+    Ok(Slip132Key {
+        xpub,
+        script_type,
+        multisig_type,
+        multisig,
+    })
+}
+
+// Swap a SLIP-132 key in a key expression like [fingerprint/path]Zpub... for a plain xpub
+pub fn normalize_key_expression(expression: &str) -> String {
+    let expression = expression.trim();
+    let (origin, key) = match expression.rfind(']') {
+        Some(i) => expression.split_at(i + 1),
+        None => ("", expression),
+    };
+
+    // Anything we can't convert is left for the descriptor parser to complain about
+    match parse_slip132(key) {
+        Ok(slip132_key) => format!("{origin}{}", slip132_key.xpub),
+        Err(_) => expression.to_string(),
+    }
+}
+
+// BIP44-style account path, e.g. m/84'/0'/0'
+pub fn get_account_path(
+    script_type: ScriptType,
+    network: Network,
+    account: u32,
+) -> Result<DerivationPath, bdk::Error> {
+    let coin_type = match network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    };
+
+    parse_derivation(&format!(
+        "m/{}'/{}'/{}'",
+        script_type.purpose(),
+        coin_type,
+        account
+    ))
+}
+
+// Single-sig descriptors for an extended public key, defaulting to the BIP44/49/84 derivation
+pub fn get_xpub_account(
+    key: &str,
+    fingerprint: Option<&str>,
+    script_type: ScriptType,
+    network: Network,
+) -> Result<XpubAccount, bdk::Error> {
+    let slip132_key = parse_slip132(key)?;
+
+    if slip132_key.multisig {
+        return Err(bdk::Error::Generic(
+            "Multisig cosigner key, use it to create a multisig wallet instead".to_string(),
+        ));
+    }
+
+    // Only plain xpub/tpub keys map to P2pkh, those don't say how they're used
+    let script_type = match slip132_key.script_type {
+        ScriptType::P2pkh => script_type,
+        t => t,
+    };
+
+    // Keys exported at account level carry the account index
+    let xpub = slip132_key.xpub;
+    let account = match (xpub.depth, xpub.child_number) {
+        (3, ChildNumber::Hardened { index }) => index,
+        _ => 0,
+    };
+
+    let derivation = get_account_path(script_type, network, account)?;
+
+    let key = match fingerprint {
+        Some(xfp) if !xfp.is_empty() => key_expression(Some((xfp, &derivation)), &xpub),
+        _ => key_expression(None, &xpub),
+    };
+
+    let label: String = script_type.into();
+    let account = get_parsed_account(&label, &script_type.wrap(&format!("{key}/0/*")), network)?;
+
+    Ok(XpubAccount {
+        xpub,
+        script_type,
+        derivation,
+        account,
+    })
+}
+
+pub fn key_expression(origin: Option<(&str, &DerivationPath)>, xpub: &ExtendedPubKey) -> String {
+    match origin {
+        None => xpub.to_string(),
+        Some((xfp, path)) => format!(
+            "[{}{}]{}",
+            xfp.to_lowercase(),
+            path.to_string().trim_start_matches('m'),
+            xpub
+        ),
+    }
+}
+
+pub fn key_origin(key: &DescriptorPublicKey) -> Option<String> {
+    let (fingerprint, path) = match key {
+        DescriptorPublicKey::Single(single) => single.origin.as_ref(),
+        DescriptorPublicKey::XPub(xpub) => xpub.origin.as_ref(),
+    }?;
+
+    Some(format!(
+        "[{}{}]",
+        fingerprint,
+        path.to_string().trim_start_matches('m')
+    ))
+}
+
+// Check a descriptor against the network and return its canonical form and key origins
+pub fn validate_descriptor(
+    descriptor: &str,
+    network: Network,
+) -> Result<(String, Vec<String>), bdk::Error> {
+    let secp = Secp256k1::new();
+    let (descriptor, _) = descriptor.into_wallet_descriptor(&secp, network)?;
+
+    let mut key_origins = vec![];
+    descriptor.for_each_key(|key| {
+        if let Some(origin) = key_origin(key) {
+            key_origins.push(origin);
+        }
+        true
+    });
+
+    Ok((descriptor.to_string(), key_origins))
+}
+
+pub fn get_parsed_account(
+    label: &str,
+    external_descriptor: &str,
+    network: Network,
+) -> Result<ParsedAccount, bdk::Error> {
+    // Drop any checksum, it won't match once we derive the change descriptor
+    let external_descriptor = external_descriptor.trim().split('#').next().unwrap_or("");
+
+    if !external_descriptor.contains("/0/*") {
+        return Err(bdk::Error::Generic(format!(
+            "Can't derive change descriptor from {external_descriptor}"
+        )));
+    }
+
+    let internal_descriptor = external_descriptor.replace("/0/*", "/1/*");
+
+    let (external_descriptor, key_origins) = validate_descriptor(external_descriptor, network)?;
+    let (internal_descriptor, _) = validate_descriptor(&internal_descriptor, network)?;
+
+    Ok(ParsedAccount {
+        label: label.to_string(),
+        external_descriptor,
+        internal_descriptor,
+        key_origins,
+    })
+}
+
+fn get_str<'a>(value: &'a Value, key: &str) -> Result<&'a str, bdk::Error> {
+    value[key]
+        .as_str()
+        .ok_or_else(|| bdk::Error::Generic(format!("Missing field: {key}")))
+}
+
+fn parse_derivation(path: &str) -> Result<DerivationPath, bdk::Error> {
+    DerivationPath::from_str(path.trim()).map_err(|e| bdk::Error::Generic(e.to_string()))
+}
+
+// Passport (and Coldcard) generic JSON export, one account per BIP44/49/84/86 section
+fn parse_generic_json(json: &Value, network: Network) -> Result<Vec<ParsedAccount>, bdk::Error> {
+    let xfp = get_str(json, "xfp")?;
+    let mut accounts = vec![];
+
+    for (section, script_type) in [
+        ("bip44", ScriptType::P2pkh),
+        ("bip49", ScriptType::P2shP2wpkh),
+        ("bip84", ScriptType::P2wpkh),
+        ("bip86", ScriptType::P2tr),
+    ] {
+        let entry = &json[section];
+        if entry.is_null() {
+            continue;
+        }
+
+        let xpub = parse_slip132(get_str(entry, "xpub")?)?.xpub;
+        let path = parse_derivation(get_str(entry, "deriv")?)?;
+        let key = key_expression(Some((xfp, &path)), &xpub);
+
+        let label: String = script_type.into();
+        accounts.push(get_parsed_account(
+            &label,
+            &script_type.wrap(&format!("{key}/0/*")),
+            network,
+        )?);
+    }
+
+    if accounts.is_empty() {
+        return Err(bdk::Error::Generic(
+            "No single-sig accounts found in export".to_string(),
+        ));
+    }
+
+    Ok(accounts)
+}
+
+fn parse_electrum_keystore(keystore: &Value) -> Result<(Slip132Key, String), bdk::Error> {
+    let slip132_key = parse_slip132(get_str(keystore, "xpub")?)?;
+
+    // Watch-only keystores may not know where their key came from
+    let key = match (
+        keystore["root_fingerprint"].as_str(),
+        keystore["derivation"].as_str(),
+    ) {
+        (Some(xfp), Some(path)) => {
+            let path = parse_derivation(path)?;
+            key_expression(Some((xfp, &path)), &slip132_key.xpub)
+        }
+        _ => key_expression(None, &slip132_key.xpub),
+    };
+
+    Ok((slip132_key, key))
+}
+
+// Electrum wallet file, as also written by Sparrow's Electrum export
+fn parse_electrum_json(json: &Value, network: Network) -> Result<Vec<ParsedAccount>, bdk::Error> {
+    let wallet_type = json["wallet_type"].as_str().unwrap_or("standard");
+    let label = json["label"].as_str().unwrap_or(wallet_type);
+
+    if wallet_type == "standard" {
+        let (slip132_key, key) = parse_electrum_keystore(&json["keystore"])?;
+        let descriptor = slip132_key.script_type.wrap(&format!("{key}/0/*"));
+        return Ok(vec![get_parsed_account(label, &descriptor, network)?]);
+    }
+
+    // Multisig wallet types look like "2of3"
+    let (threshold, total) = wallet_type
+        .split_once("of")
+        .and_then(|(m, n)| Some((m.parse::<usize>().ok()?, n.parse::<usize>().ok()?)))
+        .ok_or_else(|| bdk::Error::Generic(format!("Unsupported wallet type: {wallet_type}")))?;
+
+    let mut multisig_type = None;
+    let mut keys = vec![];
+
+    for i in 1..=total {
+        let (slip132_key, key) = parse_electrum_keystore(&json[format!("x{i}/")])?;
+        multisig_type.get_or_insert(slip132_key.multisig_type);
+        keys.push(format!("{key}/0/*"));
+    }
+
+    let multisig_type = multisig_type.unwrap_or(MultisigType::P2sh);
+    let descriptor = multisig_type.wrap(threshold, &keys);
+
+    Ok(vec![get_parsed_account(label, &descriptor, network)?])
+}
+
+// Specter exports the receive descriptor directly
+fn parse_specter_json(json: &Value, network: Network) -> Result<Vec<ParsedAccount>, bdk::Error> {
+    let label = json["label"].as_str().unwrap_or("");
+    let descriptor = get_str(json, "descriptor")?;
+
+    Ok(vec![get_parsed_account(label, descriptor, network)?])
+}
+
+// Coldcard-style multisig setup file, also produced by Passport and Sparrow
+fn parse_multisig_text(contents: &str, network: Network) -> Result<Vec<ParsedAccount>, bdk::Error> {
+    let mut label = "";
+    let mut policy: Option<(usize, usize)> = None;
+    let mut multisig_type: Option<MultisigType> = None;
+    let mut derivation: Option<DerivationPath> = None;
+    let mut keys = vec![];
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = match line.split_once(':') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => continue,
+        };
+
+        match key.to_lowercase().as_str() {
+            "name" => label = value,
+            "policy" => {
+                policy = value
+                    .split_once("of")
+                    .or_else(|| value.split_once('/'))
+                    .and_then(|(m, n)| Some((m.trim().parse().ok()?, n.trim().parse().ok()?)));
+            }
+            // Applies to all keys that follow it
+            "derivation" => derivation = Some(parse_derivation(value)?),
+            "format" => {
+                multisig_type = Some(match value.to_uppercase().as_str() {
+                    "P2SH" => MultisigType::P2sh,
+                    "P2SH-P2WSH" | "P2WSH-P2SH" => MultisigType::P2shP2wsh,
+                    "P2WSH" => MultisigType::P2wsh,
+                    _ => {
+                        return Err(bdk::Error::Generic(format!(
+                            "Unsupported multisig format: {value}"
+                        )))
+                    }
+                });
+            }
+            xfp if xfp.len() == 8 && hex::decode(xfp).is_ok() => {
+                let slip132_key = parse_slip132(value)?;
+                let path = derivation
+                    .as_ref()
+                    .ok_or_else(|| bdk::Error::Generic(format!("No derivation given for {xfp}")))?;
+
+                multisig_type.get_or_insert(slip132_key.multisig_type);
+                keys.push(format!(
+                    "{}/0/*",
+                    key_expression(Some((xfp, path)), &slip132_key.xpub)
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    let (threshold, total) =
+        policy.ok_or_else(|| bdk::Error::Generic("Missing multisig policy".to_string()))?;
+
+    if keys.len() != total || threshold == 0 || threshold > total {
+        return Err(bdk::Error::Generic(format!(
+            "Policy {threshold} of {total} doesn't match {} keys",
+            keys.len()
+        )));
+    }
+
+    let multisig_type = multisig_type.unwrap_or(MultisigType::P2sh);
+    let descriptor = multisig_type.wrap(threshold, &keys);
+
+    Ok(vec![get_parsed_account(label, &descriptor, network)?])
+}
+
+// Detect the export format and return validated descriptors for every account in it
+pub fn parse_account_export(
+    contents: &str,
+    network: Network,
+) -> Result<Vec<ParsedAccount>, bdk::Error> {
+    let contents = contents.trim();
+
+    if !contents.starts_with('{') {
+        return parse_multisig_text(contents, network);
+    }
+
+    let json: Value =
+        serde_json::from_str(contents).map_err(|e| bdk::Error::Generic(e.to_string()))?;
+
+    if json["descriptor"].is_string() {
+        parse_specter_json(&json, network)
+    } else if json["keystore"].is_object() || json["wallet_type"].is_string() {
+        parse_electrum_json(&json, network)
+    } else if json["xfp"].is_string() {
+        parse_generic_json(&json, network)
+    } else {
+        Err(bdk::Error::Generic(
+            "Unrecognized export format".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XPUB_1: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+    const ZPUB_1: &str = "zpub6jftahH18ngZxUuv6oSniLNrBCSSE1B4EEU59bwTCEt8x6aS6b2mdfLxbS4QS53g85SWWP6wexqeer516433gYpZQoJie2tcMYdJ1SYYYAL";
+    const ZPUB_2: &str = "Zpub6vZyhw1ShkEwNfad9cz3zcSR9Bd87z7UE5uUMowpYPqQsGAY39Bz9gNUkF6rhkdYG6kHHdmDwFjQVoQzWU79xwwB7CcasvrGZeaGsE5JMmK";
+
+    #[test]
+    fn test_parse_slip132() {
+        let key = parse_slip132(ZPUB_1).unwrap();
+        assert_eq!(key.xpub.to_string(), XPUB_1);
+        assert_eq!(key.script_type, ScriptType::P2wpkh);
+        assert!(!key.multisig);
+
+        let key = parse_slip132(ZPUB_2).unwrap();
+        assert_eq!(key.multisig_type, MultisigType::P2wsh);
+        assert!(key.multisig);
+    }
+
+    #[test]
+    fn test_get_xpub_account() {
+        let account = get_xpub_account(
+            ZPUB_1,
+            Some("AB88DE89"),
+            ScriptType::P2pkh,
+            Network::Bitcoin,
+        )
+        .unwrap();
+
+        assert_eq!(account.script_type, ScriptType::P2wpkh);
+        assert_eq!(account.derivation.to_string(), "m/84'/0'/0'");
+        assert!(account
+            .account
+            .external_descriptor
+            .starts_with(&format!("wpkh([ab88de89/84'/0'/0']{XPUB_1}/0/*)")));
+
+        // Plain xpubs follow the requested script type
+        let account = get_xpub_account(XPUB_1, None, ScriptType::P2tr, Network::Bitcoin).unwrap();
+        assert_eq!(account.script_type, ScriptType::P2tr);
+        assert!(account
+            .account
+            .external_descriptor
+            .starts_with(&format!("tr({XPUB_1}/0/*)")));
+
+        assert!(get_xpub_account(ZPUB_2, None, ScriptType::P2wpkh, Network::Bitcoin).is_err());
+    }
+
+    #[test]
+    fn test_parse_generic_json() {
+        let export = format!(
+            r#"{{"xfp": "AB88DE89", "bip84": {{"xpub": "{ZPUB_1}", "deriv": "m/84'/0'/0'"}}}}"#
+        );
+
+        let accounts = parse_account_export(&export, Network::Bitcoin).unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert!(accounts[0]
+            .external_descriptor
+            .starts_with(&format!("wpkh([ab88de89/84'/0'/0']{XPUB_1}/0/*)")));
+        assert!(accounts[0].internal_descriptor.contains("/1/*"));
+        assert_eq!(accounts[0].key_origins, vec!["[ab88de89/84'/0'/0']"]);
+    }
+
+    #[test]
+    fn test_parse_multisig_text() {
+        let export = format!(
+            "# Coldcard Multisig setup file\n\
+             Name: Treasury\n\
+             Policy: 2 of 2\n\
+             Derivation: m/48'/0'/0'/2'\n\
+             Format: P2WSH\n\
+             \n\
+             AB88DE89: {XPUB_1}\n\
+             0F056943: {ZPUB_2}\n"
+        );
+
+        let accounts = parse_account_export(&export, Network::Bitcoin).unwrap();
+        assert_eq!(accounts[0].label, "Treasury");
+        assert!(accounts[0]
+            .external_descriptor
+            .starts_with("wsh(sortedmulti(2,"));
+        assert_eq!(accounts[0].key_origins.len(), 2);
+    }
+
+    #[test]
+    fn test_reject_wrong_network() {
+        let export = format!(r#"{{"label": "Test", "descriptor": "wpkh({XPUB_1}/0/*)"}}"#);
+        assert!(parse_account_export(&export, Network::Testnet).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+
+// Kept in the wallet's own tree, clear of the single byte prefixes BDK uses
+const LABEL_PREFIX: &str = "envoy:label:";
+
+// A single BIP-329 record
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Label {
+    #[serde(rename = "type")]
+    pub label_type: String,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+fn is_known_type(label_type: &str) -> bool {
+    matches!(
+        label_type,
+        "tx" | "addr" | "pubkey" | "input" | "output" | "xpub"
+    )
+}
+
+fn label_key(label_type: &str, reference: &str) -> String {
+    format!("{LABEL_PREFIX}{label_type}:{reference}")
+}
+
+pub fn get_label(
+    tree: &Tree,
+    label_type: &str,
+    reference: &str,
+) -> Result<Option<Label>, bdk::Error> {
+    match tree.get(label_key(label_type, reference))? {
+        None => Ok(None),
+        Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+    }
+}
+
+pub fn set_label(tree: &Tree, label: &Label) -> Result<(), bdk::Error> {
+    let key = label_key(&label.label_type, &label.reference);
+
+    // Nothing left worth keeping
+    if label.label.is_none() && label.spendable.is_none() {
+        tree.remove(key)?;
+        return Ok(());
+    }
+
+    tree.insert(key, serde_json::to_vec(label)?)?;
+    Ok(())
+}
+
+// Set the label text, keeping whatever else we know about the record
+pub fn update_label(
+    tree: &Tree,
+    label_type: &str,
+    reference: &str,
+    text: &str,
+) -> Result<(), bdk::Error> {
+    let mut label = get_label(tree, label_type, reference)?.unwrap_or(Label {
+        label_type: label_type.to_string(),
+        reference: reference.to_string(),
+        label: None,
+        origin: None,
+        spendable: None,
+    });
+
+    label.label = match text {
+        "" => None,
+        _ => Some(text.to_string()),
+    };
+
+    set_label(tree, &label)
+}
+
+pub fn list_labels(tree: &Tree) -> Result<Vec<Label>, bdk::Error> {
+    let mut labels = vec![];
+
+    for entry in tree.scan_prefix(LABEL_PREFIX) {
+        let (_, value) = entry?;
+        labels.push(serde_json::from_slice(&value)?);
+    }
+
+    Ok(labels)
+}
+
+// Import BIP-329 JSON lines, returning how many records were stored
+pub fn import_labels(tree: &Tree, jsonl: &str) -> Result<u32, bdk::Error> {
+    let mut imported = 0;
+
+    for line in jsonl.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let label: Label = serde_json::from_str(line)?;
+
+        // BIP-329 asks importers to skip record types they don't know
+        if !is_known_type(&label.label_type) {
+            continue;
+        }
+
+        set_label(tree, &label)?;
+        imported += 1;
+    }
+
+    Ok(imported)
+}
+
+pub fn export_labels(tree: &Tree) -> Result<String, bdk::Error> {
+    let mut lines = vec![];
+
+    for label in list_labels(tree)? {
+        lines.push(serde_json::to_string(&label)?);
+    }
+
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+
+    #[test]
+    fn test_import_export_labels() {
+        let tree = util::get_test_tree();
+
+        let jsonl = r#"{"type":"tx","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd","label":"Transaction","origin":"wpkh([d34db33f/84'/0'/0'])"}
+{"type":"output","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0","label":"Output","spendable":false}
+{"type":"unknown","ref":"something","label":"Skipped"}"#;
+
+        assert_eq!(import_labels(&tree, jsonl).unwrap(), 2);
+
+        let exported = export_labels(&tree).unwrap();
+        assert_eq!(exported.lines().count(), 2);
+        assert_eq!(import_labels(&tree, &exported).unwrap(), 2);
+
+        let output = get_label(
+            &tree,
+            "output",
+            "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(output.spendable, Some(false));
+    }
+
+    #[test]
+    fn test_update_label() {
+        let tree = util::get_test_tree();
+
+        update_label(&tree, "addr", "bc1qexample", "Savings").unwrap();
+        assert_eq!(
+            get_label(&tree, "addr", "bc1qexample")
+                .unwrap()
+                .unwrap()
+                .label,
+            Some("Savings".to_string())
+        );
+
+        update_label(&tree, "addr", "bc1qexample", "").unwrap();
+        assert_eq!(get_label(&tree, "addr", "bc1qexample").unwrap(), None);
+    }
+}
@@ -14,11 +14,12 @@ use std::convert::TryFrom;
 use std::error::Error;
 
 use bdk::bitcoin::{Address, Network, OutPoint, Txid};
+use bdk::blockchain::ElectrumBlockchain;
 use bdk::database::{ConfigurableDatabase, MemoryDatabase};
-use bdk::electrum_client::{ElectrumApi, Socks5Config};
+use bdk::electrum_client::ElectrumApi;
 use bdk::sled::Tree;
 use bdk::wallet::AddressIndex;
-use bdk::{electrum_client, miniscript, Balance, FeeRate, SignOptions, SyncOptions};
+use bdk::{electrum_client, miniscript, Balance, FeeRate, KeychainKind, SignOptions, SyncOptions};
 use std::str::FromStr;
 
 use bdk::bitcoin::consensus::encode::deserialize;
@@ -31,7 +32,6 @@ use crate::miniscript::Segwitv0;
 use bdk::bitcoin::secp256k1::Secp256k1;
 use bdk::bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, KeySource};
 use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
-use bdk::keys::bip39::MnemonicWithPassphrase;
 use bdk::keys::DescriptorKey::Secret;
 use bdk::keys::{
     DerivableKey, DescriptorKey, ExtendedKey, GeneratableDefaultOptions, GeneratedKey,
@@ -39,8 +39,28 @@ use bdk::keys::{
 use bdk::miniscript::psbt::PsbtExt;
 use bdk::psbt::PsbtUtils;
 use bip39::{Language, Mnemonic};
-use std::sync::Mutex;
-
+use electrum::ElectrumSync;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tasks::Task;
+
+mod addresses;
+mod backend;
+mod coin_selection;
+mod config;
+mod cpfp;
+mod descriptor;
+mod discovery;
+mod electrum;
+mod export;
+mod failover;
+mod import;
+mod labels;
+mod message;
+mod progress;
+mod rpc;
+mod tasks;
+mod tls;
 mod util;
 
 #[repr(C)]
@@ -52,6 +72,16 @@ pub enum NetworkType {
     Regtest,
 }
 
+// What a descriptor's keys say about its network. Testnet, signet and regtest keys share
+// version bytes so only the wallet knows which of them it's on
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyNetwork {
+    Unknown,
+    Mainnet,
+    TestNetworks,
+}
+
 impl Into<Network> for NetworkType {
     fn into(self) -> Network {
         match self {
@@ -74,6 +104,144 @@ impl Into<String> for NetworkType {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScriptType {
+    P2wpkh,
+    P2tr,
+    P2pkh,
+    P2shP2wpkh,
+}
+
+impl ScriptType {
+    // Wrap a descriptor key in the top-level descriptor for this script type
+    pub fn wrap(&self, descriptor_key: &str) -> String {
+        match self {
+            ScriptType::P2wpkh => format!("wpkh({descriptor_key})"),
+            ScriptType::P2tr => format!("tr({descriptor_key})"),
+            ScriptType::P2pkh => format!("pkh({descriptor_key})"),
+            ScriptType::P2shP2wpkh => format!("sh(wpkh({descriptor_key}))"),
+        }
+    }
+
+    // BIP44/49/84/86 purpose level
+    pub fn purpose(&self) -> u32 {
+        match self {
+            ScriptType::P2wpkh => 84,
+            ScriptType::P2tr => 86,
+            ScriptType::P2pkh => 44,
+            ScriptType::P2shP2wpkh => 49,
+        }
+    }
+}
+
+impl Into<String> for ScriptType {
+    fn into(self) -> String {
+        match self {
+            ScriptType::P2wpkh => "p2wpkh".to_string(),
+            ScriptType::P2tr => "p2tr".to_string(),
+            ScriptType::P2pkh => "p2pkh".to_string(),
+            ScriptType::P2shP2wpkh => "p2sh-p2wpkh".to_string(),
+        }
+    }
+}
+
+// BIP-329 record types
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum LabelType {
+    Tx,
+    Addr,
+    Pubkey,
+    Input,
+    Output,
+    Xpub,
+}
+
+impl Into<String> for LabelType {
+    fn into(self) -> String {
+        match self {
+            LabelType::Tx => "tx".to_string(),
+            LabelType::Addr => "addr".to_string(),
+            LabelType::Pubkey => "pubkey".to_string(),
+            LabelType::Input => "input".to_string(),
+            LabelType::Output => "output".to_string(),
+            LabelType::Xpub => "xpub".to_string(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum ExportFormat {
+    Descriptor,
+    Electrum,
+    Specter,
+    Bsms,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum BackendType {
+    Electrum,
+    Esplora,
+    Rpc,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoinSelection {
+    BranchAndBound,
+    LargestFirst,
+    OldestFirst,
+    // Never mix coins with different output labels. The tags are the BIP-329 output labels
+    // kept in the wallet tree (see labels.rs), so the app's coin tags need to be mirrored
+    // there. Send max only works with coin control, as draining the wallet would mix them
+    Privacy,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PinningMode {
+    Off,
+    TrustOnFirstUse,
+    // Only connect to servers pinned beforehand
+    Strict,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum MessageFormat {
+    Legacy,
+    Bip322Simple,
+    Bip322Full,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum Keychain {
+    External,
+    Internal,
+}
+
+impl Into<KeychainKind> for Keychain {
+    fn into(self) -> KeychainKind {
+        match self {
+            Keychain::External => KeychainKind::External,
+            Keychain::Internal => KeychainKind::Internal,
+        }
+    }
+}
+
+impl From<KeychainKind> for Keychain {
+    fn from(keychain: KeychainKind) -> Self {
+        match keychain {
+            KeychainKind::External => Keychain::External,
+            KeychainKind::Internal => Keychain::Internal,
+        }
+    }
+}
+
 #[repr(C)]
 pub struct Transaction {
     txid: *const c_char,
@@ -144,6 +312,70 @@ pub struct Psbt {
     base64: *const c_char,
     txid: *const c_char,
     raw_tx: *const c_char,
+    outputs_len: u32,
+    outputs: *const PsbtOutput,
+    // Empty unless coins were selected for this PSBT
+    coin_selection: *const c_char,
+    inputs_len: u32,
+    inputs: *const PsbtInput,
+    // Every input has its final script, so it can be extracted and broadcast
+    finalized: bool,
+    // Only for fee bumps, set when the change couldn't absorb the higher fee and more coins
+    // were spent than the original transaction did
+    inputs_added: bool,
+}
+
+// What's returned when there's no PSBT to give back, the reason is in LAST_ERROR
+impl Psbt {
+    fn error() -> Self {
+        Psbt {
+            sent: 0,
+            received: 0,
+            fee: 0,
+            base64: ptr::null(),
+            txid: ptr::null(),
+            raw_tx: ptr::null(),
+            outputs_len: 0,
+            outputs: ptr::null(),
+            coin_selection: ptr::null(),
+            inputs_len: 0,
+            inputs: ptr::null(),
+            finalized: false,
+            inputs_added: false,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct PsbtInput {
+    txid: *const c_char,
+    vout: u32,
+    amount: u64,
+    // Why coin selection picked it, empty if it didn't run
+    reason: *const c_char,
+}
+
+#[repr(C)]
+pub struct PsbtOutput {
+    // Empty for scripts without an address form
+    address: *const c_char,
+    amount: u64,
+    is_mine: bool,
+    is_change: bool,
+}
+
+#[repr(C)]
+pub struct Recipient {
+    address: *const c_char,
+    // Ignored for the send max output
+    amount: u64,
+    send_max: bool,
+}
+
+#[repr(C)]
+pub struct RecipientList {
+    recipients_len: u32,
+    recipients: *const Recipient,
 }
 
 #[repr(C)]
@@ -166,6 +398,134 @@ pub struct Wallet {
     bkd_wallet_ptr: *mut usize,
 }
 
+#[repr(C)]
+pub struct ImportedAccount {
+    label: *const c_char,
+    external_descriptor: *const c_char,
+    internal_descriptor: *const c_char,
+    key_origins_len: u8,
+    key_origins: *const *const c_char,
+}
+
+#[repr(C)]
+pub struct ImportedAccountList {
+    accounts_len: u8,
+    accounts: *const ImportedAccount,
+}
+
+#[repr(C)]
+pub struct DescriptorInfo {
+    descriptor: *const c_char,
+    public_descriptor: *const c_char,
+    checksum: *const c_char,
+    network: KeyNetwork,
+    script_type: *const c_char,
+    key_origins_len: u8,
+    key_origins: *const *const c_char,
+    ranged: bool,
+    private: bool,
+}
+
+#[repr(C)]
+pub struct XpubDescriptors {
+    xpub: *const c_char,
+    script_type: ScriptType,
+    derivation: *const c_char,
+    external_descriptor: *const c_char,
+    internal_descriptor: *const c_char,
+}
+
+#[repr(C)]
+pub struct AddressInfo {
+    address: *const c_char,
+    keychain: Keychain,
+    index: u32,
+    path: *const c_char,
+    used: bool,
+    received: u64,
+    label: *const c_char,
+}
+
+#[repr(C)]
+pub struct AddressList {
+    addresses_len: u32,
+    addresses: *const AddressInfo,
+}
+
+#[repr(C)]
+pub struct AddressOwner {
+    keychain: Keychain,
+    index: u32,
+    path: *const c_char,
+    descriptor: *const c_char,
+}
+
+#[repr(C)]
+pub struct ServedFeeRate {
+    fee_rate: f64,
+    server: *const c_char,
+}
+
+#[repr(C)]
+pub struct ServedTxid {
+    txid: *const c_char,
+    server: *const c_char,
+}
+
+#[repr(C)]
+pub struct ElectrumServerHealth {
+    server: *const c_char,
+    successes: u32,
+    errors: u32,
+    consecutive_errors: u32,
+    latency_ms: u32,
+    last_error: *const c_char,
+}
+
+#[repr(C)]
+pub struct ElectrumServerHealthList {
+    servers_len: u32,
+    servers: *const ElectrumServerHealth,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SyncConfig {
+    // Unused addresses in a row before a keychain is considered done. A plain Electrum sync
+    // goes through BDK, which keeps its own
+    stop_gap: u32,
+    retry: u8,
+    // Seconds, 0 for the default which is longer over Tor
+    timeout: u8,
+    // Scripts per Electrum batch request when we look up history ourselves
+    batch_size: u32,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            stop_gap: 50,
+            retry: 0,
+            timeout: 0,
+            batch_size: 50,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct DiscoveredAccount {
+    script_type: ScriptType,
+    account: u32,
+    path: *const c_char,
+    tx_count: u32,
+}
+
+#[repr(C)]
+pub struct DiscoveredAccountList {
+    accounts_len: u32,
+    accounts: *const DiscoveredAccount,
+}
+
 thread_local! {
     static LAST_ERROR: RefCell<Option<Box<dyn Error>>> = RefCell::new(None);
 }
@@ -241,17 +601,21 @@ pub unsafe extern "C" fn wallet_init(
     )
 }
 
+// Calls still running hold a reference of their own, the wallet goes once the last is done
 #[no_mangle]
 pub unsafe extern "C" fn wallet_drop(wallet: *mut Mutex<bdk::Wallet<Tree>>) {
-    drop(wallet);
+    if !wallet.is_null() {
+        drop(Arc::from_raw(wallet));
+    }
 }
 
-// Get wallet public/private pair from seed words, path and network
+// Get wallet public/private pair from seed words, path, script type and network
 #[no_mangle]
 pub unsafe extern "C" fn wallet_derive(
     seed_words: *const c_char,
     passphrase: *const c_char,
     path: *const c_char,
+    script_type: ScriptType,
     network: NetworkType,
     init_wallet: bool,
     data_dir: *const c_char,
@@ -269,60 +633,40 @@ pub unsafe extern "C" fn wallet_derive(
 
     let seed_words = unwrap_or_return!(CStr::from_ptr(seed_words).to_str(), error_return);
     let path = unwrap_or_return!(CStr::from_ptr(path).to_str(), error_return);
-
-    // Parse seed words
-    let mnemonic_words = unwrap_or_return!(Mnemonic::parse(seed_words), error_return);
-
-    let mnemonic: MnemonicWithPassphrase = {
+    let passphrase = {
         if !passphrase.is_null() {
-            let passphrase = unwrap_or_return!(CStr::from_ptr(passphrase).to_str(), error_return);
-            (mnemonic_words, Some(passphrase.parse().unwrap()))
+            Some(unwrap_or_return!(
+                CStr::from_ptr(passphrase).to_str(),
+                error_return
+            ))
         } else {
-            (mnemonic_words, None)
+            None
         }
     };
 
-    let xkey: ExtendedKey = unwrap_or_return!(mnemonic.into_extended_key(), error_return);
-
-    let derivation_path = unwrap_or_return!(DerivationPath::from_str(path), error_return);
     let secp = Secp256k1::new();
+    let xprv = unwrap_or_return!(
+        util::get_master_xprv(seed_words, passphrase, network),
+        error_return
+    );
 
-    // Derive
-    let xprv = match xkey.into_xprv(network.clone().into()) {
-        None => {
-            return error_return;
-        }
-        Some(p) => p,
-    };
-
-    let derived_xprv = &xprv.derive_priv(&secp, &derivation_path).unwrap();
-    let origin: KeySource = (xprv.fingerprint(&secp), derivation_path);
-
-    // Get descriptors
-    let derived_xprv_desc_key: DescriptorKey<Segwitv0> = derived_xprv
-        .into_descriptor_key(Some(origin), DerivationPath::default())
-        .unwrap();
-
-    let (descriptor_prv, descriptor_pub) = match derived_xprv_desc_key {
-        DescriptorKey::Public(_, _, _) => {
-            return error_return;
-        }
-        Secret(desc_seckey, _, _) => (desc_seckey.to_string(), {
-            let desc_pubkey = desc_seckey.to_public(&secp).unwrap();
-            desc_pubkey.to_string()
-        }),
-    };
+    let derivation_path = unwrap_or_return!(DerivationPath::from_str(path), error_return);
+    let account = util::get_account_index(&derivation_path);
 
-    let external_pub_descriptor = format!("wpkh({descriptor_pub})").replace("/*", "/0/*");
-    let internal_pub_descriptor = external_pub_descriptor.replace("/0/*", "/1/*");
+    // Derive and get descriptors
+    let descriptors = unwrap_or_return!(
+        util::get_descriptors(&secp, &xprv, &derivation_path, script_type),
+        error_return
+    );
 
-    let external_prv_descriptor = format!("wpkh({descriptor_prv})").replace("/*", "/0/*");
-    let internal_prv_descriptor = external_prv_descriptor.replace("/0/*", "/1/*");
+    let external_pub_descriptor = descriptors.external_pub;
+    let internal_pub_descriptor = descriptors.internal_pub;
+    let external_prv_descriptor = descriptors.external_prv;
+    let internal_prv_descriptor = descriptors.internal_prv;
 
-    let xfp = &descriptor_prv[1..9];
-    let network_str: String = network.into();
+    let xfp = xprv.fingerprint(&secp).to_string();
 
-    let name = format!("{xfp}-{network_str}");
+    let name = util::get_wallet_name(&xfp, network, script_type, account);
 
     let data_dir = unwrap_or_return!(CStr::from_ptr(data_dir).to_str(), error_return);
     let wallet_dir = format!("{data_dir}{name}");
@@ -360,56 +704,434 @@ pub unsafe extern "C" fn wallet_derive(
     }
 }
 
+// Get a watch-only wsh(sortedmulti) wallet from key-origin xpubs, e.g. [fingerprint/48'/0'/0'/2']xpub...
 #[no_mangle]
-pub unsafe extern "C" fn wallet_get_address(
-    wallet: *mut Mutex<bdk::Wallet<Tree>>,
-) -> *const c_char {
-    let wallet = util::get_wallet_mutex(wallet).lock().unwrap();
+pub unsafe extern "C" fn wallet_derive_multisig(
+    threshold: u8,
+    xpubs_len: u8,
+    xpubs: *const *const c_char,
+    network: NetworkType,
+    init_wallet: bool,
+    data_dir: *const c_char,
+) -> Wallet {
+    let error_return = Wallet {
+        name: ptr::null(),
+        network,
+        external_pub_descriptor: ptr::null(),
+        internal_pub_descriptor: ptr::null(),
+        external_prv_descriptor: ptr::null(),
+        internal_prv_descriptor: ptr::null(),
+        bkd_wallet_ptr: null_mut(),
+    };
 
-    let address = wallet
-        .get_address(AddressIndex::New)
-        .unwrap()
-        .address
-        .to_string();
+    let xpubs = unwrap_or_return!(util::extract_string_list(xpubs_len, xpubs), error_return);
 
-    // SFT-1580: unreliable fsync on mobile platforms occasionally causes address reuse
-    let _ = wallet.database().flush();
+    let (external_pub_descriptor, internal_pub_descriptor) = unwrap_or_return!(
+        util::get_multisig_descriptors(threshold as usize, &xpubs),
+        error_return
+    );
 
-    CString::new(address).unwrap().into_raw()
+    let checksum = unwrap_or_return!(
+        bdk::descriptor::calc_checksum(&external_pub_descriptor),
+        error_return
+    );
+    let network_str: String = network.into();
+    let name = format!("{checksum}-{network_str}-{threshold}of{}", xpubs.len());
+
+    let data_dir = unwrap_or_return!(CStr::from_ptr(data_dir).to_str(), error_return);
+    let wallet_dir = format!("{data_dir}{name}");
+
+    let ptr = {
+        if !init_wallet {
+            null_mut()
+        } else {
+            let ptr = init(
+                network,
+                &*name,
+                &*external_pub_descriptor,
+                &*internal_pub_descriptor,
+                &*wallet_dir,
+            );
+
+            if ptr.is_null() {
+                return error_return;
+            }
+
+            ptr
+        }
+    };
+
+    Wallet {
+        name: CString::new(name).unwrap().into_raw(),
+        network,
+        external_pub_descriptor: CString::new(external_pub_descriptor).unwrap().into_raw(),
+        internal_pub_descriptor: CString::new(internal_pub_descriptor).unwrap().into_raw(),
+        external_prv_descriptor: ptr::null(),
+        internal_prv_descriptor: ptr::null(),
+        bkd_wallet_ptr: ptr as *mut usize,
+    }
 }
 
+// Get descriptors from a Passport/Coldcard generic JSON, Sparrow/Electrum wallet file,
+// Specter JSON or Coldcard-style multisig setup file
 #[no_mangle]
-pub unsafe extern "C" fn wallet_get_change_address(
-    wallet: *mut Mutex<bdk::Wallet<Tree>>,
-) -> *const c_char {
-    let wallet = util::get_wallet_mutex(wallet).lock().unwrap();
+pub unsafe extern "C" fn wallet_parse_account_export(
+    contents: *const c_char,
+    network: NetworkType,
+) -> ImportedAccountList {
+    let error_return = ImportedAccountList {
+        accounts_len: 0,
+        accounts: ptr::null(),
+    };
 
-    let address = wallet
-        .get_internal_address(AddressIndex::New)
-        .unwrap()
-        .address
-        .to_string();
+    let contents = unwrap_or_return!(CStr::from_ptr(contents).to_str(), error_return);
+    let accounts = unwrap_or_return!(
+        import::parse_account_export(contents, network.into()),
+        error_return
+    );
 
-    // SFT-1580: unreliable fsync on mobile platforms occasionally causes address reuse
-    let _ = wallet.database().flush();
+    let accounts: Vec<_> = accounts
+        .into_iter()
+        .map(|account| {
+            let key_origins: Vec<_> = account
+                .key_origins
+                .into_iter()
+                .map(|o| CString::new(o).unwrap().into_raw() as *const c_char)
+                .collect();
+
+            let key_origins_len = key_origins.len() as u8;
+            let key_origins_ptr = key_origins.as_ptr();
+            std::mem::forget(key_origins);
+
+            ImportedAccount {
+                label: CString::new(account.label).unwrap().into_raw(),
+                external_descriptor: CString::new(account.external_descriptor)
+                    .unwrap()
+                    .into_raw(),
+                internal_descriptor: CString::new(account.internal_descriptor)
+                    .unwrap()
+                    .into_raw(),
+                key_origins_len,
+                key_origins: key_origins_ptr,
+            }
+        })
+        .collect();
 
-    CString::new(address).unwrap().into_raw()
+    let accounts_len = accounts.len() as u8;
+    let accounts_box = accounts.into_boxed_slice();
+    let accounts_ptr = Box::into_raw(accounts_box);
+
+    ImportedAccountList {
+        accounts_len,
+        accounts: accounts_ptr as _,
+    }
 }
 
+// Parse a descriptor without touching any wallet and report what's in it
 #[no_mangle]
-pub unsafe extern "C" fn wallet_sync(
-    wallet: *mut Mutex<bdk::Wallet<Tree>>,
-    electrum_address: *const c_char,
-    tor_port: i32,
-) -> bool {
-    let wallet = unwrap_or_return!(util::get_wallet_mutex(wallet).lock(), false);
+pub unsafe extern "C" fn wallet_get_descriptor_info(descriptor: *const c_char) -> DescriptorInfo {
+    let error_return = DescriptorInfo {
+        descriptor: ptr::null(),
+        public_descriptor: ptr::null(),
+        checksum: ptr::null(),
+        network: KeyNetwork::Unknown,
+        script_type: ptr::null(),
+        key_origins_len: 0,
+        key_origins: ptr::null(),
+        ranged: false,
+        private: false,
+    };
 
-    let electrum_address = unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), false);
+    let descriptor = unwrap_or_return!(CStr::from_ptr(descriptor).to_str(), error_return);
+    let summary = unwrap_or_return!(descriptor::get_descriptor_summary(descriptor), error_return);
 
-    let blockchain = unwrap_or_return!(
-        util::get_electrum_blockchain(tor_port, electrum_address),
-        false
-    );
+    let key_origins: Vec<_> = summary
+        .key_origins
+        .into_iter()
+        .map(|o| CString::new(o).unwrap().into_raw() as *const c_char)
+        .collect();
+
+    let key_origins_len = key_origins.len() as u8;
+    let key_origins_ptr = key_origins.as_ptr();
+    std::mem::forget(key_origins);
+
+    DescriptorInfo {
+        descriptor: CString::new(summary.descriptor).unwrap().into_raw(),
+        public_descriptor: CString::new(summary.public_descriptor).unwrap().into_raw(),
+        checksum: CString::new(summary.checksum).unwrap().into_raw(),
+        network: summary.network,
+        script_type: CString::new(summary.script_type).unwrap().into_raw(),
+        key_origins_len,
+        key_origins: key_origins_ptr,
+        ranged: summary.ranged,
+        private: summary.private,
+    }
+}
+
+// Get descriptors from an xpub/ypub/zpub/tpub/upub/vpub. The script type is taken from the
+// SLIP-132 prefix and the passed script type only applies to plain xpub/tpub keys.
+// Fingerprint is optional and adds key origin to the descriptors when given.
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_xpub_descriptors(
+    xpub: *const c_char,
+    fingerprint: *const c_char,
+    script_type: ScriptType,
+    network: NetworkType,
+) -> XpubDescriptors {
+    let error_return = XpubDescriptors {
+        xpub: ptr::null(),
+        script_type,
+        derivation: ptr::null(),
+        external_descriptor: ptr::null(),
+        internal_descriptor: ptr::null(),
+    };
+
+    let xpub = unwrap_or_return!(CStr::from_ptr(xpub).to_str(), error_return);
+    let fingerprint = {
+        if !fingerprint.is_null() {
+            Some(unwrap_or_return!(
+                CStr::from_ptr(fingerprint).to_str(),
+                error_return
+            ))
+        } else {
+            None
+        }
+    };
+
+    let xpub_account = unwrap_or_return!(
+        import::get_xpub_account(xpub, fingerprint, script_type, network.into()),
+        error_return
+    );
+
+    XpubDescriptors {
+        xpub: CString::new(xpub_account.xpub.to_string())
+            .unwrap()
+            .into_raw(),
+        script_type: xpub_account.script_type,
+        derivation: CString::new(xpub_account.derivation.to_string())
+            .unwrap()
+            .into_raw(),
+        external_descriptor: CString::new(xpub_account.account.external_descriptor)
+            .unwrap()
+            .into_raw(),
+        internal_descriptor: CString::new(xpub_account.account.internal_descriptor)
+            .unwrap()
+            .into_raw(),
+    }
+}
+
+// Scan accounts of every script type for history so a restored seed finds all its funds
+#[no_mangle]
+pub unsafe extern "C" fn wallet_discover_accounts(
+    seed_words: *const c_char,
+    passphrase: *const c_char,
+    network: NetworkType,
+    electrum_address: *const c_char,
+    tor_port: i32,
+    sync_config: SyncConfig,
+) -> DiscoveredAccountList {
+    let error_return = DiscoveredAccountList {
+        accounts_len: 0,
+        accounts: ptr::null(),
+    };
+
+    let seed_words = unwrap_or_return!(CStr::from_ptr(seed_words).to_str(), error_return);
+    let passphrase = {
+        if !passphrase.is_null() {
+            Some(unwrap_or_return!(
+                CStr::from_ptr(passphrase).to_str(),
+                error_return
+            ))
+        } else {
+            None
+        }
+    };
+
+    let secp = Secp256k1::new();
+    let xprv = unwrap_or_return!(
+        util::get_master_xprv(seed_words, passphrase, network),
+        error_return
+    );
+
+    let electrum_address =
+        unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), error_return);
+    let client = unwrap_or_return!(
+        util::get_electrum_client(tor_port, electrum_address, &sync_config),
+        error_return
+    );
+
+    let accounts = unwrap_or_return!(
+        discovery::discover_accounts(&client, &secp, &xprv, network.into(), &sync_config),
+        error_return
+    );
+
+    let accounts: Vec<_> = accounts
+        .into_iter()
+        .map(|account| DiscoveredAccount {
+            script_type: account.script_type,
+            account: account.account,
+            path: CString::new(account.path).unwrap().into_raw(),
+            tx_count: account.tx_count,
+        })
+        .collect();
+
+    let accounts_len = accounts.len() as u32;
+    let accounts_box = accounts.into_boxed_slice();
+    let accounts_ptr = Box::into_raw(accounts_box);
+
+    DiscoveredAccountList {
+        accounts_len,
+        accounts: accounts_ptr as _,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_address(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let address = unwrap_or_return!(wallet.get_address(AddressIndex::New), error_return)
+        .address
+        .to_string();
+
+    // SFT-1580: unreliable fsync on mobile platforms occasionally causes address reuse
+    let _ = wallet.database().flush();
+
+    CString::new(address).unwrap().into_raw()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_change_address(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let address = unwrap_or_return!(wallet.get_internal_address(AddressIndex::New), error_return)
+        .address
+        .to_string();
+
+    // SFT-1580: unreliable fsync on mobile platforms occasionally causes address reuse
+    let _ = wallet.database().flush();
+
+    CString::new(address).unwrap().into_raw()
+}
+
+// Hand out the receive address nobody has paid to yet instead of deriving a new one each time
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_unused_address(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let address = unwrap_or_return!(wallet.get_address(AddressIndex::LastUnused), error_return)
+        .address
+        .to_string();
+
+    // SFT-1580: unreliable fsync on mobile platforms occasionally causes address reuse
+    let _ = wallet.database().flush();
+
+    CString::new(address).unwrap().into_raw()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_addresses(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    keychain: Keychain,
+    offset: u32,
+    limit: u32,
+) -> AddressList {
+    let error_return = AddressList {
+        addresses_len: 0,
+        addresses: ptr::null(),
+    };
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let entries = unwrap_or_return!(
+        addresses::list_addresses(&wallet, keychain.into(), offset, limit),
+        error_return
+    );
+
+    let addresses: Vec<_> = entries
+        .into_iter()
+        .map(|entry| AddressInfo {
+            address: CString::new(entry.address).unwrap().into_raw(),
+            keychain: entry.keychain.into(),
+            index: entry.index,
+            path: CString::new(entry.path).unwrap().into_raw(),
+            used: entry.used,
+            received: entry.received,
+            label: CString::new(entry.label.unwrap_or_default())
+                .unwrap()
+                .into_raw(),
+        })
+        .collect();
+
+    let addresses_len = addresses.len() as u32;
+    let addresses_box = addresses.into_boxed_slice();
+    let addresses_ptr = Box::into_raw(addresses_box);
+
+    AddressList {
+        addresses_len,
+        addresses: addresses_ptr as _,
+    }
+}
+
+// Which keychain and index an address or scriptPubKey was derived at, e.g. to verify it on Passport
+#[no_mangle]
+pub unsafe extern "C" fn wallet_find_address(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    address_or_script: *const c_char,
+    search_limit: u32,
+) -> AddressOwner {
+    let error_return = AddressOwner {
+        keychain: Keychain::External,
+        index: 0,
+        path: ptr::null(),
+        descriptor: ptr::null(),
+    };
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let address_or_script =
+        unwrap_or_return!(CStr::from_ptr(address_or_script).to_str(), error_return);
+
+    let owner = unwrap_or_return!(
+        addresses::find_address(&wallet, address_or_script, search_limit),
+        error_return
+    );
+
+    AddressOwner {
+        keychain: owner.keychain.into(),
+        index: owner.index,
+        path: CString::new(owner.path).unwrap().into_raw(),
+        descriptor: CString::new(owner.descriptor).unwrap().into_raw(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_sync(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    electrum_address: *const c_char,
+    tor_port: i32,
+) -> bool {
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), false);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), false);
+
+    let electrum_address = unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), false);
+
+    let sync_config = unwrap_or_return!(config::get_sync_config(&wallet.database()), false);
+    let blockchain = unwrap_or_return!(
+        util::get_electrum_blockchain(tor_port, electrum_address, &sync_config),
+        false
+    );
     unwrap_or_return!(
         wallet.sync(&blockchain, SyncOptions { progress: None }),
         false
@@ -419,10 +1141,111 @@ pub unsafe extern "C" fn wallet_sync(
     true
 }
 
+// Same as wallet_sync, posting JSON progress messages to the given Dart isolate port
+#[no_mangle]
+pub unsafe extern "C" fn wallet_sync_with_progress(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    electrum_address: *const c_char,
+    tor_port: i32,
+    isolate_port: i64,
+) -> bool {
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), false);
+    let electrum_address = unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), false);
+
+    let sync_config = {
+        let wallet = unwrap_or_return!(util::lock_wallet(&wallet), false);
+        unwrap_or_return!(config::get_sync_config(&wallet.database()), false)
+    };
+    let client = unwrap_or_return!(
+        util::get_electrum_client(tor_port, electrum_address, &sync_config),
+        false
+    );
+    unwrap_or_return!(
+        progress::sync_electrum_with_progress(&wallet, client, &sync_config, isolate_port),
+        false
+    );
+
+    true
+}
+
+// Light client sync over P2P, so no server learns our addresses. wallet_dir is where the
+// wallet's sled database lives, progress is posted like wallet_sync_with_progress does.
+// Exported either way so the bindings resolve, builds without compact-filters just fail
+#[no_mangle]
+pub unsafe extern "C" fn wallet_sync_compact_filters(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    peers: *const c_char,
+    tor_port: i32,
+    wallet_dir: *const c_char,
+    isolate_port: i64,
+) -> bool {
+    #[cfg(feature = "compact-filters")]
+    {
+        let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), false);
+        let wallet = unwrap_or_return!(util::lock_wallet(&wallet), false);
+        let peers = unwrap_or_return!(CStr::from_ptr(peers).to_str(), false);
+        let wallet_dir = unwrap_or_return!(CStr::from_ptr(wallet_dir).to_str(), false);
+
+        let blockchain = unwrap_or_return!(
+            backend::get_compact_filters_blockchain(peers, tor_port, wallet.network(), wallet_dir),
+            false
+        );
+        unwrap_or_return!(
+            progress::sync_with_progress(&wallet, &blockchain, isolate_port),
+            false
+        );
+
+        true
+    }
+
+    #[cfg(not(feature = "compact-filters"))]
+    {
+        let _ = (wallet, peers, tor_port, wallet_dir, isolate_port);
+        update_last_error(bdk::Error::Generic(
+            "compact filters not enabled".to_string(),
+        ));
+        false
+    }
+}
+
+// Stored in the wallet's tree and picked up by every later sync
+#[no_mangle]
+pub unsafe extern "C" fn wallet_set_sync_config(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    sync_config: SyncConfig,
+) -> bool {
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), false);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), false);
+
+    unwrap_or_return!(
+        config::set_sync_config(&wallet.database(), &sync_config),
+        false
+    );
+
+    let _ = wallet.database().flush();
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_sync_config(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+) -> SyncConfig {
+    let error_return = SyncConfig::default();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let sync_config = config::get_sync_config(&wallet.database());
+
+    unwrap_or_return!(sync_config, error_return)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn wallet_get_balance(wallet: *mut Mutex<bdk::Wallet<Tree>>) -> u64 {
-    let wallet = util::get_wallet_mutex(wallet).lock().unwrap();
-    let balance = wallet.get_balance().unwrap();
+    let error_return = 0;
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let balance = unwrap_or_return!(wallet.get_balance(), error_return);
     get_total_balance(balance)
 }
 
@@ -432,9 +1255,14 @@ fn get_total_balance(balance: Balance) -> u64 {
 
 #[no_mangle]
 pub unsafe extern "C" fn wallet_get_utxos(wallet: *mut Mutex<bdk::Wallet<Tree>>) -> UtxoList {
-    let wallet = util::get_wallet_mutex(wallet).lock().unwrap();
+    let error_return = UtxoList {
+        utxos_len: 0,
+        utxos: ptr::null(),
+    };
 
-    let utxos = wallet.list_unspent().unwrap();
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let utxos = unwrap_or_return!(wallet.list_unspent(), error_return);
     let utxos_len = utxos.len() as u32;
 
     let mut utxos_vec: Vec<Utxo> = vec![];
@@ -465,7 +1293,8 @@ pub unsafe extern "C" fn wallet_get_fee_rate(
     target: u16,
 ) -> f64 {
     let electrum_address = CStr::from_ptr(electrum_address).to_str().unwrap();
-    let client = match util::get_electrum_client(tor_port, electrum_address) {
+    let client = match util::get_electrum_client(tor_port, electrum_address, &SyncConfig::default())
+    {
         Ok(c) => c,
         Err(e) => {
             update_last_error(e);
@@ -492,7 +1321,7 @@ pub unsafe extern "C" fn wallet_get_server_features(
 
     let electrum_address = CStr::from_ptr(electrum_address).to_str().unwrap();
     let client = unwrap_or_return!(
-        util::get_electrum_client(tor_port, electrum_address),
+        util::get_electrum_client(tor_port, electrum_address, &SyncConfig::default()),
         error_return
     );
 
@@ -527,7 +1356,8 @@ pub unsafe extern "C" fn wallet_get_transactions(
         transactions: ptr::null(),
     };
 
-    let wallet = unwrap_or_return!(util::get_wallet_mutex(wallet).lock(), err_ret);
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), err_ret);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), err_ret);
 
     let transactions = unwrap_or_return!(wallet.list_transactions(true), err_ret);
     let transactions_len = transactions.len() as u32;
@@ -650,35 +1480,48 @@ pub unsafe extern "C" fn wallet_get_max_feerate(
 ) -> f64 {
     let error_return = 0.0;
 
-    let wallet = unwrap_or_return!(util::get_wallet_mutex(wallet).lock(), error_return);
-    let address = CStr::from_ptr(send_to).to_str().unwrap();
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let address = unwrap_or_return!(CStr::from_ptr(send_to).to_str(), error_return);
     let send_to = unwrap_or_return!(Address::from_str(address), error_return);
 
     let must_spend = util::extract_utxo_list(must_spend);
     let dont_spend = util::extract_utxo_list(dont_spend);
 
-    let balance = get_total_balance(wallet.get_balance().unwrap());
+    unwrap_or_return!(
+        util::get_max_fee_rate(
+            &[(send_to.script_pubkey(), amount)],
+            &wallet,
+            &must_spend,
+            &dont_spend
+        ),
+        error_return
+    )
+}
 
-    match util::build_tx(
-        amount.clone(),
-        0.0,
-        Some(balance - amount),
-        &wallet,
-        send_to.clone(),
-        &must_spend,
-        &dont_spend,
-    ) {
-        Ok((psbt, _)) => {
-            return match psbt.fee_rate() {
-                None => error_return,
-                Some(r) => r.as_sat_per_vb() as f64,
-            };
-        }
-        Err(e) => {
-            update_last_error(e);
-            return error_return;
-        }
-    }
+// Amount a send max transaction to send_to would pay at this fee rate
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_max_spendable(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    send_to: *const c_char,
+    fee_rate: f64,
+    must_spend: *const UtxoList,
+    dont_spend: *const UtxoList,
+) -> u64 {
+    let error_return = 0;
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let address = unwrap_or_return!(CStr::from_ptr(send_to).to_str(), error_return);
+    let send_to = unwrap_or_return!(Address::from_str(address), error_return);
+
+    let must_spend = util::extract_utxo_list(must_spend);
+    let dont_spend = util::extract_utxo_list(dont_spend);
+
+    unwrap_or_return!(
+        util::get_max_spendable(send_to, fee_rate, &wallet, &must_spend, &dont_spend),
+        error_return
+    )
 }
 
 #[no_mangle]
@@ -687,34 +1530,37 @@ pub unsafe extern "C" fn wallet_create_psbt(
     send_to: *const c_char,
     amount: u64,
     fee_rate: f64,
+    // Send everything spendable to send_to, amount is ignored
+    send_max: bool,
+    coin_selection: CoinSelection,
     must_spend: *const UtxoList,
     dont_spend: *const UtxoList,
 ) -> Psbt {
-    let error_return = Psbt {
-        sent: 0,
-        received: 0,
-        fee: 0,
-        base64: ptr::null(),
-        txid: ptr::null(),
-        raw_tx: ptr::null(),
-    };
-    let wallet = unwrap_or_return!(util::get_wallet_mutex(wallet).lock(), error_return);
-    let address = CStr::from_ptr(send_to).to_str().unwrap();
+    let error_return = Psbt::error();
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let address = unwrap_or_return!(CStr::from_ptr(send_to).to_str(), error_return);
     let send_to = unwrap_or_return!(Address::from_str(address), error_return);
     let must_spend = util::extract_utxo_list(must_spend);
     let dont_spend = util::extract_utxo_list(dont_spend);
 
-    let tx = util::build_tx(
-        amount,
+    let (recipients, drain_to) = match send_max {
+        true => (vec![], Some(send_to.script_pubkey())),
+        false => (vec![(send_to.script_pubkey(), amount)], None),
+    };
+
+    let tx = util::build_tx_with_coin_selection(
+        &recipients,
+        drain_to,
         fee_rate,
         None,
+        coin_selection,
         &wallet,
-        send_to,
         &must_spend,
         &dont_spend,
     );
     match tx {
-        Ok((mut psbt, _)) => {
+        Ok((mut psbt, _, selection)) => {
             let sign_options = SignOptions {
                 trust_witness_utxo: true,
                 ..Default::default()
@@ -726,7 +1572,10 @@ pub unsafe extern "C" fn wallet_create_psbt(
                 Err(_) => false,
             };
 
-            util::psbt_extract_details(&wallet, &psbt)
+            unwrap_or_return!(
+                util::psbt_extract_details_with_selection(&wallet, &psbt, Some(&selection)),
+                error_return
+            )
         }
         Err(e) => {
             update_last_error(e);
@@ -735,42 +1584,188 @@ pub unsafe extern "C" fn wallet_create_psbt(
     }
 }
 
+// Highest fee rate that still pays every recipient in full from the wallet's balance
 #[no_mangle]
-pub unsafe extern "C" fn wallet_decode_psbt(
+pub unsafe extern "C" fn wallet_get_max_feerate_batch(
     wallet: *mut Mutex<bdk::Wallet<Tree>>,
-    psbt: *const c_char,
-) -> Psbt {
-    let error_return = Psbt {
-        sent: 0,
-        received: 0,
-        fee: 0,
-        base64: ptr::null(),
-        txid: ptr::null(),
-        raw_tx: ptr::null(),
-    };
+    recipients: *const RecipientList,
+    must_spend: *const UtxoList,
+    dont_spend: *const UtxoList,
+) -> f64 {
+    let error_return = 0.0;
 
-    let wallet = unwrap_or_return!(util::get_wallet_mutex(wallet).lock(), error_return);
-    let data = unwrap_or_return!(
-        base64::decode(CStr::from_ptr(psbt).to_str().unwrap()),
-        error_return
-    );
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let (recipients, send_max) =
+        unwrap_or_return!(util::extract_recipient_list(recipients), error_return);
 
-    match deserialize::<PartiallySignedTransaction>(&data) {
-        Ok(psbt) => {
-            let secp = Secp256k1::verification_only();
-            let finalized_psbt = PsbtExt::finalize(psbt, &secp).unwrap();
-            util::psbt_extract_details(&wallet, &finalized_psbt)
-        }
-        Err(e) => {
-            update_last_error(e);
-            error_return
-        }
+    if send_max.is_some() {
+        update_last_error(bdk::Error::Generic(
+            "A send max output has no max fee rate".to_string(),
+        ));
+        return error_return;
     }
-}
 
-#[no_mangle]
-pub unsafe extern "C" fn wallet_decode_raw_tx(
-    raw_tx: *const c_char,
+    let must_spend = util::extract_utxo_list(must_spend);
+    let dont_spend = util::extract_utxo_list(dont_spend);
+
+    unwrap_or_return!(
+        util::get_max_fee_rate(&recipients, &wallet, &must_spend, &dont_spend),
+        error_return
+    )
+}
+
+// Pay several recipients in one transaction, one of them can take whatever is left
+#[no_mangle]
+pub unsafe extern "C" fn wallet_create_batch_psbt(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    recipients: *const RecipientList,
+    fee_rate: f64,
+    coin_selection: CoinSelection,
+    must_spend: *const UtxoList,
+    dont_spend: *const UtxoList,
+) -> Psbt {
+    let error_return = Psbt::error();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let (recipients, send_max) =
+        unwrap_or_return!(util::extract_recipient_list(recipients), error_return);
+    let must_spend = util::extract_utxo_list(must_spend);
+    let dont_spend = util::extract_utxo_list(dont_spend);
+
+    let (mut psbt, _, selection) = unwrap_or_return!(
+        util::build_tx_with_coin_selection(
+            &recipients,
+            send_max,
+            fee_rate,
+            None,
+            coin_selection,
+            &wallet,
+            &must_spend,
+            &dont_spend,
+        ),
+        error_return
+    );
+
+    let sign_options = SignOptions {
+        trust_witness_utxo: true,
+        ..Default::default()
+    };
+
+    // Always try signing
+    let _ = wallet.sign(&mut psbt, sign_options);
+
+    unwrap_or_return!(
+        util::psbt_extract_details_with_selection(&wallet, &psbt, Some(&selection)),
+        error_return
+    )
+}
+
+// Replacement PSBT for a stuck outgoing transaction, fee_rate in BTC per kB like everywhere else
+#[no_mangle]
+pub unsafe extern "C" fn wallet_bump_fee(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    txid: *const c_char,
+    fee_rate: f64,
+    must_spend: *const UtxoList,
+    dont_spend: *const UtxoList,
+) -> Psbt {
+    let error_return = Psbt::error();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let txid = unwrap_or_return!(CStr::from_ptr(txid).to_str(), error_return);
+    let txid = unwrap_or_return!(Txid::from_str(txid), error_return);
+    let must_spend = util::extract_utxo_list(must_spend);
+    let dont_spend = util::extract_utxo_list(dont_spend);
+
+    let (mut psbt, _, inputs_added) = unwrap_or_return!(
+        util::build_fee_bump(txid, fee_rate, &wallet, &must_spend, &dont_spend),
+        error_return
+    );
+
+    let sign_options = SignOptions {
+        trust_witness_utxo: true,
+        ..Default::default()
+    };
+
+    // Always try signing
+    let _ = wallet.sign(&mut psbt, sign_options);
+
+    Psbt {
+        inputs_added,
+        ..unwrap_or_return!(util::psbt_extract_details(&wallet, &psbt), error_return)
+    }
+}
+
+// Child paying for an unconfirmed incoming transaction, fee_rate is the target for both together
+#[no_mangle]
+pub unsafe extern "C" fn wallet_create_cpfp_psbt(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    txid: *const c_char,
+    fee_rate: f64,
+) -> Psbt {
+    let error_return = Psbt::error();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let txid = unwrap_or_return!(CStr::from_ptr(txid).to_str(), error_return);
+    let txid = unwrap_or_return!(Txid::from_str(txid), error_return);
+
+    let (mut psbt, _) = unwrap_or_return!(
+        cpfp::build_cpfp(
+            &wallet,
+            txid,
+            FeeRate::from_sat_per_vb((fee_rate * 100000.0) as f32)
+        ),
+        error_return
+    );
+
+    let sign_options = SignOptions {
+        trust_witness_utxo: true,
+        ..Default::default()
+    };
+
+    // Always try signing
+    let _ = wallet.sign(&mut psbt, sign_options);
+
+    unwrap_or_return!(util::psbt_extract_details(&wallet, &psbt), error_return)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_decode_psbt(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    psbt: *const c_char,
+) -> Psbt {
+    let error_return = Psbt::error();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let data = unwrap_or_return!(
+        base64::decode(CStr::from_ptr(psbt).to_str().unwrap()),
+        error_return
+    );
+
+    match deserialize::<PartiallySignedTransaction>(&data) {
+        Ok(psbt) => {
+            let secp = Secp256k1::verification_only();
+            let finalized_psbt = PsbtExt::finalize(psbt, &secp).unwrap();
+            unwrap_or_return!(
+                util::psbt_extract_details(&wallet, &finalized_psbt),
+                error_return
+            )
+        }
+        Err(e) => {
+            update_last_error(e);
+            error_return
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_decode_raw_tx(
+    raw_tx: *const c_char,
     network: NetworkType,
 ) -> RawTransaction {
     let error_return = RawTransaction {
@@ -844,7 +1839,7 @@ pub unsafe extern "C" fn wallet_broadcast_tx(
     let electrum_address =
         unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), error_return);
     let client = unwrap_or_return!(
-        util::get_electrum_client(tor_port, electrum_address),
+        util::get_electrum_client(tor_port, electrum_address, &SyncConfig::default()),
         error_return
     );
 
@@ -862,7 +1857,8 @@ pub unsafe extern "C" fn wallet_validate_address(
     wallet: *mut Mutex<bdk::Wallet<Tree>>,
     address: *const c_char,
 ) -> bool {
-    let wallet = unwrap_or_return!(util::get_wallet_mutex(wallet).lock(), false);
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), false);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), false);
 
     match Address::from_str(CStr::from_ptr(address).to_str().unwrap()) {
         Ok(a) => wallet.network() == a.network, // Only valid if it's on same network
@@ -877,14 +1873,7 @@ pub unsafe extern "C" fn wallet_sign_offline(
     internal_descriptor: *const c_char,
     network: NetworkType,
 ) -> Psbt {
-    let error_return = Psbt {
-        sent: 0,
-        received: 0,
-        fee: 0,
-        base64: ptr::null(),
-        txid: ptr::null(),
-        raw_tx: ptr::null(),
-    };
+    let error_return = Psbt::error();
 
     let external_descriptor = CStr::from_ptr(external_descriptor).to_str().unwrap();
     let internal_descriptor = CStr::from_ptr(internal_descriptor).to_str().unwrap();
@@ -901,7 +1890,7 @@ pub unsafe extern "C" fn wallet_sign_offline(
     let mut psbt = deserialize::<PartiallySignedTransaction>(&data).unwrap();
 
     match wallet.sign(&mut psbt, SignOptions::default()) {
-        Ok(_) => util::psbt_extract_details(&wallet, &psbt),
+        Ok(_) => unwrap_or_return!(util::psbt_extract_details(&wallet, &psbt), error_return),
         Err(e) => {
             update_last_error(e);
             error_return
@@ -914,22 +1903,19 @@ pub unsafe extern "C" fn wallet_sign_psbt(
     wallet: *mut Mutex<bdk::Wallet<Tree>>,
     psbt: *const c_char,
 ) -> Psbt {
-    let error_return = Psbt {
-        sent: 0,
-        received: 0,
-        fee: 0,
-        base64: ptr::null(),
-        txid: ptr::null(),
-        raw_tx: ptr::null(),
-    };
-
-    let wallet = util::get_wallet_mutex(wallet).lock().unwrap();
-
-    let data = base64::decode(CStr::from_ptr(psbt).to_str().unwrap()).unwrap();
-    let mut psbt = deserialize::<PartiallySignedTransaction>(&data).unwrap();
+    let error_return = Psbt::error();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let psbt = unwrap_or_return!(CStr::from_ptr(psbt).to_str(), error_return);
+    let data = unwrap_or_return!(base64::decode(psbt), error_return);
+    let mut psbt = unwrap_or_return!(
+        deserialize::<PartiallySignedTransaction>(&data),
+        error_return
+    );
 
     match wallet.sign(&mut psbt, SignOptions::default()) {
-        Ok(_) => util::psbt_extract_details(&wallet, &psbt),
+        Ok(_) => unwrap_or_return!(util::psbt_extract_details(&wallet, &psbt), error_return),
         Err(e) => {
             update_last_error(e);
             error_return
@@ -937,6 +1923,150 @@ pub unsafe extern "C" fn wallet_sign_psbt(
     }
 }
 
+// Merge PSBTs signed by several cosigners and finalize if enough signatures are present
+#[no_mangle]
+pub unsafe extern "C" fn wallet_combine_psbts(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    psbts_len: u8,
+    psbts: *const *const c_char,
+) -> Psbt {
+    let error_return = Psbt::error();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let psbts = unwrap_or_return!(util::extract_string_list(psbts_len, psbts), error_return);
+
+    let mut combined: Option<PartiallySignedTransaction> = None;
+
+    for psbt in psbts {
+        let data = unwrap_or_return!(base64::decode(psbt), error_return);
+        let psbt = unwrap_or_return!(
+            deserialize::<PartiallySignedTransaction>(&data),
+            error_return
+        );
+
+        combined = match combined {
+            None => Some(psbt),
+            Some(mut c) => {
+                unwrap_or_return!(c.combine(psbt), error_return);
+                Some(c)
+            }
+        };
+    }
+
+    let mut psbt = match combined {
+        None => {
+            update_last_error(bdk::Error::Generic("No PSBTs to combine".to_string()));
+            return error_return;
+        }
+        Some(p) => p,
+    };
+
+    let sign_options = SignOptions {
+        trust_witness_utxo: true,
+        ..Default::default()
+    };
+
+    // Not finalizing just means we're still waiting on signatures, finalized tells them apart
+    unwrap_or_return!(wallet.finalize_psbt(&mut psbt, sign_options), error_return);
+
+    unwrap_or_return!(util::psbt_extract_details(&wallet, &psbt), error_return)
+}
+
+// Label a transaction, address, output etc. An empty label removes it
+#[no_mangle]
+pub unsafe extern "C" fn wallet_set_label(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    label_type: LabelType,
+    reference: *const c_char,
+    label: *const c_char,
+) -> bool {
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), false);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), false);
+    let reference = unwrap_or_return!(CStr::from_ptr(reference).to_str(), false);
+    let label = unwrap_or_return!(CStr::from_ptr(label).to_str(), false);
+    let label_type: String = label_type.into();
+
+    unwrap_or_return!(
+        labels::update_label(&wallet.database(), &label_type, reference, label),
+        false
+    );
+
+    let _ = wallet.database().flush();
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_label(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    label_type: LabelType,
+    reference: *const c_char,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let reference = unwrap_or_return!(CStr::from_ptr(reference).to_str(), error_return);
+    let label_type: String = label_type.into();
+
+    let label = unwrap_or_return!(
+        labels::get_label(&wallet.database(), &label_type, reference),
+        error_return
+    );
+
+    match label.and_then(|l| l.label) {
+        None => error_return,
+        Some(l) => unwrap_or_return!(CString::new(l), error_return).into_raw(),
+    }
+}
+
+// Import BIP-329 JSON lines, returns the number of labels imported or -1 on error
+#[no_mangle]
+pub unsafe extern "C" fn wallet_import_labels(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    jsonl: *const c_char,
+) -> i32 {
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), -1);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), -1);
+    let jsonl = unwrap_or_return!(CStr::from_ptr(jsonl).to_str(), -1);
+
+    let imported = unwrap_or_return!(labels::import_labels(&wallet.database(), jsonl), -1);
+
+    let _ = wallet.database().flush();
+    imported as i32
+}
+
+// Export all labels as BIP-329 JSON lines
+#[no_mangle]
+pub unsafe extern "C" fn wallet_export_labels(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let jsonl = unwrap_or_return!(labels::export_labels(&wallet.database()), error_return);
+
+    unwrap_or_return!(CString::new(jsonl), error_return).into_raw()
+}
+
+// Export the wallet for use in Sparrow, Electrum, Specter or another multisig coordinator
+#[no_mangle]
+pub unsafe extern "C" fn wallet_export_config(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    label: *const c_char,
+    format: ExportFormat,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let label = unwrap_or_return!(CStr::from_ptr(label).to_str(), error_return);
+
+    let config = unwrap_or_return!(export::export_wallet(&wallet, label, format), error_return);
+    unwrap_or_return!(CString::new(config), error_return).into_raw()
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn wallet_generate_seed(network: NetworkType) -> Seed {
     let secp = Secp256k1::new();
@@ -1077,6 +2207,497 @@ pub unsafe fn init(
         null_mut()
     ));
 
-    let wallet_box = Box::new(wallet);
-    Box::into_raw(wallet_box)
+    // Shared with async tasks that are still running when Dart drops the wallet
+    Arc::into_raw(Arc::new(wallet)) as *mut _
+}
+
+// Sign with the key behind one of this wallet's addresses, returns the base64 signature
+#[no_mangle]
+pub unsafe extern "C" fn wallet_sign_message(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    address: *const c_char,
+    message: *const c_char,
+    format: MessageFormat,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let address = unwrap_or_return!(CStr::from_ptr(address).to_str(), error_return);
+    let message = unwrap_or_return!(CStr::from_ptr(message).to_str(), error_return);
+
+    let signature = unwrap_or_return!(
+        message::sign_message(&wallet, address, message, format),
+        error_return
+    );
+
+    CString::new(signature).unwrap().into_raw()
+}
+
+// Works out the signature format by itself, false with an error set if it can't be read
+#[no_mangle]
+pub unsafe extern "C" fn wallet_verify_message(
+    address: *const c_char,
+    message: *const c_char,
+    signature: *const c_char,
+) -> bool {
+    let address = unwrap_or_return!(CStr::from_ptr(address).to_str(), false);
+    let message = unwrap_or_return!(CStr::from_ptr(message).to_str(), false);
+    let signature = unwrap_or_return!(CStr::from_ptr(signature).to_str(), false);
+
+    unwrap_or_return!(message::verify_message(address, message, signature), false)
+}
+
+// Unsigned BIP-322 PSBT for signing the message on Passport
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_bip322_psbt(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    address: *const c_char,
+    message: *const c_char,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let address = unwrap_or_return!(CStr::from_ptr(address).to_str(), error_return);
+    let message = unwrap_or_return!(CStr::from_ptr(message).to_str(), error_return);
+
+    let psbt = unwrap_or_return!(
+        message::get_bip322_psbt(&wallet, address, message),
+        error_return
+    );
+
+    CString::new(base64::encode(&serialize(&psbt)))
+        .unwrap()
+        .into_raw()
+}
+
+// Signature out of a BIP-322 PSBT that came back signed
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_bip322_signature(
+    psbt: *const c_char,
+    format: MessageFormat,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let psbt = unwrap_or_return!(CStr::from_ptr(psbt).to_str(), error_return);
+    let data = unwrap_or_return!(base64::decode(psbt), error_return);
+    let psbt = unwrap_or_return!(
+        deserialize::<PartiallySignedTransaction>(&data),
+        error_return
+    );
+
+    let signature = unwrap_or_return!(message::get_bip322_signature(psbt, format), error_return);
+
+    CString::new(signature).unwrap().into_raw()
+}
+
+// Nothing is posted for a cancelled task. Dart still frees it with wallet_task_free
+#[no_mangle]
+pub unsafe extern "C" fn wallet_cancel(task: *mut Task) -> bool {
+    if task.is_null() {
+        return false;
+    }
+
+    (*task).cancel();
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_task_free(task: *mut Task) {
+    if !task.is_null() {
+        drop(Box::from_raw(task));
+    }
+}
+
+// The *_async calls below return straight away and post their result to the isolate port
+#[no_mangle]
+pub unsafe extern "C" fn wallet_sync_async(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    electrum_address: *const c_char,
+    tor_port: i32,
+    isolate_port: i64,
+) -> *mut Task {
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), ptr::null_mut());
+    let electrum_address =
+        unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), ptr::null_mut()).to_string();
+
+    tasks::spawn(isolate_port, move |cancelled| {
+        // The lock isn't held while the server is asked, only while its answers are applied
+        let (tree, sync_config) = {
+            let wallet = util::lock_wallet(&wallet).map_err(|e| e.to_string())?;
+            let tree = wallet.database().clone();
+            let sync_config = config::get_sync_config(&tree).map_err(|e| e.to_string())?;
+            (tree, sync_config)
+        };
+
+        let client = util::get_electrum_client(tor_port, &electrum_address, &sync_config)
+            .map_err(|e| e.to_string())?;
+        let blockchain = {
+            let wallet = util::lock_wallet(&wallet).map_err(|e| e.to_string())?;
+            ElectrumSync::new(client, &wallet, &sync_config).map_err(|e| e.to_string())?
+        };
+        let update = blockchain
+            .cancel_on(cancelled)
+            .fetch(&tree)
+            .map_err(|e| e.to_string())?;
+
+        let wallet = util::lock_wallet(&wallet).map_err(|e| e.to_string())?;
+        update
+            .apply(&mut wallet.database().clone())
+            .map_err(|e| e.to_string())?;
+
+        Ok(true)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_fee_rate_async(
+    electrum_address: *const c_char,
+    tor_port: i32,
+    target: u16,
+    isolate_port: i64,
+) -> *mut Task {
+    let electrum_address =
+        unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), ptr::null_mut()).to_string();
+
+    tasks::spawn(isolate_port, move |_| {
+        let client = util::get_electrum_client(tor_port, &electrum_address, &SyncConfig::default())
+            .map_err(|e| e.to_string())?;
+
+        // BTC per kb
+        client
+            .estimate_fee(target as usize)
+            .map_err(|e| e.to_string())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_server_features_async(
+    electrum_address: *const c_char,
+    tor_port: i32,
+    isolate_port: i64,
+) -> *mut Task {
+    let electrum_address =
+        unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), ptr::null_mut()).to_string();
+
+    tasks::spawn(isolate_port, move |_| {
+        let client = util::get_electrum_client(tor_port, &electrum_address, &SyncConfig::default())
+            .map_err(|e| e.to_string())?;
+        let features = client.server_features().map_err(|e| e.to_string())?;
+
+        Ok(serde_json::json!({
+            "server_version": features.server_version,
+            "protocol_min": features.protocol_min,
+            "protocol_max": features.protocol_max,
+            "pruning": features.pruning.unwrap_or(-1),
+            "genesis_hash": hex::encode(features.genesis_hash),
+        }))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_broadcast_tx_async(
+    electrum_address: *const c_char,
+    tor_port: i32,
+    tx: *const c_char,
+    isolate_port: i64,
+) -> *mut Task {
+    let electrum_address =
+        unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), ptr::null_mut()).to_string();
+    let hex_tx = unwrap_or_return!(CStr::from_ptr(tx).to_str(), ptr::null_mut()).to_string();
+
+    tasks::spawn(isolate_port, move |_| {
+        let client = util::get_electrum_client(tor_port, &electrum_address, &SyncConfig::default())
+            .map_err(|e| e.to_string())?;
+
+        let raw_tx = hex::decode(hex_tx).map_err(|e| e.to_string())?;
+        let tx: bdk::bitcoin::Transaction = deserialize(&raw_tx).map_err(|e| e.to_string())?;
+        let txid = client
+            .transaction_broadcast(&tx)
+            .map_err(|e| e.to_string())?;
+
+        Ok(txid.to_string())
+    })
+}
+
+// Sync against the chosen backend, e.g. an Esplora instance when no Electrum port is reachable
+#[no_mangle]
+pub unsafe extern "C" fn wallet_sync_with_backend(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    backend: BackendType,
+    address: *const c_char,
+    tor_port: i32,
+) -> bool {
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), false);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), false);
+    let address = unwrap_or_return!(CStr::from_ptr(address).to_str(), false);
+
+    unwrap_or_return!(
+        backend::sync_wallet(backend, tor_port, address, &wallet),
+        false
+    );
+
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_fee_rate_with_backend(
+    backend: BackendType,
+    address: *const c_char,
+    tor_port: i32,
+    target: u16,
+) -> f64 {
+    let address = unwrap_or_return!(CStr::from_ptr(address).to_str(), -1.0);
+
+    // BTC per kb
+    unwrap_or_return!(
+        backend::get_fee_rate(backend, tor_port, address, target),
+        -1.0
+    )
+}
+
+// Raw transaction in hex
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_tx_with_backend(
+    backend: BackendType,
+    address: *const c_char,
+    tor_port: i32,
+    txid: *const c_char,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let address = unwrap_or_return!(CStr::from_ptr(address).to_str(), error_return);
+    let txid = unwrap_or_return!(CStr::from_ptr(txid).to_str(), error_return);
+    let txid = unwrap_or_return!(Txid::from_str(txid), error_return);
+
+    let tx = unwrap_or_return!(
+        backend::get_tx(backend, tor_port, address, &txid),
+        error_return
+    );
+
+    CString::new(hex::encode(serialize(&tx)))
+        .unwrap()
+        .into_raw()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_broadcast_tx_with_backend(
+    backend: BackendType,
+    address: *const c_char,
+    tor_port: i32,
+    tx: *const c_char,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let address = unwrap_or_return!(CStr::from_ptr(address).to_str(), error_return);
+
+    let hex_tx = unwrap_or_return!(CStr::from_ptr(tx).to_str(), error_return);
+    let raw_tx = unwrap_or_return!(hex::decode(hex_tx), error_return);
+
+    let tx: bdk::bitcoin::Transaction = unwrap_or_return!(deserialize(&*raw_tx), error_return);
+    let txid = unwrap_or_return!(
+        backend::broadcast(backend, tor_port, address, &tx),
+        error_return
+    );
+
+    unwrap_or_return!(CString::new(txid.to_string()), error_return).into_raw()
+}
+
+// Sync from the first server in the list that works, returns the server used
+#[no_mangle]
+pub unsafe extern "C" fn wallet_sync_failover(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+    servers_len: u8,
+    servers: *const *const c_char,
+    tor_port: i32,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let wallet = unwrap_or_return!(util::get_wallet_arc(wallet), error_return);
+    let wallet = unwrap_or_return!(util::lock_wallet(&wallet), error_return);
+    let servers = unwrap_or_return!(
+        util::extract_string_list(servers_len, servers),
+        error_return
+    );
+    let sync_config = unwrap_or_return!(config::get_sync_config(&wallet.database()), error_return);
+
+    let (_, server) = unwrap_or_return!(
+        failover::with_failover(
+            &servers,
+            tor_port,
+            wallet.network(),
+            &sync_config,
+            |client| {
+                let blockchain = ElectrumBlockchain::from(Client::from(client));
+                wallet.sync(&blockchain, SyncOptions { progress: None })
+            }
+        ),
+        error_return
+    );
+
+    CString::new(server).unwrap().into_raw()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_fee_rate_failover(
+    servers_len: u8,
+    servers: *const *const c_char,
+    tor_port: i32,
+    network: NetworkType,
+    target: u16,
+) -> ServedFeeRate {
+    let error_return = ServedFeeRate {
+        fee_rate: -1.0,
+        server: ptr::null(),
+    };
+
+    let servers = unwrap_or_return!(
+        util::extract_string_list(servers_len, servers),
+        error_return
+    );
+
+    let (fee_rate, server) = unwrap_or_return!(
+        failover::with_failover(
+            &servers,
+            tor_port,
+            network.into(),
+            &SyncConfig::default(),
+            // BTC per kb
+            |client| Ok(client.estimate_fee(target as usize)?)
+        ),
+        error_return
+    );
+
+    ServedFeeRate {
+        fee_rate,
+        server: CString::new(server).unwrap().into_raw(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_broadcast_tx_failover(
+    servers_len: u8,
+    servers: *const *const c_char,
+    tor_port: i32,
+    network: NetworkType,
+    tx: *const c_char,
+) -> ServedTxid {
+    let error_return = ServedTxid {
+        txid: ptr::null(),
+        server: ptr::null(),
+    };
+
+    let servers = unwrap_or_return!(
+        util::extract_string_list(servers_len, servers),
+        error_return
+    );
+
+    let hex_tx = unwrap_or_return!(CStr::from_ptr(tx).to_str(), error_return);
+    let raw_tx = unwrap_or_return!(hex::decode(hex_tx), error_return);
+    let tx: bdk::bitcoin::Transaction = unwrap_or_return!(deserialize(&*raw_tx), error_return);
+
+    let (txid, server) = unwrap_or_return!(
+        failover::with_failover(
+            &servers,
+            tor_port,
+            network.into(),
+            &SyncConfig::default(),
+            |client| Ok(client.transaction_broadcast(&tx)?)
+        ),
+        error_return
+    );
+
+    ServedTxid {
+        txid: CString::new(txid.to_string()).unwrap().into_raw(),
+        server: CString::new(server).unwrap().into_raw(),
+    }
+}
+
+// Latency and error counts of every server used since the library was loaded
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_server_health() -> ElectrumServerHealthList {
+    let servers: Vec<_> = failover::get_server_health()
+        .into_iter()
+        .map(|(server, health)| ElectrumServerHealth {
+            server: CString::new(server).unwrap().into_raw(),
+            successes: health.successes,
+            errors: health.errors,
+            consecutive_errors: health.consecutive_errors,
+            latency_ms: health.latency_ms,
+            last_error: CString::new(health.last_error.unwrap_or_default())
+                .unwrap()
+                .into_raw(),
+        })
+        .collect();
+
+    let servers_len = servers.len() as u32;
+    let servers_box = servers.into_boxed_slice();
+    let servers_ptr = Box::into_raw(servers_box);
+
+    ElectrumServerHealthList {
+        servers_len,
+        servers: servers_ptr as _,
+    }
+}
+
+// Applies to every ssl:// Electrum connection made from here on
+#[no_mangle]
+pub unsafe extern "C" fn wallet_set_certificate_pinning(
+    data_dir: *const c_char,
+    mode: PinningMode,
+) -> bool {
+    let data_dir = unwrap_or_return!(CStr::from_ptr(data_dir).to_str(), false);
+    unwrap_or_return!(tls::set_pinning(data_dir, mode), false);
+    true
+}
+
+// For showing the user what they're about to trust
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_server_fingerprint(
+    electrum_address: *const c_char,
+    tor_port: i32,
+) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let electrum_address =
+        unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), error_return);
+    let fingerprint = unwrap_or_return!(
+        tls::get_fingerprint(electrum_address, tor_port, &SyncConfig::default()),
+        error_return
+    );
+
+    CString::new(fingerprint).unwrap().into_raw()
+}
+
+// Empty if the server isn't pinned yet
+#[no_mangle]
+pub unsafe extern "C" fn wallet_get_server_pin(electrum_address: *const c_char) -> *const c_char {
+    let error_return = CString::new("").unwrap().into_raw();
+
+    let electrum_address =
+        unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), error_return);
+    let pin = unwrap_or_return!(tls::get_pin(electrum_address.trim()), error_return);
+
+    CString::new(pin.unwrap_or_default()).unwrap().into_raw()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_pin_server_certificate(
+    electrum_address: *const c_char,
+    fingerprint: *const c_char,
+) -> bool {
+    let electrum_address = unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), false);
+    let fingerprint = unwrap_or_return!(CStr::from_ptr(fingerprint).to_str(), false);
+
+    unwrap_or_return!(tls::set_pin(electrum_address.trim(), fingerprint), false);
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wallet_forget_server_certificate(electrum_address: *const c_char) -> bool {
+    let electrum_address = unwrap_or_return!(CStr::from_ptr(electrum_address).to_str(), false);
+
+    unwrap_or_return!(tls::remove_pin(electrum_address.trim()), false);
+    true
 }
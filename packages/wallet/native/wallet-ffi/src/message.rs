@@ -0,0 +1,474 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::{addresses, MessageFormat};
+use bdk::bitcoin::blockdata::opcodes;
+use bdk::bitcoin::blockdata::script::Builder;
+use bdk::bitcoin::consensus::encode::{deserialize, serialize};
+use bdk::bitcoin::hashes::{sha256, Hash, HashEngine};
+use bdk::bitcoin::secp256k1::{Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use bdk::bitcoin::util::bip32::ChildNumber;
+use bdk::bitcoin::util::misc::{signed_msg_hash, MessageSignature};
+use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
+use bdk::bitcoin::util::sighash::{Prevouts, SighashCache};
+use bdk::bitcoin::{
+    Address, EcdsaSig, OutPoint, PackedLockTime, PublicKey, SchnorrSig, Script, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+use bdk::miniscript::descriptor::{DescriptorSecretKey, DescriptorType};
+use bdk::miniscript::psbt::PsbtExt;
+use bdk::SignOptions;
+use sled::Tree;
+use std::str::FromStr;
+
+// How far past the revealed addresses we look for the signing address
+const ADDRESS_SEARCH_LIMIT: u32 = 1000;
+
+fn generic_error<E: ToString>(e: E) -> bdk::Error {
+    bdk::Error::Generic(e.to_string())
+}
+
+fn parse_address(address: &str) -> Result<Address, bdk::Error> {
+    Address::from_str(address.trim()).map_err(generic_error)
+}
+
+// BIP-340 style tagged hash BIP-322 commits the message with
+fn bip322_message_hash(message: &str) -> sha256::Hash {
+    let tag = sha256::Hash::hash(b"BIP0322-signed-message");
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(message.as_bytes());
+
+    sha256::Hash::from_engine(engine)
+}
+
+// Virtual transaction that "pays" the address, never broadcast
+fn bip322_to_spend(script_pubkey: &Script, message: &str) -> Transaction {
+    let script_sig = Builder::new()
+        .push_int(0)
+        .push_slice(&bip322_message_hash(message)[..])
+        .into_script();
+
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0xFFFFFFFF),
+            script_sig,
+            sequence: Sequence(0),
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+// Virtual transaction spending to_spend, its witness is the signature
+fn bip322_to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: Script::new(),
+            sequence: Sequence(0),
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .into_script(),
+        }],
+    }
+}
+
+fn check_bip322_script(script_pubkey: &Script) -> Result<(), bdk::Error> {
+    if script_pubkey.is_v0_p2wpkh() || script_pubkey.is_v1_p2tr() {
+        Ok(())
+    } else {
+        Err(bdk::Error::Generic(
+            "BIP-322 signing is only supported for wpkh and tr addresses".to_string(),
+        ))
+    }
+}
+
+// Unsigned to_sign PSBT carrying everything a signer needs to know the key, e.g. for Passport
+pub fn get_bip322_psbt(
+    wallet: &bdk::Wallet<Tree>,
+    address: &str,
+    message: &str,
+) -> Result<PartiallySignedTransaction, bdk::Error> {
+    let script_pubkey = parse_address(address)?.script_pubkey();
+    check_bip322_script(&script_pubkey)?;
+
+    let owner = addresses::find_address(wallet, address, ADDRESS_SEARCH_LIMIT)?;
+    let descriptor = wallet
+        .get_descriptor_for_keychain(owner.keychain)
+        .at_derivation_index(owner.index);
+
+    let to_spend = bip322_to_spend(&script_pubkey, message);
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(bip322_to_sign(&to_spend))
+        .map_err(generic_error)?;
+
+    psbt.inputs[0].witness_utxo = Some(to_spend.output[0].clone());
+    psbt.update_input_with_descriptor(0, &descriptor)
+        .map_err(generic_error)?;
+
+    Ok(psbt)
+}
+
+// Turn a signed BIP-322 PSBT into the simple (witness only) or full (whole to_sign) signature
+pub fn get_bip322_signature(
+    mut psbt: PartiallySignedTransaction,
+    format: MessageFormat,
+) -> Result<String, bdk::Error> {
+    let secp = Secp256k1::verification_only();
+    psbt.finalize_mut(&secp).map_err(|errors| {
+        bdk::Error::Generic(
+            errors
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    })?;
+
+    let to_sign = psbt.extract_tx();
+
+    match format {
+        MessageFormat::Bip322Simple => Ok(base64::encode(serialize(&to_sign.input[0].witness))),
+        MessageFormat::Bip322Full => Ok(base64::encode(serialize(&to_sign))),
+        MessageFormat::Legacy => Err(bdk::Error::Generic(
+            "Legacy signatures don't use a PSBT".to_string(),
+        )),
+    }
+}
+
+// Private key for a single key address of this wallet
+fn get_address_key(wallet: &bdk::Wallet<Tree>, address: &str) -> Result<SecretKey, bdk::Error> {
+    let secp = Secp256k1::new();
+    let owner = addresses::find_address(wallet, address, ADDRESS_SEARCH_LIMIT)?;
+
+    if !matches!(
+        wallet
+            .get_descriptor_for_keychain(owner.keychain)
+            .desc_type(),
+        DescriptorType::Pkh | DescriptorType::Wpkh | DescriptorType::ShWpkh
+    ) {
+        return Err(bdk::Error::Generic(
+            "Legacy message signing needs a single key pkh, wpkh or sh-wpkh address".to_string(),
+        ));
+    }
+
+    let key_map = wallet.get_signers(owner.keychain).as_key_map(&secp);
+    match key_map.values().next() {
+        Some(DescriptorSecretKey::XPrv(xprv)) => {
+            let path = xprv
+                .derivation_path
+                .child(ChildNumber::Normal { index: owner.index });
+            Ok(xprv.xkey.derive_priv(&secp, &path)?.private_key)
+        }
+        Some(DescriptorSecretKey::Single(single)) => Ok(single.key.inner),
+        None => Err(bdk::Error::Generic(
+            "No private key for this address".to_string(),
+        )),
+    }
+}
+
+fn sign_legacy(
+    wallet: &bdk::Wallet<Tree>,
+    address: &str,
+    message: &str,
+) -> Result<String, bdk::Error> {
+    let secp = Secp256k1::new();
+    let secret_key = get_address_key(wallet, address)?;
+
+    let msg = Message::from_slice(&signed_msg_hash(message)[..]).map_err(generic_error)?;
+    let signature = MessageSignature::new(secp.sign_ecdsa_recoverable(&msg, &secret_key), true);
+
+    Ok(base64::encode(signature.serialize()))
+}
+
+pub fn sign_message(
+    wallet: &bdk::Wallet<Tree>,
+    address: &str,
+    message: &str,
+    format: MessageFormat,
+) -> Result<String, bdk::Error> {
+    if let MessageFormat::Legacy = format {
+        return sign_legacy(wallet, address, message);
+    }
+
+    let mut psbt = get_bip322_psbt(wallet, address, message)?;
+
+    let sign_options = SignOptions {
+        trust_witness_utxo: true,
+        try_finalize: false,
+        ..Default::default()
+    };
+    wallet.sign(&mut psbt, sign_options)?;
+
+    get_bip322_signature(psbt, format)
+}
+
+// Signer's public key must hash to the address, any of the single key types Electrum signs for
+fn verify_legacy(address: &Address, message: &str, signature: &[u8]) -> Result<bool, bdk::Error> {
+    let secp = Secp256k1::verification_only();
+
+    // BIP137 headers 35-38 (p2sh-p2wpkh) and 39-42 (p2wpkh) also mean a compressed key, but
+    // MessageSignature only knows 27-30 and 31-34. The address check below covers the type
+    let mut signature = signature.to_vec();
+    if signature[0] >= 31 {
+        signature[0] = 31 + ((signature[0] - 27) & 0x03);
+    }
+    let signature = MessageSignature::from_slice(&signature).map_err(generic_error)?;
+
+    let public_key = match signature.recover_pubkey(&secp, signed_msg_hash(message)) {
+        Ok(pk) => pk,
+        Err(_) => return Ok(false),
+    };
+
+    let script_pubkey = address.script_pubkey();
+    let network = address.network;
+
+    Ok(
+        Address::p2pkh(&public_key, network).script_pubkey() == script_pubkey
+            || Address::p2wpkh(&public_key, network)
+                .map(|a| a.script_pubkey() == script_pubkey)
+                .unwrap_or(false)
+            || Address::p2shwpkh(&public_key, network)
+                .map(|a| a.script_pubkey() == script_pubkey)
+                .unwrap_or(false),
+    )
+}
+
+// Checks the to_sign input against to_spend, covering key path spends of wpkh and tr
+fn verify_bip322_witness(
+    to_spend: &Transaction,
+    to_sign: &Transaction,
+) -> Result<bool, bdk::Error> {
+    let secp = Secp256k1::verification_only();
+    let prevout = &to_spend.output[0];
+    let script_pubkey = &prevout.script_pubkey;
+    let witness = to_sign.input[0].witness.to_vec();
+    let mut sighash_cache = SighashCache::new(to_sign);
+
+    if script_pubkey.is_v0_p2wpkh() {
+        if witness.len() != 2 {
+            return Ok(false);
+        }
+
+        let signature = EcdsaSig::from_slice(&witness[0]).map_err(generic_error)?;
+        let public_key = PublicKey::from_slice(&witness[1]).map_err(generic_error)?;
+        let wpubkey_hash = match public_key.wpubkey_hash() {
+            Some(h) => h,
+            None => return Ok(false),
+        };
+
+        if Script::new_v0_p2wpkh(&wpubkey_hash) != *script_pubkey {
+            return Ok(false);
+        }
+
+        let sighash = sighash_cache
+            .segwit_signature_hash(
+                0,
+                &Script::new_p2pkh(&public_key.pubkey_hash()),
+                prevout.value,
+                signature.hash_ty,
+            )
+            .map_err(generic_error)?;
+
+        let msg = Message::from_slice(&sighash[..]).map_err(generic_error)?;
+        Ok(secp
+            .verify_ecdsa(&msg, &signature.sig, &public_key.inner)
+            .is_ok())
+    } else if script_pubkey.is_v1_p2tr() {
+        if witness.len() != 1 {
+            return Ok(false);
+        }
+
+        let signature = SchnorrSig::from_slice(&witness[0]).map_err(generic_error)?;
+        let output_key =
+            XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]).map_err(generic_error)?;
+
+        let sighash = sighash_cache
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(&to_spend.output),
+                signature.hash_ty,
+            )
+            .map_err(generic_error)?;
+
+        let msg = Message::from_slice(&sighash[..]).map_err(generic_error)?;
+        Ok(secp
+            .verify_schnorr(&signature.sig, &msg, &output_key)
+            .is_ok())
+    } else {
+        Err(bdk::Error::Generic(
+            "BIP-322 verification is only supported for wpkh and tr addresses".to_string(),
+        ))
+    }
+}
+
+// Accepts legacy, BIP-322 simple and BIP-322 full signatures, telling them apart by encoding
+pub fn verify_message(address: &str, message: &str, signature: &str) -> Result<bool, bdk::Error> {
+    let address = parse_address(address)?;
+    let signature = base64::decode(signature.trim()).map_err(generic_error)?;
+
+    // Recoverable ECDSA signature with a 27-42 header byte
+    if signature.len() == 65 && (27..=42).contains(&signature[0]) {
+        return verify_legacy(&address, message, &signature);
+    }
+
+    let to_spend = bip322_to_spend(&address.script_pubkey(), message);
+    let mut to_sign = bip322_to_sign(&to_spend);
+
+    if let Ok(witness) = deserialize::<Witness>(&signature) {
+        to_sign.input[0].witness = witness;
+    } else {
+        let full: Transaction = deserialize(&signature).map_err(generic_error)?;
+
+        // Proof of funds with extra inputs isn't supported
+        if full.input.len() != 1
+            || full.input[0].previous_output != to_sign.input[0].previous_output
+            || full.output != to_sign.output
+        {
+            return Ok(false);
+        }
+
+        to_sign = full;
+    }
+
+    verify_bip322_witness(&to_spend, &to_sign)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+    use bdk::bitcoin::Network;
+    use bdk::wallet::AddressIndex;
+
+    // Private key of the BIP-322 test vectors
+    const KEY: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+
+    // Wallet of KEY alone, with its first address
+    fn get_key_wallet(descriptor: &str) -> (bdk::Wallet<Tree>, String) {
+        let wallet =
+            bdk::Wallet::new(descriptor, None, Network::Bitcoin, util::get_test_tree()).unwrap();
+        let address = wallet.get_address(AddressIndex::New).unwrap().to_string();
+
+        (wallet, address)
+    }
+
+    #[test]
+    fn test_legacy_round_trip() {
+        for descriptor in [
+            format!("pkh({KEY})"),
+            format!("sh(wpkh({KEY}))"),
+            format!("wpkh({KEY})"),
+        ] {
+            let (wallet, address) = get_key_wallet(&descriptor);
+            let signature =
+                sign_message(&wallet, &address, "Hello World", MessageFormat::Legacy).unwrap();
+
+            assert!(verify_message(&address, "Hello World", &signature).unwrap());
+            assert!(!verify_message(&address, "Goodbye World", &signature).unwrap());
+        }
+
+        let (wallet, address) = get_key_wallet(&format!("tr({KEY})"));
+        assert!(sign_message(&wallet, &address, "Hello World", MessageFormat::Legacy).is_err());
+    }
+
+    #[test]
+    fn test_verify_legacy_bip137_headers() {
+        // Trezor style headers: 35-38 for p2sh-p2wpkh, 39-42 for p2wpkh
+        for (descriptor, offset) in [(format!("sh(wpkh({KEY}))"), 4), (format!("wpkh({KEY})"), 8)] {
+            let (wallet, address) = get_key_wallet(&descriptor);
+            let signature =
+                sign_message(&wallet, &address, "Hello World", MessageFormat::Legacy).unwrap();
+
+            let mut bytes = base64::decode(signature).unwrap();
+            assert!((31..=34).contains(&bytes[0]));
+            bytes[0] += offset;
+
+            assert!(verify_message(&address, "Hello World", &base64::encode(bytes)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_bip322_round_trip() {
+        for descriptor in [format!("wpkh({KEY})"), format!("tr({KEY})")] {
+            let (wallet, address) = get_key_wallet(&descriptor);
+
+            for format in [MessageFormat::Bip322Simple, MessageFormat::Bip322Full] {
+                let signature = sign_message(&wallet, &address, "Hello World", format).unwrap();
+
+                assert!(verify_message(&address, "Hello World", &signature).unwrap());
+                assert!(!verify_message(&address, "Goodbye World", &signature).unwrap());
+            }
+        }
+
+        let (wallet, address) = get_key_wallet(&format!("pkh({KEY})"));
+        assert!(sign_message(
+            &wallet,
+            &address,
+            "Hello World",
+            MessageFormat::Bip322Simple
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_bip322_vector_address() {
+        // The BIP-322 vectors sign for this key's wpkh address
+        let (_, address) = get_key_wallet(&format!("wpkh({KEY})"));
+        assert_eq!(address, "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l");
+    }
+
+    #[test]
+    fn test_bip322_message_hash() {
+        // Test vectors from BIP-322
+        assert_eq!(
+            bip322_message_hash("").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            bip322_message_hash("Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn test_verify_bip322_simple() {
+        // Test vectors from BIP-322
+        let address = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+
+        assert!(verify_message(
+            address,
+            "",
+            "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="
+        )
+        .unwrap());
+        assert!(verify_message(
+            address,
+            "Hello World",
+            "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="
+        )
+        .unwrap());
+
+        // Signature for the other message
+        assert!(!verify_message(
+            address,
+            "Hello World",
+            "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="
+        )
+        .unwrap());
+    }
+}
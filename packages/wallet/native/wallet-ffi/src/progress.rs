@@ -0,0 +1,296 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::electrum::{ElectrumSync, HistorySource};
+use crate::{util, SyncConfig};
+use allo_isolate::Isolate;
+#[cfg(feature = "compact-filters")]
+use bdk::blockchain::{GetHeight, Progress, WalletSync};
+use bdk::database::Database;
+#[cfg(feature = "compact-filters")]
+use bdk::SyncOptions;
+use serde::Serialize;
+use sled::Tree;
+#[cfg(feature = "compact-filters")]
+use std::fmt;
+use std::sync::Mutex;
+
+// Posted to Dart as a JSON string
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SyncProgress {
+    pub stage: String,
+    pub scripts: u32,
+    pub transactions: u32,
+    pub percent: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+fn post(isolate: &Isolate, progress: &SyncProgress) {
+    if let Ok(json) = serde_json::to_string(progress) {
+        isolate.post(json);
+    }
+}
+
+// Forwards whatever the backend reports while it syncs
+#[cfg(feature = "compact-filters")]
+struct ReportProgress<F> {
+    report: F,
+    scripts: u32,
+}
+
+#[cfg(feature = "compact-filters")]
+impl<F> fmt::Debug for ReportProgress<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReportProgress")
+            .field("scripts", &self.scripts)
+            .finish()
+    }
+}
+
+#[cfg(feature = "compact-filters")]
+impl<F: Fn(&SyncProgress) + Send + 'static> Progress for ReportProgress<F> {
+    fn update(&self, progress: f32, message: Option<String>) -> Result<(), bdk::Error> {
+        (self.report)(&SyncProgress {
+            stage: "syncing".to_string(),
+            scripts: self.scripts,
+            transactions: 0,
+            percent: progress,
+            message,
+        });
+
+        Ok(())
+    }
+}
+
+// For backends that report through BDK's Progress, which in BDK 0.28 only compact filters do
+#[cfg(feature = "compact-filters")]
+pub fn sync_with_progress<B: WalletSync + GetHeight>(
+    wallet: &bdk::Wallet<Tree>,
+    blockchain: &B,
+    isolate_port: i64,
+) -> Result<(), bdk::Error> {
+    let isolate = Isolate::new(isolate_port);
+    let started = get_started(wallet)?;
+    post(&isolate, &started);
+
+    wallet.sync(
+        blockchain,
+        SyncOptions {
+            progress: Some(Box::new(ReportProgress {
+                report: move |progress: &SyncProgress| post(&isolate, progress),
+                scripts: started.scripts,
+            })),
+        },
+    )?;
+
+    post(&Isolate::new(isolate_port), &get_finished(wallet)?);
+    Ok(())
+}
+
+// BDK's Electrum sync never reports, ours does after every batch of scripts. The wallet
+// is only locked around the server's answers being applied, not while it's asked
+pub fn sync_electrum_with_progress<C: HistorySource>(
+    wallet: &Mutex<bdk::Wallet<Tree>>,
+    client: C,
+    sync_config: &SyncConfig,
+    isolate_port: i64,
+) -> Result<(), bdk::Error> {
+    let isolate = Isolate::new(isolate_port);
+    sync_electrum(wallet, client, sync_config, &|progress| {
+        post(&isolate, progress)
+    })
+}
+
+fn sync_electrum<C: HistorySource>(
+    wallet: &Mutex<bdk::Wallet<Tree>>,
+    client: C,
+    sync_config: &SyncConfig,
+    report: &dyn Fn(&SyncProgress),
+) -> Result<(), bdk::Error> {
+    let (blockchain, tree) = {
+        let wallet = util::lock_wallet(wallet)?;
+        report(&get_started(&wallet)?);
+
+        let blockchain = ElectrumSync::new(client, &wallet, sync_config)?;
+        (blockchain, wallet.database().clone())
+    };
+
+    let blockchain = blockchain.on_batch(|checked, total, transactions| {
+        report(&SyncProgress {
+            stage: "syncing".to_string(),
+            scripts: checked as u32,
+            transactions: transactions as u32,
+            percent: checked as f32 * 100.0 / total.max(1) as f32,
+            message: None,
+        })
+    });
+    let update = blockchain.fetch(&tree)?;
+
+    let wallet = util::lock_wallet(wallet)?;
+    update.apply(&mut wallet.database().clone())?;
+    report(&get_finished(&wallet)?);
+
+    Ok(())
+}
+
+fn get_started(wallet: &bdk::Wallet<Tree>) -> Result<SyncProgress, bdk::Error> {
+    Ok(SyncProgress {
+        stage: "started".to_string(),
+        scripts: wallet.database().iter_script_pubkeys(None)?.len() as u32,
+        transactions: 0,
+        percent: 0.0,
+        message: None,
+    })
+}
+
+// Addresses revealed during the sync are included by now
+fn get_finished(wallet: &bdk::Wallet<Tree>) -> Result<SyncProgress, bdk::Error> {
+    Ok(SyncProgress {
+        stage: "finished".to_string(),
+        scripts: wallet.database().iter_script_pubkeys(None)?.len() as u32,
+        transactions: wallet.list_transactions(false)?.len() as u32,
+        percent: 100.0,
+        message: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::electrum::MockServer;
+    use bdk::bitcoin::{Network, OutPoint, PackedLockTime, Script, Transaction, TxIn, TxOut};
+    use bdk::wallet::AddressIndex;
+    use bdk::KeychainKind;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_sync_electrum_progress() {
+        let wallet = Mutex::new(util::get_test_wallet(Network::Testnet));
+
+        // Paid at index 90, so the stop gap runs past the 100 addresses BDK caches up front
+        let address = wallet
+            .lock()
+            .unwrap()
+            .get_address(AddressIndex::Peek(90))
+            .unwrap();
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let payment = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(funding.txid(), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 90_000,
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+
+        let server = MockServer {
+            histories: HashMap::from([(
+                address.script_pubkey(),
+                vec![(payment.txid(), Some(500))],
+            )]),
+            transactions: HashMap::from([
+                (funding.txid(), funding.clone()),
+                (payment.txid(), payment.clone()),
+            ]),
+        };
+        let sync_config = SyncConfig {
+            batch_size: 20,
+            ..SyncConfig::default()
+        };
+
+        let messages = RefCell::new(vec![]);
+        sync_electrum(&wallet, server, &sync_config, &|progress| {
+            messages.borrow_mut().push(progress.clone())
+        })
+        .unwrap();
+        let messages = messages.into_inner();
+
+        assert_eq!(messages.first().unwrap().stage, "started");
+        let finished = messages.last().unwrap();
+        assert_eq!(finished.stage, "finished");
+        assert_eq!(finished.transactions, 1);
+
+        // A message per batch: 100 cached external scripts, 41 more derived, 100 internal
+        let syncing: Vec<&SyncProgress> = messages
+            .iter()
+            .filter(|progress| progress.stage == "syncing")
+            .collect();
+        assert_eq!(syncing.len(), 13);
+        assert!(syncing.windows(2).all(|w| w[0].scripts < w[1].scripts));
+        assert!(syncing.iter().all(|progress| progress.percent <= 100.0));
+        assert_eq!(syncing[0].scripts, 20);
+        assert_eq!(syncing.last().unwrap().scripts, 241);
+        assert_eq!(syncing.last().unwrap().transactions, 1);
+
+        let wallet = wallet.lock().unwrap();
+        assert_eq!(wallet.get_balance().unwrap().confirmed, 90_000);
+        let tx = &wallet.list_transactions(false).unwrap()[0];
+        assert_eq!(tx.fee, Some(10_000));
+        assert_eq!(tx.confirmation_time.as_ref().unwrap().height, 500);
+        assert_eq!(
+            wallet
+                .database()
+                .iter_script_pubkeys(Some(KeychainKind::External))
+                .unwrap()
+                .len(),
+            141
+        );
+    }
+
+    #[test]
+    fn test_sync_electrum_cancelled() {
+        let wallet = util::get_test_wallet(Network::Testnet);
+        let server = MockServer::default();
+
+        let cancelled = AtomicBool::new(true);
+        let blockchain = ElectrumSync::new(server, &wallet, &SyncConfig::default())
+            .unwrap()
+            .cancel_on(&cancelled);
+
+        let error = blockchain.fetch(&*wallet.database()).err().unwrap();
+        assert_eq!(error.to_string(), "Generic error: Sync cancelled");
+    }
+
+    #[test]
+    #[cfg(feature = "compact-filters")]
+    fn test_report_progress() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let progress = ReportProgress {
+            report: move |progress: &SyncProgress| sender.send(progress.clone()).unwrap(),
+            scripts: 200,
+        };
+
+        // What BDK's compact filters sync sends while it downloads filters
+        progress
+            .update(42.5, Some("Processed 100/235 filters".to_string()))
+            .unwrap();
+        progress.update(100.0, Some("Done".to_string())).unwrap();
+
+        let messages: Vec<SyncProgress> = receiver.try_iter().collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].stage, "syncing");
+        assert_eq!(messages[0].scripts, 200);
+        assert_eq!(messages[0].percent, 42.5);
+        assert_eq!(
+            messages[0].message.as_deref(),
+            Some("Processed 100/235 filters")
+        );
+        assert_eq!(messages[1].percent, 100.0);
+    }
+}
@@ -0,0 +1,387 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// bitcoind's JSON-RPC over a SOCKS5 proxy. jsonrpc's own HTTP transport only connects
+// directly, which would take calls meant for Tor around it. BDK's RPC sync builds its own
+// client, so that's pointed at a local relay through the proxy instead
+
+use crate::{util, SyncConfig};
+use bdk::bitcoincore_rpc::jsonrpc::{self, Request, Response, Transport};
+use bdk::bitcoincore_rpc::{Auth as RpcAuth, Client as RpcClient};
+use bdk::blockchain::rpc::Auth;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+struct SocksHttpTransport {
+    proxy: String,
+    host: String,
+    port: u16,
+    path: String,
+    // Ready to go in the Authorization header
+    basic_auth: Option<String>,
+    timeout: Duration,
+}
+
+// Host, port and path of an http:// URL. Onion addresses are left for the proxy to resolve
+fn parse_http_url(url: &str) -> Result<(String, u16, String), bdk::Error> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        bdk::Error::Generic("Only plain http:// RPC addresses can go through a proxy".to_string())
+    })?;
+
+    let (host_port, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = host_port
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+        .ok_or_else(|| bdk::Error::Generic(format!("RPC port missing from {url}")))?;
+
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+impl SocksHttpTransport {
+    fn request<R: DeserializeOwned>(&self, body: impl Serialize) -> Result<R, io::Error> {
+        let mut stream =
+            socks::Socks5Stream::connect(self.proxy.as_str(), (self.host.as_str(), self.port))?
+                .into_inner();
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let body = serde_json::to_vec(&body)?;
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+            self.path,
+            self.host,
+            self.port,
+            body.len()
+        );
+        if let Some(auth) = &self.basic_auth {
+            request.push_str(&format!("Authorization: {auth}\r\n"));
+        }
+        request.push_str("\r\n");
+
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(&body)?;
+        stream.flush()?;
+
+        let mut reader = BufReader::new(stream);
+        let mut status = String::new();
+        reader.read_line(&mut status)?;
+        let code = status.split_whitespace().nth(1).unwrap_or_default();
+
+        let mut content_length = None;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header == "\r\n" {
+                break;
+            }
+
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+
+        let mut body = vec![];
+        match content_length {
+            Some(length) => {
+                body.resize(length, 0);
+                reader.read_exact(&mut body)?;
+            }
+            None => {
+                reader.read_to_end(&mut body)?;
+            }
+        }
+
+        // bitcoind answers errors with a JSON body too, which says more than the status code
+        serde_json::from_slice(&body).map_err(|e| match code {
+            "200" => e.into(),
+            _ => io::Error::other(format!("HTTP {}", status.trim())),
+        })
+    }
+}
+
+impl Transport for SocksHttpTransport {
+    fn send_request(&self, request: Request) -> Result<Response, jsonrpc::Error> {
+        self.request(request)
+            .map_err(|e| jsonrpc::Error::Transport(Box::new(e)))
+    }
+
+    fn send_batch(&self, requests: &[Request]) -> Result<Vec<Response>, jsonrpc::Error> {
+        self.request(requests)
+            .map_err(|e| jsonrpc::Error::Transport(Box::new(e)))
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "http://{}:{}{} via {}",
+            self.host, self.port, self.path, self.proxy
+        )
+    }
+}
+
+// A bitcoind client for the calls that don't need a wallet, through Tor when there's a port
+pub fn get_rpc_client(url: &str, auth: Auth, tor_port: i32) -> Result<RpcClient, bdk::Error> {
+    let proxy = match util::get_socks5_address(tor_port) {
+        Some(proxy) => proxy,
+        None => return Ok(RpcClient::new(url, auth.into())?),
+    };
+
+    let (host, port, path) = parse_http_url(url)?;
+    let basic_auth = match RpcAuth::from(auth).get_user_pass()? {
+        (Some(user), password) => Some(format!(
+            "Basic {}",
+            base64::encode(format!("{user}:{}", password.unwrap_or_default()))
+        )),
+        (None, _) => None,
+    };
+
+    let transport = SocksHttpTransport {
+        proxy,
+        host,
+        port,
+        path,
+        basic_auth,
+        timeout: Duration::from_secs(util::get_timeout(tor_port, &SyncConfig::default()) as u64),
+    };
+
+    Ok(RpcClient::from_jsonrpc(jsonrpc::Client::with_transport(
+        transport,
+    )))
+}
+
+// Passes whatever connects to it on to bitcoind through the proxy, until it's dropped
+pub struct SocksRelay {
+    address: SocketAddr,
+    path: String,
+    stopped: Arc<AtomicBool>,
+    listener: Option<JoinHandle<()>>,
+}
+
+impl SocksRelay {
+    pub fn start(proxy: String, url: &str) -> Result<Self, bdk::Error> {
+        let (host, port, path) = parse_http_url(url)?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = stopped.clone();
+        let listener = thread::spawn(move || {
+            for client in listener.incoming() {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                if let Ok(client) = client {
+                    let (proxy, host) = (proxy.clone(), host.clone());
+                    thread::spawn(move || relay(client, &proxy, (&host, port)));
+                }
+            }
+        });
+
+        Ok(SocksRelay {
+            address,
+            path,
+            stopped,
+            listener: Some(listener),
+        })
+    }
+
+    // Stands in for the bitcoind URL. BDK appends /wallet/<name> to it
+    pub fn url(&self) -> String {
+        format!("http://{}{}", self.address, self.path.trim_end_matches('/'))
+    }
+}
+
+impl Drop for SocksRelay {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+
+        // Wakes the listener up so it sees it's been stopped
+        let _ = TcpStream::connect(self.address);
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.join();
+        }
+    }
+}
+
+// Copies both ways until either end hangs up. Timeouts are left to the RPC client
+fn relay(client: TcpStream, proxy: &str, target: (&str, u16)) -> Result<(), io::Error> {
+    let server = socks::Socks5Stream::connect(proxy, target)?.into_inner();
+
+    let (mut client_reader, mut server_writer) = (client.try_clone()?, server.try_clone()?);
+    let upload = thread::spawn(move || {
+        let _ = io::copy(&mut client_reader, &mut server_writer);
+        let _ = server_writer.shutdown(Shutdown::Write);
+    });
+
+    let (mut server_reader, mut client_writer) = (server, client);
+    let _ = io::copy(&mut server_reader, &mut client_writer);
+    let _ = client_writer.shutdown(Shutdown::Write);
+    let _ = upload.join();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoincore_rpc::RpcApi;
+    use std::sync::mpsc;
+
+    // A SOCKS5 proxy that answers the JSON-RPC itself instead of connecting anywhere.
+    // Sends back the target it was asked for and the request's Authorization header
+    fn start_mock_proxy() -> (u16, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let seen = answer(stream.unwrap());
+                sender.send(seen).unwrap();
+            }
+        });
+
+        (port, receiver)
+    }
+
+    fn answer(mut stream: TcpStream) -> (String, String) {
+        // Greeting, then a CONNECT to a domain name
+        let mut greeting = [0u8; 2];
+        stream.read_exact(&mut greeting).unwrap();
+        let mut methods = vec![0u8; greeting[1] as usize];
+        stream.read_exact(&mut methods).unwrap();
+        stream.write_all(&[5, 0]).unwrap();
+
+        let mut request = [0u8; 5];
+        stream.read_exact(&mut request).unwrap();
+        assert_eq!(request[3], 3);
+        let mut domain = vec![0u8; request[4] as usize + 2];
+        stream.read_exact(&mut domain).unwrap();
+        let port = u16::from_be_bytes([domain[domain.len() - 2], domain[domain.len() - 1]]);
+        let target = format!(
+            "{}:{port}",
+            String::from_utf8_lossy(&domain[..domain.len() - 2])
+        );
+        stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut authorization = String::new();
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Authorization: ") {
+                authorization = value.trim().to_string();
+            }
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body).unwrap();
+        let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(request["method"], "estimatesmartfee");
+
+        let response = serde_json::json!({
+            "result": { "feerate": 0.0002, "blocks": 6 },
+            "error": null,
+            "id": request["id"],
+        })
+        .to_string();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{response}",
+            response.len()
+        )
+        .unwrap();
+
+        (target, authorization)
+    }
+
+    #[test]
+    fn test_parse_http_url() {
+        assert_eq!(
+            parse_http_url("http://bitcoind.onion:8332").unwrap(),
+            ("bitcoind.onion".to_string(), 8332, "/".to_string())
+        );
+        assert_eq!(
+            parse_http_url("http://127.0.0.1:8332/wallet/envoy").unwrap(),
+            ("127.0.0.1".to_string(), 8332, "/wallet/envoy".to_string())
+        );
+        assert!(parse_http_url("https://bitcoind.onion:8332").is_err());
+        assert!(parse_http_url("http://bitcoind.onion").is_err());
+    }
+
+    #[test]
+    fn test_rpc_over_socks() {
+        let (port, seen) = start_mock_proxy();
+
+        let client = get_rpc_client(
+            "http://bitcoind.onion:8332",
+            Auth::UserPass {
+                username: "alice".to_string(),
+                password: "secret".to_string(),
+            },
+            port as i32,
+        )
+        .unwrap();
+
+        let estimate = client.estimate_smart_fee(6, None).unwrap();
+        assert_eq!(estimate.fee_rate.unwrap().to_btc(), 0.0002);
+
+        let (target, authorization) = seen.recv().unwrap();
+        assert_eq!(target, "bitcoind.onion:8332");
+        assert_eq!(
+            authorization,
+            format!("Basic {}", base64::encode("alice:secret"))
+        );
+    }
+
+    #[test]
+    fn test_relay_over_socks() {
+        let (port, seen) = start_mock_proxy();
+
+        let relay = SocksRelay::start(
+            util::get_socks5_address(port as i32).unwrap(),
+            "http://bitcoind.onion:8332",
+        )
+        .unwrap();
+        assert!(relay.url().starts_with("http://127.0.0.1:"));
+        assert!(!relay.url().ends_with('/'));
+
+        // A plain client, like the one BDK's RPC sync makes for itself
+        let client = RpcClient::new(
+            &relay.url(),
+            RpcAuth::UserPass("alice".to_string(), "secret".to_string()),
+        )
+        .unwrap();
+
+        let estimate = client.estimate_smart_fee(6, None).unwrap();
+        assert_eq!(estimate.fee_rate.unwrap().to_btc(), 0.0002);
+
+        let (target, authorization) = seen.recv().unwrap();
+        assert_eq!(target, "bitcoind.onion:8332");
+        assert_eq!(
+            authorization,
+            format!("Basic {}", base64::encode("alice:secret"))
+        );
+    }
+}
@@ -0,0 +1,66 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::update_last_error;
+use allo_isolate::Isolate;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::json;
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinHandle;
+
+lazy_static! {
+    static ref RUNTIME: io::Result<Runtime> = Builder::new_multi_thread().enable_all().build();
+}
+
+// What Dart holds for a spawned call until it hands it to wallet_task_free
+pub struct Task {
+    handle: JoinHandle<()>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Task {
+    // Drops the result and raises the flag the task checks between steps. Calls that can't
+    // check it, like a single electrum-client request, carry on until their connection gives up
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.handle.abort();
+    }
+}
+
+// Run a blocking network call off the caller's thread and post {"result": ...} or
+// {"error": "..."} to Dart when it's done. The task is passed its cancellation flag.
+// Null if there's no runtime to run it on, with the reason in LAST_ERROR
+pub fn spawn<T, F>(isolate_port: i64, task: F) -> *mut Task
+where
+    F: FnOnce(&AtomicBool) -> Result<T, String> + Send + 'static,
+    T: Serialize + Send + 'static,
+{
+    let rt = match RUNTIME.as_ref() {
+        Ok(rt) => rt,
+        Err(e) => {
+            update_last_error(bdk::Error::Generic(e.to_string()));
+            return ptr::null_mut();
+        }
+    };
+
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let flag = cancelled.clone();
+
+    let handle = rt.spawn(async move {
+        let message = match tokio::task::spawn_blocking(move || task(&flag)).await {
+            Ok(Ok(result)) => json!({ "result": result }),
+            Ok(Err(error)) => json!({ "error": error }),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+
+        Isolate::new(isolate_port).post(message.to_string());
+    });
+
+    Box::into_raw(Box::new(Task { handle, cancelled }))
+}
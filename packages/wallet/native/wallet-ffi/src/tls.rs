@@ -0,0 +1,482 @@
+// SPDX-FileCopyrightText: 2022 Foundation Devices Inc.
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::electrum::ElectrumClient;
+use crate::{util, PinningMode, SyncConfig};
+use bdk::database::any::SledDbConfiguration;
+use bdk::database::ConfigurableDatabase;
+use bdk::electrum_client;
+use bdk::electrum_client::raw_client::{ElectrumSslStream, RawClient};
+use bitcoin_hashes::hex::ToHex;
+use bitcoin_hashes::{sha256, Hash};
+use lazy_static::lazy_static;
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, ClientConnection, ServerName, StreamOwned};
+use sled::Tree;
+use std::convert::TryFrom;
+use std::io;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+// Stable prefixes so the app can tell these apart from connection errors
+pub const CERTIFICATE_CHANGED: &str = "Certificate changed";
+pub const CERTIFICATE_NOT_PINNED: &str = "Certificate not pinned";
+
+struct PinStore {
+    data_dir: String,
+    tree: Tree,
+    mode: PinningMode,
+}
+
+lazy_static! {
+    static ref PIN_STORE: Mutex<Option<PinStore>> = Mutex::new(None);
+}
+
+// Pins live next to the wallets, in their own database shared by all of them
+pub fn set_pinning(data_dir: &str, mode: PinningMode) -> Result<(), bdk::Error> {
+    let mut store = PIN_STORE
+        .lock()
+        .map_err(|e| bdk::Error::Generic(e.to_string()))?;
+
+    match store.as_mut() {
+        // sled refuses to open the same database twice in one process
+        Some(s) if s.data_dir == data_dir => s.mode = mode,
+        _ => {
+            let tree = Tree::from_config(&SledDbConfiguration {
+                path: Path::new(data_dir)
+                    .join("electrum_pins")
+                    .to_string_lossy()
+                    .to_string(),
+                tree_name: "pins".to_string(),
+            })?;
+
+            *store = Some(PinStore {
+                data_dir: data_dir.to_string(),
+                tree,
+                mode,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn get_pin_tree() -> Result<Tree, bdk::Error> {
+    match PIN_STORE.lock() {
+        Ok(store) => match store.as_ref() {
+            Some(s) => Ok(s.tree.clone()),
+            None => Err(bdk::Error::Generic(
+                "Certificate pinning isn't set up".to_string(),
+            )),
+        },
+        Err(e) => Err(bdk::Error::Generic(e.to_string())),
+    }
+}
+
+pub fn get_pin(server: &str) -> Result<Option<String>, bdk::Error> {
+    let pin = get_pin_tree()?.get(server)?;
+    Ok(pin.map(|p| String::from_utf8_lossy(&p).to_string()))
+}
+
+// Also how the user accepts a changed certificate after checking it out of band
+pub fn set_pin(server: &str, fingerprint: &str) -> Result<(), bdk::Error> {
+    let tree = get_pin_tree()?;
+    tree.insert(server, fingerprint.to_lowercase().as_bytes())?;
+    tree.flush()?;
+    Ok(())
+}
+
+pub fn remove_pin(server: &str) -> Result<(), bdk::Error> {
+    let tree = get_pin_tree()?;
+    tree.remove(server)?;
+    tree.flush()?;
+    Ok(())
+}
+
+// Only for reading a server's certificate to show the user, nothing is sent over it
+struct AcceptAnyCertificate;
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+// Checks the pin as part of the handshake of the connection we then talk to the server over
+struct PinningVerifier {
+    tree: Tree,
+    mode: PinningMode,
+    server: String,
+    // check_pin's error, which rustls would only pass on as a generic handshake failure
+    failure: Mutex<Option<String>>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = sha256::Hash::hash(&end_entity.0).to_hex();
+
+        match check_pin(&self.tree, self.mode, &self.server, &fingerprint) {
+            Ok(()) => Ok(ServerCertVerified::assertion()),
+            Err(e) => {
+                let message = e.to_string();
+                if let Ok(mut failure) = self.failure.lock() {
+                    *failure = Some(message.clone());
+                }
+                Err(rustls::Error::General(message))
+            }
+        }
+    }
+}
+
+fn split_host_port(host_port: &str) -> Option<(String, u16)> {
+    let (host, port) = host_port.rsplit_once(':')?;
+    Some((host.to_string(), port.parse().ok()?))
+}
+
+// Host and port of an ssl:// address, None for plain TCP which has no certificate to pin
+fn parse_ssl_address(electrum_address: &str) -> Option<(String, u16)> {
+    split_host_port(electrum_address.trim().strip_prefix("ssl://")?)
+}
+
+// Host and port of a tcp:// address, the scheme being optional as it is for electrum-client
+fn parse_tcp_address(electrum_address: &str) -> Option<(String, u16)> {
+    let address = electrum_address.trim();
+    split_host_port(address.strip_prefix("tcp://").unwrap_or(address))
+}
+
+fn connect(host: &str, port: u16, tor_port: i32, timeout: u8) -> io::Result<TcpStream> {
+    let stream = match util::get_socks5_address(tor_port) {
+        Some(proxy) => socks::Socks5Stream::connect(proxy.as_str(), (host, port))?.into_inner(),
+        None => TcpStream::connect((host, port))?,
+    };
+
+    let timeout = Some(Duration::from_secs(timeout as u64));
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+
+    Ok(stream)
+}
+
+fn handshake(
+    host: &str,
+    port: u16,
+    tor_port: i32,
+    sync_config: &SyncConfig,
+    verifier: Arc<dyn ServerCertVerifier>,
+) -> Result<ElectrumSslStream, bdk::Error> {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth();
+
+    // The name only matters for SNI here, IP addresses get a placeholder
+    let server_name = ServerName::try_from(host)
+        .or_else(|_| ServerName::try_from("electrum"))
+        .map_err(|e| bdk::Error::Generic(e.to_string()))?;
+
+    let mut connection = ClientConnection::new(Arc::new(config), server_name)
+        .map_err(|e| bdk::Error::Generic(e.to_string()))?;
+
+    let mut stream = connect(
+        host,
+        port,
+        tor_port,
+        util::get_timeout(tor_port, sync_config),
+    )
+    .map_err(|e| bdk::Error::Generic(e.to_string()))?;
+
+    while connection.is_handshaking() {
+        connection
+            .complete_io(&mut stream)
+            .map_err(|e| bdk::Error::Generic(e.to_string()))?;
+    }
+
+    Ok(StreamOwned::new(connection, stream))
+}
+
+// SHA-256 of the DER encoded leaf certificate, hex encoded
+pub fn get_fingerprint(
+    electrum_address: &str,
+    tor_port: i32,
+    sync_config: &SyncConfig,
+) -> Result<String, bdk::Error> {
+    let (host, port) = parse_ssl_address(electrum_address)
+        .ok_or_else(|| bdk::Error::Generic("Not an ssl:// Electrum address".to_string()))?;
+
+    let stream = handshake(
+        &host,
+        port,
+        tor_port,
+        sync_config,
+        Arc::new(AcceptAnyCertificate),
+    )?;
+
+    let certificate = stream
+        .conn
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+        .ok_or_else(|| bdk::Error::Generic("Server sent no certificate".to_string()))?;
+
+    Ok(sha256::Hash::hash(&certificate.0).to_hex())
+}
+
+fn check_pin(
+    tree: &Tree,
+    mode: PinningMode,
+    server: &str,
+    fingerprint: &str,
+) -> Result<(), electrum_client::Error> {
+    let pinned = tree
+        .get(server)
+        .map_err(|e| electrum_client::Error::Message(e.to_string()))?
+        .map(|p| String::from_utf8_lossy(&p).to_string());
+
+    match (pinned, mode) {
+        (Some(pinned), _) if pinned == fingerprint => Ok(()),
+        (Some(pinned), _) => Err(electrum_client::Error::Message(format!(
+            "{CERTIFICATE_CHANGED} for {server}: pinned {pinned}, got {fingerprint}"
+        ))),
+        (None, PinningMode::TrustOnFirstUse) => {
+            tree.insert(server, fingerprint.as_bytes())
+                .and_then(|_| tree.flush())
+                .map_err(|e| electrum_client::Error::Message(e.to_string()))?;
+            Ok(())
+        }
+        (None, _) => Err(electrum_client::Error::Message(format!(
+            "{CERTIFICATE_NOT_PINNED} for {server}: got {fingerprint}"
+        ))),
+    }
+}
+
+// A client for an ssl:// server whose certificate passed the pin check during the handshake.
+// None when there's nothing to pin, for plain TCP or with pinning off
+pub fn connect_pinned(
+    electrum_address: &str,
+    tor_port: i32,
+    sync_config: &SyncConfig,
+) -> Result<Option<RawClient<ElectrumSslStream>>, electrum_client::Error> {
+    let (host, port) = match parse_ssl_address(electrum_address) {
+        Some(host_port) => host_port,
+        None => return Ok(None),
+    };
+
+    let (tree, mode) = match PIN_STORE.lock() {
+        Ok(store) => match store.as_ref() {
+            Some(s) if s.mode != PinningMode::Off => (s.tree.clone(), s.mode),
+            _ => return Ok(None),
+        },
+        Err(e) => return Err(electrum_client::Error::Message(e.to_string())),
+    };
+
+    let verifier = Arc::new(PinningVerifier {
+        tree,
+        mode,
+        server: electrum_address.trim().to_string(),
+        failure: Mutex::new(None),
+    });
+
+    // A bad certificate isn't worth retrying, a dropped connection is
+    let mut attempt = 0;
+    loop {
+        match handshake(&host, port, tor_port, sync_config, verifier.clone()) {
+            Ok(stream) => return Ok(Some(RawClient::from(stream))),
+            Err(e) => {
+                let failure = verifier.failure.lock().ok().and_then(|mut f| f.take());
+                if failure.is_some() || attempt >= sync_config.retry {
+                    return Err(electrum_client::Error::Message(
+                        failure.unwrap_or_else(|| e.to_string()),
+                    ));
+                }
+            }
+        }
+
+        attempt += 1;
+    }
+}
+
+// A client over Tor with the configured timeout on its stream. Certificates aren't checked,
+// same as electrum-client's own client which doesn't validate domains either
+pub fn connect_proxied(
+    electrum_address: &str,
+    tor_port: i32,
+    sync_config: &SyncConfig,
+) -> Result<ElectrumClient, electrum_client::Error> {
+    let ssl = parse_ssl_address(electrum_address);
+    let (host, port) = match ssl.clone().or_else(|| parse_tcp_address(electrum_address)) {
+        Some(host_port) => host_port,
+        None => {
+            return Err(electrum_client::Error::Message(format!(
+                "Invalid Electrum address {electrum_address}"
+            )))
+        }
+    };
+
+    let mut attempt = 0;
+    loop {
+        let client = match ssl {
+            Some(_) => handshake(
+                &host,
+                port,
+                tor_port,
+                sync_config,
+                Arc::new(AcceptAnyCertificate),
+            )
+            .map(|stream| ElectrumClient::ProxiedSsl(RawClient::from(stream)))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string())),
+            None => connect(
+                &host,
+                port,
+                tor_port,
+                util::get_timeout(tor_port, sync_config),
+            )
+            .map(|stream| ElectrumClient::Proxied(RawClient::from(stream))),
+        };
+
+        match client {
+            Ok(client) => return Ok(client),
+            Err(e) if attempt >= sync_config.retry => {
+                return Err(electrum_client::Error::IOError(e))
+            }
+            Err(_) => attempt += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::{PrivateKey, ServerConfig, ServerConnection};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const SERVER: &str = "ssl://electrum.example:50002";
+
+    // Self-signed P-256 certificate and PKCS#8 key, DER as base64, for the server below
+    const CERTIFICATE: &str = "MIIBjjCCATOgAwIBAgIUOo7skW34Zv3UGUt6jW/fhs67QJwwCgYIKoZIzj0EAwIwGzEZMBcGA1UEAwwQZWxlY3RydW0uZXhhbXBsZTAgFw0yNjEwMTgwODM0MTFaGA8yMTI2MDkyNDA4MzQxMVowGzEZMBcGA1UEAwwQZWxlY3RydW0uZXhhbXBsZTBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABO4FXP6oIBZUcc68DKy/LGqXIgo3Zby5gdEusF4F1cqQ6r2iKAd8JpiDgDZjtUxTI6vA3awhy7O8BVmFmga2Qp2jUzBRMB0GA1UdDgQWBBQQ8j+CpLWpYhRFm7lRtL4kcB2x/zAfBgNVHSMEGDAWgBQQ8j+CpLWpYhRFm7lRtL4kcB2x/zAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0kAMEYCIQDRE3Mn8rLiAmybDfE65vsViyVnD0OpB9I6Hfad6rDc+gIhAJfxyTOAXGI89C0hXaxex/a+l7DaF7slVKs5FK3uWPkq";
+    const KEY: &str = "MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQg7cb+aFNy/b9vGbUIVYy7UVz54M+Yz8b84qvoIKKSQ8GhRANCAATuBVz+qCAWVHHOvAysvyxqlyIKN2W8uYHRLrBeBdXKkOq9oigHfCaYg4A2Y7VMUyOrwN2sIcuzvAVZhZoGtkKd";
+
+    // Completes TLS handshakes and nothing more, one connection at a time
+    fn start_tls_server() -> String {
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(base64::decode(CERTIFICATE).unwrap())],
+                PrivateKey(base64::decode(KEY).unwrap()),
+            )
+            .unwrap();
+        let config = Arc::new(config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut connection = ServerConnection::new(config.clone()).unwrap();
+                while connection.is_handshaking() {
+                    if connection.complete_io(&mut stream).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        format!("ssl://{address}")
+    }
+
+    #[test]
+    fn test_parse_ssl_address() {
+        assert_eq!(
+            parse_ssl_address(SERVER),
+            Some(("electrum.example".to_string(), 50002))
+        );
+        assert_eq!(parse_ssl_address("tcp://electrum.example:50001"), None);
+        assert_eq!(parse_ssl_address("electrum.example:50001"), None);
+
+        assert_eq!(
+            parse_tcp_address("tcp://electrum.example:50001"),
+            Some(("electrum.example".to_string(), 50001))
+        );
+        assert_eq!(
+            parse_tcp_address("electrum.example:50001"),
+            Some(("electrum.example".to_string(), 50001))
+        );
+    }
+
+    #[test]
+    fn test_check_pin() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree("pins").unwrap();
+
+        assert!(check_pin(&tree, PinningMode::Strict, SERVER, "aa")
+            .unwrap_err()
+            .to_string()
+            .starts_with(CERTIFICATE_NOT_PINNED));
+
+        // First use pins, later uses compare
+        check_pin(&tree, PinningMode::TrustOnFirstUse, SERVER, "aa").unwrap();
+        check_pin(&tree, PinningMode::TrustOnFirstUse, SERVER, "aa").unwrap();
+        check_pin(&tree, PinningMode::Strict, SERVER, "aa").unwrap();
+
+        let error = check_pin(&tree, PinningMode::TrustOnFirstUse, SERVER, "bb").unwrap_err();
+        assert!(error.to_string().starts_with(CERTIFICATE_CHANGED));
+
+        // The old pin stays until replaced explicitly
+        assert_eq!(tree.get(SERVER).unwrap().unwrap().as_ref(), b"aa");
+    }
+
+    #[test]
+    fn test_connect_pinned() {
+        let server = start_tls_server();
+        let data_dir = std::env::temp_dir().join(format!("envoy-pins-test-{}", std::process::id()));
+        let data_dir = data_dir.to_str().unwrap();
+        let sync_config = SyncConfig::default();
+
+        // Plain TCP has nothing to pin
+        set_pinning(data_dir, PinningMode::Strict).unwrap();
+        assert!(Path::new(data_dir).join("electrum_pins").exists());
+        assert!(connect_pinned("tcp://127.0.0.1:1", 0, &sync_config)
+            .unwrap()
+            .is_none());
+
+        // The handshake of the connection itself is checked
+        let error = connect_pinned(&server, 0, &sync_config).err().unwrap();
+        assert!(error.to_string().starts_with(CERTIFICATE_NOT_PINNED));
+
+        set_pinning(data_dir, PinningMode::TrustOnFirstUse).unwrap();
+        assert!(connect_pinned(&server, 0, &sync_config).unwrap().is_some());
+
+        let fingerprint = sha256::Hash::hash(&base64::decode(CERTIFICATE).unwrap()).to_hex();
+        assert_eq!(get_pin(&server).unwrap(), Some(fingerprint.clone()));
+        assert_eq!(
+            get_fingerprint(&server, 0, &sync_config).unwrap(),
+            fingerprint
+        );
+
+        set_pin(&server, &"00".repeat(32)).unwrap();
+        let error = connect_pinned(&server, 0, &sync_config).err().unwrap();
+        assert!(error.to_string().starts_with(CERTIFICATE_CHANGED));
+
+        remove_pin(&server).unwrap();
+    }
+}
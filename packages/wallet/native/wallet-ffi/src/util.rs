@@ -2,99 +2,127 @@
 //
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::coin_selection::{SelectionReport, Strategy};
+use crate::electrum::ElectrumClient;
 use crate::{
-    serialize, Address, Client, OutPoint, PartiallySignedTransaction, Psbt, Socks5Config, Txid,
-    UtxoList,
+    import, serialize, tls, Address, Client, CoinSelection, NetworkType, OutPoint,
+    PartiallySignedTransaction, Psbt, PsbtInput, PsbtOutput, RecipientList, ScriptType, SyncConfig,
+    Txid, UtxoList,
 };
-use bdk::blockchain::{ConfigurableBlockchain, ElectrumBlockchain, ElectrumBlockchainConfig};
-use bdk::database::BatchDatabase;
+use bdk::bitcoin::secp256k1::{All, Secp256k1};
+use bdk::bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, KeySource};
+use bdk::bitcoin::Script;
+use bdk::blockchain::ElectrumBlockchain;
+use bdk::database::{BatchDatabase, Database};
 use bdk::electrum_client;
 use bdk::electrum_client::ConfigBuilder;
+use bdk::keys::bip39::MnemonicWithPassphrase;
+use bdk::keys::{DerivableKey, DescriptorKey, ExtendedKey};
+use bdk::miniscript::descriptor::{DescriptorPublicKey, DescriptorType, Wildcard};
+use bdk::miniscript::{Legacy, ScriptContext, Segwitv0, Tap};
+use bdk::psbt::PsbtUtils;
 use bdk::wallet::tx_builder::TxOrdering;
 use bdk::wallet::AddressIndex;
-use bdk::{FeeRate, TransactionDetails};
+use bdk::{FeeRate, KeychainKind, TransactionDetails};
 use bip39::{Language, Mnemonic};
 use bitcoin_hashes::hex::ToHex;
 use sled::Tree;
 use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
 use std::str::FromStr;
-use std::sync::{Mutex, MutexGuard};
+use std::str::Utf8Error;
+use std::sync::{Arc, Mutex, MutexGuard};
 
-pub unsafe fn get_wallet_mutex(
-    wallet: *mut Mutex<bdk::Wallet<Tree>>,
-) -> &'static mut Mutex<bdk::Wallet<Tree>> {
-    let wallet = {
-        assert!(!wallet.is_null());
-        &mut *wallet
-    };
+// Starts the error when bumping would need more than the change output has, so the caller
+// can tell it apart from a transaction without change
+pub const FEE_BUMP_CHANGE_TOO_SMALL: &str = "Change can't absorb the higher fee";
+
+// Waits for any other call that's holding the wallet, a sync included
+pub fn lock_wallet(
+    wallet: &Mutex<bdk::Wallet<Tree>>,
+) -> Result<MutexGuard<'_, bdk::Wallet<Tree>>, bdk::Error> {
     wallet
+        .lock()
+        .map_err(|e| bdk::Error::Generic(e.to_string()))
 }
 
-fn get_electrum_blockchain_config(
-    tor_port: i32,
-    electrum_address: &str,
-) -> ElectrumBlockchainConfig {
+// Another reference to the wallet init() handed out, so a wallet_drop from another isolate
+// doesn't free the wallet while a call or task is still using it
+pub unsafe fn get_wallet_arc(
+    wallet: *mut Mutex<bdk::Wallet<Tree>>,
+) -> Result<Arc<Mutex<bdk::Wallet<Tree>>>, bdk::Error> {
+    if wallet.is_null() {
+        return Err(bdk::Error::Generic("Wallet isn't initialized".to_string()));
+    }
+
+    Arc::increment_strong_count(wallet);
+    Ok(Arc::from_raw(wallet))
+}
+
+// Seconds. Tor circuits are slow to build so they get longer
+pub fn get_timeout(tor_port: i32, sync_config: &SyncConfig) -> u8 {
+    match sync_config.timeout {
+        0 if tor_port > 0 => 30,
+        0 => 5,
+        t => t,
+    }
+}
+
+pub fn get_socks5_address(tor_port: i32) -> Option<String> {
     if tor_port > 0 {
-        ElectrumBlockchainConfig {
-            url: electrum_address.parse().unwrap(),
-            socks5: Some("127.0.0.1:".to_owned() + &tor_port.to_string()),
-            retry: 0,
-            timeout: None,
-            stop_gap: 50,
-            validate_domain: false,
-        }
+        Some("127.0.0.1:".to_owned() + &tor_port.to_string())
     } else {
-        ElectrumBlockchainConfig {
-            url: electrum_address.parse().unwrap(),
-            socks5: None,
-            retry: 0,
-            timeout: Some(5),
-            stop_gap: 50,
-            validate_domain: false,
-        }
+        None
     }
 }
 
+// BDK's sync, over a client that honours the certificate pins. BDK keeps its own stop gap
+// for a blockchain made from a client
 pub fn get_electrum_blockchain(
     tor_port: i32,
     electrum_address: &str,
+    sync_config: &SyncConfig,
 ) -> Result<ElectrumBlockchain, bdk::Error> {
-    let config = get_electrum_blockchain_config(tor_port, electrum_address);
-    ElectrumBlockchain::from_config(&config)
+    let client = get_electrum_client(tor_port, electrum_address, sync_config)?;
+    Ok(ElectrumBlockchain::from(Client::from(client)))
 }
 
 pub fn get_electrum_client(
     tor_port: i32,
     electrum_address: &str,
-) -> Result<Client, electrum_client::Error> {
-    let config: electrum_client::Config;
+    sync_config: &SyncConfig,
+) -> Result<ElectrumClient, electrum_client::Error> {
+    if let Some(client) = tls::connect_pinned(electrum_address, tor_port, sync_config)? {
+        return Ok(ElectrumClient::Pinned(client));
+    }
+
+    // electrum-client refuses a timeout along with a proxy, so over Tor we open the stream
     if tor_port > 0 {
-        let tor_config = Socks5Config {
-            addr: "127.0.0.1:".to_owned() + &tor_port.to_string(),
-            credentials: None,
-        };
-        config = ConfigBuilder::new()
-            .validate_domain(false)
-            .socks5(Some(tor_config))
-            .unwrap()
-            .build();
-    } else {
-        config = ConfigBuilder::new()
-            .validate_domain(false)
-            .socks5(None)
-            .unwrap()
-            .timeout(Some(5))
-            .unwrap()
-            .build();
+        return tls::connect_proxied(electrum_address, tor_port, sync_config);
     }
 
-    Client::from_config(electrum_address, config)
+    let config = ConfigBuilder::new()
+        .validate_domain(false)
+        .timeout(Some(get_timeout(tor_port, sync_config)))?
+        .retry(sync_config.retry)
+        .build();
+
+    Client::from_config(electrum_address, config).map(ElectrumClient::Client)
 }
 
 pub fn psbt_extract_details<T: BatchDatabase>(
     wallet: &bdk::Wallet<T>,
     psbt: &PartiallySignedTransaction,
-) -> Psbt {
+) -> Result<Psbt, bdk::Error> {
+    psbt_extract_details_with_selection(wallet, psbt, None)
+}
+
+// Same, plus why coin selection picked each input when it ran for this PSBT
+pub fn psbt_extract_details_with_selection<T: BatchDatabase>(
+    wallet: &bdk::Wallet<T>,
+    psbt: &PartiallySignedTransaction,
+    selection: Option<&SelectionReport>,
+) -> Result<Psbt, bdk::Error> {
     let tx = psbt.clone().extract_tx();
     let raw_tx = serialize::<bdk::bitcoin::Transaction>(&tx).to_hex();
 
@@ -112,26 +140,95 @@ pub fn psbt_extract_details<T: BatchDatabase>(
         .map(|o| o.value)
         .sum();
 
-    let inputs_value: u64 = psbt
-        .inputs
-        .iter()
-        .map(|i| match &i.witness_utxo {
+    // Legacy inputs only carry the full previous transaction
+    let inputs_value: u64 = (0..psbt.inputs.len())
+        .map(|i| match psbt.get_utxo_for(i) {
             None => 0,
-            Some(tx) => tx.value,
+            Some(txout) => txout.value,
         })
         .sum();
 
+    // Paying out more than the inputs are worth means one of them didn't say what it's worth
+    let fee = inputs_value
+        .checked_sub(sent)
+        .and_then(|value| value.checked_sub(received))
+        .ok_or_else(|| {
+            bdk::Error::Generic("PSBT inputs are worth less than its outputs".to_string())
+        })?;
+
+    let outputs: Vec<PsbtOutput> = tx
+        .output
+        .iter()
+        .map(|o| {
+            let address = Address::from_script(&o.script_pubkey, wallet.network())
+                .map(|a| a.to_string())
+                .unwrap_or_default();
+            let keychain = wallet
+                .database()
+                .get_path_from_script_pubkey(&o.script_pubkey)
+                .unwrap_or(None)
+                .map(|(keychain, _)| keychain);
+
+            PsbtOutput {
+                address: CString::new(address).unwrap().into_raw(),
+                amount: o.value,
+                is_mine: keychain.is_some(),
+                is_change: keychain == Some(KeychainKind::Internal),
+            }
+        })
+        .collect();
+
+    let outputs_len = outputs.len() as u32;
+    let outputs_ptr = Box::into_raw(outputs.into_boxed_slice());
+
+    let inputs: Vec<PsbtInput> = tx
+        .input
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            let reason = selection
+                .and_then(|s| s.reasons.get(&input.previous_output))
+                .cloned()
+                .unwrap_or_default();
+
+            PsbtInput {
+                txid: CString::new(input.previous_output.txid.to_hex())
+                    .unwrap()
+                    .into_raw(),
+                vout: input.previous_output.vout,
+                amount: psbt.get_utxo_for(i).map(|txout| txout.value).unwrap_or(0),
+                reason: CString::new(reason).unwrap().into_raw(),
+            }
+        })
+        .collect();
+
+    let inputs_len = inputs.len() as u32;
+    let inputs_ptr = Box::into_raw(inputs.into_boxed_slice());
+
+    let coin_selection = selection.map(|s| s.strategy.clone()).unwrap_or_default();
+    let finalized = psbt
+        .inputs
+        .iter()
+        .all(|input| input.final_script_sig.is_some() || input.final_script_witness.is_some());
+
     let encoded = base64::encode(&serialize(&psbt));
     let psbt = CString::new(encoded).unwrap().into_raw();
 
-    return Psbt {
+    Ok(Psbt {
         sent,
         received,
-        fee: inputs_value - sent - received,
+        fee,
         base64: psbt,
         txid: CString::new(tx.txid().to_hex()).unwrap().into_raw(),
         raw_tx: CString::new(raw_tx).unwrap().into_raw(),
-    };
+        outputs_len,
+        outputs: outputs_ptr as _,
+        coin_selection: CString::new(coin_selection).unwrap().into_raw(),
+        inputs_len,
+        inputs: inputs_ptr as _,
+        finalized,
+        inputs_added: false,
+    })
 }
 
 pub unsafe fn extract_utxo_list(utxos: *const UtxoList) -> Vec<OutPoint> {
@@ -148,25 +245,168 @@ pub unsafe fn extract_utxo_list(utxos: *const UtxoList) -> Vec<OutPoint> {
     must_spend
 }
 
+// Recipients with fixed amounts, and the one getting the rest of the wallet if any
+pub unsafe fn extract_recipient_list(
+    recipients: *const RecipientList,
+) -> Result<(Vec<(Script, u64)>, Option<Script>), bdk::Error> {
+    let mut fixed = vec![];
+    let mut send_max = None;
+
+    for i in 0..(*recipients).recipients_len as isize {
+        let recipient = &*(*recipients).recipients.offset(i);
+
+        let address = CStr::from_ptr(recipient.address)
+            .to_str()
+            .map_err(|e| bdk::Error::Generic(e.to_string()))?;
+        let script = Address::from_str(address)
+            .map_err(|e| bdk::Error::Generic(e.to_string()))?
+            .script_pubkey();
+
+        if !recipient.send_max {
+            fixed.push((script, recipient.amount));
+        } else if send_max.replace(script).is_some() {
+            return Err(bdk::Error::Generic(
+                "Only one output can send max".to_string(),
+            ));
+        }
+    }
+
+    if fixed.is_empty() && send_max.is_none() {
+        return Err(bdk::Error::NoRecipients);
+    }
+
+    Ok((fixed, send_max))
+}
+
+pub unsafe fn extract_string_list(
+    len: u8,
+    strings: *const *const c_char,
+) -> Result<Vec<&'static str>, Utf8Error> {
+    let mut list = vec![];
+
+    for i in 0..len as isize {
+        list.push(CStr::from_ptr(*strings.offset(i)).to_str()?);
+    }
+
+    Ok(list)
+}
+
+// Get (external, internal) wsh(sortedmulti) descriptors from key-origin xpubs
+pub fn get_multisig_descriptors(
+    threshold: usize,
+    xpubs: &[&str],
+) -> Result<(String, String), bdk::Error> {
+    if threshold == 0 || threshold > xpubs.len() {
+        return Err(bdk::Error::Generic(format!(
+            "Invalid threshold {} for {} keys",
+            threshold,
+            xpubs.len()
+        )));
+    }
+
+    let mut keys = vec![];
+
+    for xpub in xpubs {
+        let key = DescriptorPublicKey::from_str(&import::normalize_key_expression(xpub))
+            .map_err(|e| bdk::Error::Generic(e.to_string()))?;
+
+        match &key {
+            DescriptorPublicKey::XPub(xkey)
+                if xkey.origin.is_some()
+                    && xkey.derivation_path.is_master()
+                    && xkey.wildcard == Wildcard::None => {}
+            _ => {
+                return Err(bdk::Error::Generic(format!(
+                    "Expected an xpub with key origin and no derivation: {}",
+                    xpub
+                )));
+            }
+        }
+
+        keys.push(format!("{key}/0/*"));
+    }
+
+    let external_descriptor = format!("wsh(sortedmulti({},{}))", threshold, keys.join(","));
+    let internal_descriptor = external_descriptor.replace("/0/*", "/1/*");
+
+    Ok((external_descriptor, internal_descriptor))
+}
+
+// Pays every recipient, and whatever is left of the wallet after fees to drain_to if given
 pub fn build_tx(
-    amount: u64,
+    recipients: &[(Script, u64)],
+    drain_to: Option<Script>,
     fee_rate: f64,
     fee_absolute: Option<u64>,
-    wallet: &MutexGuard<bdk::Wallet<Tree>>,
-    send_to: Address,
+    wallet: &bdk::Wallet<Tree>,
     must_spend: &Vec<OutPoint>,
     dont_spend: &Vec<OutPoint>,
 ) -> Result<(PartiallySignedTransaction, TransactionDetails), bdk::Error> {
-    let mut builder = wallet.build_tx();
+    build_tx_with_coin_selection(
+        recipients,
+        drain_to,
+        fee_rate,
+        fee_absolute,
+        CoinSelection::BranchAndBound,
+        wallet,
+        must_spend,
+        dont_spend,
+    )
+    .map(|(psbt, details, _)| (psbt, details))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn build_tx_with_coin_selection(
+    recipients: &[(Script, u64)],
+    drain_to: Option<Script>,
+    fee_rate: f64,
+    fee_absolute: Option<u64>,
+    coin_selection: CoinSelection,
+    wallet: &bdk::Wallet<Tree>,
+    must_spend: &Vec<OutPoint>,
+    dont_spend: &Vec<OutPoint>,
+) -> Result<
+    (
+        PartiallySignedTransaction,
+        TransactionDetails,
+        SelectionReport,
+    ),
+    bdk::Error,
+> {
+    // Send max makes every coin required, which mixes tags as soon as there are two
+    if coin_selection == CoinSelection::Privacy && drain_to.is_some() && must_spend.is_empty() {
+        return Err(bdk::Error::Generic(
+            "Privacy coin selection can only send max from coins picked with coin control"
+                .to_string(),
+        ));
+    }
+
+    let (strategy, report) = Strategy::new(coin_selection, &wallet.database())?;
+
+    let mut builder = wallet.build_tx().coin_selection(strategy);
     builder
         .change_address_index(AddressIndex::Current)
         .ordering(TxOrdering::Shuffle)
-        .only_witness_utxo()
-        .add_recipient(send_to.script_pubkey(), amount)
+        .set_recipients(recipients.to_vec())
         .enable_rbf()
         .add_utxos(&*must_spend)
         .unwrap();
 
+    // Drain the coins picked by the user if any, everything not excluded otherwise
+    if let Some(script) = drain_to {
+        if must_spend.is_empty() {
+            builder.drain_wallet();
+        } else {
+            builder.manually_selected_only();
+        }
+        builder.drain_to(script);
+    }
+
+    // Legacy inputs can only be signed with the full previous transaction
+    if is_segwit(wallet) {
+        builder.only_witness_utxo();
+    }
+
     match fee_absolute {
         None => {
             builder.fee_rate(FeeRate::from_sat_per_vb((fee_rate * 100000.0) as f32));
@@ -180,7 +420,264 @@ pub fn build_tx(
         builder.add_unspendable(*outpoint);
     }
 
-    builder.finish()
+    let (psbt, details) = builder.finish()?;
+    let report = report.borrow().clone();
+
+    Ok((psbt, details, report))
+}
+
+// Value of the coins a transaction with this coin control can spend
+pub fn get_spendable_value(
+    wallet: &bdk::Wallet<Tree>,
+    must_spend: &Vec<OutPoint>,
+    dont_spend: &Vec<OutPoint>,
+) -> Result<u64, bdk::Error> {
+    Ok(wallet
+        .list_unspent()?
+        .iter()
+        .filter(|utxo| must_spend.is_empty() || must_spend.contains(&utxo.outpoint))
+        .filter(|utxo| !dont_spend.contains(&utxo.outpoint))
+        .map(|utxo| utxo.txout.value)
+        .sum())
+}
+
+// Highest fee rate, in sat/vB, at which the coins can still pay every recipient in full
+pub fn get_max_fee_rate(
+    recipients: &[(Script, u64)],
+    wallet: &bdk::Wallet<Tree>,
+    must_spend: &Vec<OutPoint>,
+    dont_spend: &Vec<OutPoint>,
+) -> Result<f64, bdk::Error> {
+    let spendable = get_spendable_value(wallet, must_spend, dont_spend)?;
+    let total: u64 = recipients.iter().map(|(_, amount)| amount).sum();
+    let fee = spendable
+        .checked_sub(total)
+        .ok_or(bdk::Error::InsufficientFunds {
+            needed: total,
+            available: spendable,
+        })?;
+
+    let (psbt, _) = build_tx(
+        recipients,
+        None,
+        0.0,
+        Some(fee),
+        wallet,
+        must_spend,
+        dont_spend,
+    )?;
+
+    psbt.fee_rate()
+        .map(|rate| rate.as_sat_per_vb() as f64)
+        .ok_or_else(|| bdk::Error::Generic("Missing input values".to_string()))
+}
+
+// Amount a send max transaction to send_to would pay at this fee rate
+pub fn get_max_spendable(
+    send_to: Address,
+    fee_rate: f64,
+    wallet: &bdk::Wallet<Tree>,
+    must_spend: &Vec<OutPoint>,
+    dont_spend: &Vec<OutPoint>,
+) -> Result<u64, bdk::Error> {
+    let script_pubkey = send_to.script_pubkey();
+    let (psbt, _) = build_tx(
+        &[],
+        Some(script_pubkey.clone()),
+        fee_rate,
+        None,
+        wallet,
+        must_spend,
+        dont_spend,
+    )?;
+
+    Ok(psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .filter(|output| output.script_pubkey == script_pubkey)
+        .map(|output| output.value)
+        .sum())
+}
+
+// Replace an unconfirmed transaction of ours with one paying the new fee rate. Also says
+// whether it spends coins the original didn't, which BDK adds without telling us when the
+// change can't absorb the higher fee
+pub fn build_fee_bump(
+    txid: Txid,
+    fee_rate: f64,
+    wallet: &bdk::Wallet<Tree>,
+    must_spend: &Vec<OutPoint>,
+    dont_spend: &Vec<OutPoint>,
+) -> Result<(PartiallySignedTransaction, TransactionDetails, bool), bdk::Error> {
+    let original_inputs: Vec<OutPoint> = wallet
+        .get_tx(&txid, true)?
+        .and_then(|details| details.transaction)
+        .map(|tx| tx.input.iter().map(|input| input.previous_output).collect())
+        .unwrap_or_default();
+
+    let mut builder = wallet.build_fee_bump(txid)?;
+    builder
+        .fee_rate(FeeRate::from_sat_per_vb((fee_rate * 100000.0) as f32))
+        .enable_rbf()
+        .add_utxos(must_spend)?;
+
+    if is_segwit(wallet) {
+        builder.only_witness_utxo();
+    }
+
+    for outpoint in dont_spend {
+        builder.add_unspendable(*outpoint);
+    }
+
+    // BDK takes the fee out of change first and only then looks for more inputs
+    let (psbt, details) = builder.finish().map_err(|e| match e {
+        bdk::Error::InsufficientFunds { needed, available } if has_change(wallet, &txid) => {
+            bdk::Error::Generic(format!(
+                "{FEE_BUMP_CHANGE_TOO_SMALL} and spendable coins can't cover it either: \
+                {available} sat available of {needed} sat needed"
+            ))
+        }
+        e => e,
+    })?;
+
+    let inputs_added = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .any(|input| !original_inputs.contains(&input.previous_output));
+
+    Ok((psbt, details, inputs_added))
+}
+
+fn has_change(wallet: &bdk::Wallet<Tree>, txid: &Txid) -> bool {
+    let tx = match wallet.get_tx(txid, true) {
+        Ok(Some(details)) => details.transaction,
+        _ => None,
+    };
+
+    tx.map(|tx| {
+        tx.output.iter().any(|output| {
+            matches!(
+                wallet
+                    .database()
+                    .get_path_from_script_pubkey(&output.script_pubkey),
+                Ok(Some((KeychainKind::Internal, _)))
+            )
+        })
+    })
+    .unwrap_or(false)
+}
+
+pub struct Descriptors {
+    pub external_prv: String,
+    pub internal_prv: String,
+    pub external_pub: String,
+    pub internal_pub: String,
+}
+
+pub fn get_master_xprv(
+    seed_words: &str,
+    passphrase: Option<&str>,
+    network: NetworkType,
+) -> Result<ExtendedPrivKey, bdk::Error> {
+    let mnemonic_words =
+        Mnemonic::parse(seed_words).map_err(|e| bdk::Error::Generic(e.to_string()))?;
+    let mnemonic: MnemonicWithPassphrase = (mnemonic_words, passphrase.map(|p| p.to_string()));
+
+    let xkey: ExtendedKey = mnemonic.into_extended_key()?;
+    xkey.into_xprv(network.into())
+        .ok_or_else(|| bdk::Error::Generic("Can't get master private key".to_string()))
+}
+
+// Get receive and change descriptors for an account path under the master key
+pub fn get_descriptors(
+    secp: &Secp256k1<All>,
+    xprv: &ExtendedPrivKey,
+    path: &DerivationPath,
+    script_type: ScriptType,
+) -> Result<Descriptors, bdk::Error> {
+    let derived_xprv = &xprv.derive_priv(secp, path)?;
+    let origin: KeySource = (xprv.fingerprint(secp), path.clone());
+
+    let descriptor_keys = match script_type {
+        ScriptType::P2wpkh => get_descriptor_keys::<Segwitv0>(secp, derived_xprv, origin),
+        ScriptType::P2tr => get_descriptor_keys::<Tap>(secp, derived_xprv, origin),
+        ScriptType::P2pkh => get_descriptor_keys::<Legacy>(secp, derived_xprv, origin),
+        ScriptType::P2shP2wpkh => get_descriptor_keys::<Segwitv0>(secp, derived_xprv, origin),
+    };
+
+    let (descriptor_prv, descriptor_pub) = descriptor_keys
+        .ok_or_else(|| bdk::Error::Generic("Can't get descriptor keys".to_string()))?;
+
+    let external_pub = script_type.wrap(&descriptor_pub).replace("/*", "/0/*");
+    let internal_pub = external_pub.replace("/0/*", "/1/*");
+
+    let external_prv = script_type.wrap(&descriptor_prv).replace("/*", "/0/*");
+    let internal_prv = external_prv.replace("/0/*", "/1/*");
+
+    Ok(Descriptors {
+        external_prv,
+        internal_prv,
+        external_pub,
+        internal_pub,
+    })
+}
+
+// Get the (private, public) descriptor key strings for a derived key in the given script context
+pub fn get_descriptor_keys<Ctx: ScriptContext>(
+    secp: &Secp256k1<All>,
+    derived_xprv: &ExtendedPrivKey,
+    origin: KeySource,
+) -> Option<(String, String)> {
+    let derived_xprv_desc_key: DescriptorKey<Ctx> = derived_xprv
+        .into_descriptor_key(Some(origin), DerivationPath::default())
+        .ok()?;
+
+    match derived_xprv_desc_key {
+        DescriptorKey::Public(_, _, _) => None,
+        DescriptorKey::Secret(desc_seckey, _, _) => {
+            let desc_pubkey = desc_seckey.to_public(secp).ok()?;
+            Some((desc_seckey.to_string(), desc_pubkey.to_string()))
+        }
+    }
+}
+
+pub fn is_segwit<T: BatchDatabase>(wallet: &bdk::Wallet<T>) -> bool {
+    !matches!(
+        wallet
+            .get_descriptor_for_keychain(KeychainKind::External)
+            .desc_type(),
+        DescriptorType::Bare
+            | DescriptorType::Pkh
+            | DescriptorType::Sh
+            | DescriptorType::ShSortedMulti
+    )
+}
+
+// Account is the third level of BIP44-style paths, e.g. m/49'/0'/<account>'
+pub fn get_account_index(path: &DerivationPath) -> u32 {
+    match path.as_ref().get(2) {
+        Some(ChildNumber::Hardened { index }) | Some(ChildNumber::Normal { index }) => *index,
+        None => 0,
+    }
+}
+
+pub fn get_wallet_name(
+    xfp: &str,
+    network: NetworkType,
+    script_type: ScriptType,
+    account: u32,
+) -> String {
+    let network_str: String = network.into();
+
+    // The first native segwit account keeps the original naming so existing databases are found
+    if script_type == ScriptType::P2wpkh && account == 0 {
+        return format!("{xfp}-{network_str}");
+    }
+
+    let script_type_str: String = script_type.into();
+    format!("{xfp}-{network_str}-{script_type_str}-{account}")
 }
 
 pub fn generate_mnemonic() -> (Mnemonic, String) {
@@ -189,3 +686,606 @@ pub fn generate_mnemonic() -> (Mnemonic, String) {
 
     (mnemonic, mnemonic_string)
 }
+
+// Empty tree in a sled database that's gone once the tree is dropped
+#[cfg(test)]
+pub fn get_test_tree() -> Tree {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    db.open_tree("wallet").unwrap()
+}
+
+// Watch-only P2WPKH wallet over a fresh test tree
+#[cfg(test)]
+pub fn get_test_wallet(network: bdk::bitcoin::Network) -> bdk::Wallet<Tree> {
+    bdk::Wallet::new(
+        "wpkh([ab88de89/84'/1'/0']tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp/0/*)",
+        Some("wpkh([ab88de89/84'/1'/0']tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp/1/*)"),
+        network,
+        get_test_tree(),
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Recipient;
+    use bdk::bitcoin::{PackedLockTime, Transaction, TxIn, TxOut};
+    use bdk::database::BatchOperations;
+    use bdk::{BlockTime, LocalUtxo};
+
+    #[test]
+    fn test_get_timeout() {
+        let sync_config = SyncConfig::default();
+        assert_eq!(get_timeout(0, &sync_config), 5);
+        assert_eq!(get_timeout(9050, &sync_config), 30);
+
+        // A configured timeout holds over Tor as well, on the stream we open through the proxy
+        let sync_config = SyncConfig {
+            timeout: 20,
+            ..sync_config
+        };
+        assert_eq!(get_timeout(0, &sync_config), 20);
+        assert_eq!(get_timeout(9050, &sync_config), 20);
+    }
+
+    #[test]
+    fn test_get_electrum_client_over_tor() {
+        // Nothing listening, so the proxy can't be reached
+        let tor_port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port() as i32
+        };
+
+        // An error to hand back over FFI rather than electrum-client's BothSocksAndTimeout panic
+        for address in [
+            "tcp://electrum.example:50001",
+            "ssl://electrum.example:50002",
+        ] {
+            assert!(get_electrum_client(tor_port, address, &SyncConfig::default()).is_err());
+        }
+    }
+
+    // Wallet with a confirmed coin of each value, paid to consecutive receive addresses
+    fn get_funded_wallet(values: &[u64]) -> (Mutex<bdk::Wallet<Tree>>, Vec<OutPoint>) {
+        let wallet = get_test_wallet(bdk::bitcoin::Network::Testnet);
+
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn::default()],
+            output: values
+                .iter()
+                .map(|value| TxOut {
+                    value: *value,
+                    script_pubkey: wallet
+                        .get_address(AddressIndex::New)
+                        .unwrap()
+                        .script_pubkey(),
+                })
+                .collect(),
+        };
+        let txid = funding.txid();
+
+        let mut database = wallet.database().clone();
+        let outpoints: Vec<OutPoint> = (0..values.len() as u32)
+            .map(|vout| OutPoint::new(txid, vout))
+            .collect();
+        for (outpoint, txout) in outpoints.iter().zip(funding.output.iter()) {
+            database
+                .set_utxo(&LocalUtxo {
+                    outpoint: *outpoint,
+                    txout: txout.clone(),
+                    keychain: KeychainKind::External,
+                    is_spent: false,
+                })
+                .unwrap();
+        }
+        database
+            .set_tx(&TransactionDetails {
+                transaction: Some(funding),
+                txid,
+                received: values.iter().sum(),
+                sent: 0,
+                fee: Some(1000),
+                confirmation_time: Some(BlockTime {
+                    height: 100,
+                    timestamp: 1_600_000_000,
+                }),
+            })
+            .unwrap();
+
+        (Mutex::new(wallet), outpoints)
+    }
+
+    #[test]
+    fn test_privacy_send_max() {
+        let (wallet, outpoints) = get_funded_wallet(&[50_000, 80_000]);
+        let wallet = wallet.lock().unwrap();
+        let drain_to = wallet
+            .get_address(AddressIndex::New)
+            .unwrap()
+            .script_pubkey();
+
+        let error = build_tx_with_coin_selection(
+            &[],
+            Some(drain_to.clone()),
+            0.00001,
+            None,
+            CoinSelection::Privacy,
+            &wallet,
+            &vec![],
+            &vec![],
+        )
+        .err()
+        .unwrap();
+        assert!(error
+            .to_string()
+            .contains("only send max from coins picked"));
+
+        // Fine once the coins are picked
+        let (_, details, report) = build_tx_with_coin_selection(
+            &[],
+            Some(drain_to),
+            0.00001,
+            None,
+            CoinSelection::Privacy,
+            &wallet,
+            &vec![outpoints[1]],
+            &vec![],
+        )
+        .unwrap();
+        assert_eq!(details.sent, 80_000);
+        assert_eq!(report.strategy, "privacy");
+    }
+
+    // Keeps the address strings alive for as long as the list is used
+    fn get_recipient_list(
+        recipients: &[(&str, u64, bool)],
+    ) -> (RecipientList, Vec<Recipient>, Vec<CString>) {
+        let addresses: Vec<CString> = recipients
+            .iter()
+            .map(|(address, _, _)| CString::new(*address).unwrap())
+            .collect();
+        let recipients: Vec<Recipient> = recipients
+            .iter()
+            .zip(addresses.iter())
+            .map(|((_, amount, send_max), address)| Recipient {
+                address: address.as_ptr(),
+                amount: *amount,
+                send_max: *send_max,
+            })
+            .collect();
+
+        (
+            RecipientList {
+                recipients_len: recipients.len() as u32,
+                recipients: recipients.as_ptr(),
+            },
+            recipients,
+            addresses,
+        )
+    }
+
+    unsafe fn get_outputs(psbt: &Psbt) -> Vec<(String, u64, bool, bool)> {
+        std::slice::from_raw_parts(psbt.outputs, psbt.outputs_len as usize)
+            .iter()
+            .map(|output| {
+                (
+                    CStr::from_ptr(output.address).to_str().unwrap().to_string(),
+                    output.amount,
+                    output.is_mine,
+                    output.is_change,
+                )
+            })
+            .collect()
+    }
+
+    const ALICE: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+    const BOB: &str = "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7";
+
+    #[test]
+    fn test_batch_recipients() {
+        let (wallet, _) = get_funded_wallet(&[100_000, 50_000]);
+        let wallet = wallet.lock().unwrap();
+        let own = wallet
+            .get_address(AddressIndex::Peek(5))
+            .unwrap()
+            .to_string();
+
+        let (list, _recipients, _addresses) = get_recipient_list(&[
+            (ALICE, 30_000, false),
+            (BOB, 40_000, false),
+            (&own, 5_000, false),
+        ]);
+        let (recipients, send_max) = unsafe { extract_recipient_list(&list) }.unwrap();
+        assert_eq!(recipients.len(), 3);
+        assert!(send_max.is_none());
+
+        let (psbt, details) =
+            build_tx(&recipients, None, 0.00001, None, &wallet, &vec![], &vec![]).unwrap();
+        let summary = psbt_extract_details(&wallet, &psbt).unwrap();
+        let mut outputs = unsafe { get_outputs(&summary) };
+        outputs.sort_by_key(|(_, amount, _, _)| *amount);
+
+        // Three recipients plus change
+        assert_eq!(outputs.len(), 4);
+        assert!(outputs.contains(&(ALICE.to_string(), 30_000, false, false)));
+        assert!(outputs.contains(&(BOB.to_string(), 40_000, false, false)));
+        assert!(outputs.contains(&(own, 5_000, true, false)));
+
+        let change = outputs
+            .iter()
+            .find(|(_, _, _, is_change)| *is_change)
+            .unwrap();
+        assert!(change.2);
+        assert_eq!(change.1, details.sent - 75_000 - details.fee.unwrap());
+    }
+
+    #[test]
+    fn test_psbt_missing_input_values() {
+        let (wallet, _) = get_funded_wallet(&[100_000]);
+        let wallet = wallet.lock().unwrap();
+
+        let (list, _recipients, _addresses) = get_recipient_list(&[(ALICE, 30_000, false)]);
+        let (recipients, _) = unsafe { extract_recipient_list(&list) }.unwrap();
+        let (mut psbt, _) =
+            build_tx(&recipients, None, 0.00001, None, &wallet, &vec![], &vec![]).unwrap();
+
+        for input in psbt.inputs.iter_mut() {
+            input.witness_utxo = None;
+            input.non_witness_utxo = None;
+        }
+
+        let error = psbt_extract_details(&wallet, &psbt).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Generic error: PSBT inputs are worth less than its outputs"
+        );
+    }
+
+    #[test]
+    fn test_batch_send_max() {
+        let (wallet, _) = get_funded_wallet(&[100_000, 50_000]);
+        let wallet = wallet.lock().unwrap();
+
+        let (list, _recipients, _addresses) =
+            get_recipient_list(&[(ALICE, 30_000, false), (BOB, 0, true)]);
+        let (recipients, send_max) = unsafe { extract_recipient_list(&list) }.unwrap();
+
+        let (psbt, details) = build_tx(
+            &recipients,
+            send_max,
+            0.00001,
+            None,
+            &wallet,
+            &vec![],
+            &vec![],
+        )
+        .unwrap();
+        let outputs = unsafe { get_outputs(&psbt_extract_details(&wallet, &psbt).unwrap()) };
+
+        // Everything not paid to Alice goes to Bob, no change
+        assert_eq!(outputs.len(), 2);
+        assert!(outputs.contains(&(ALICE.to_string(), 30_000, false, false)));
+        assert!(outputs.contains(&(
+            BOB.to_string(),
+            150_000 - 30_000 - details.fee.unwrap(),
+            false,
+            false
+        )));
+    }
+
+    #[test]
+    fn test_batch_two_send_max() {
+        let (list, _recipients, _addresses) =
+            get_recipient_list(&[(ALICE, 0, true), (BOB, 0, true)]);
+        let error = unsafe { extract_recipient_list(&list) }.err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Generic error: Only one output can send max"
+        );
+
+        let (list, _recipients, _addresses) = get_recipient_list(&[]);
+        assert!(matches!(
+            unsafe { extract_recipient_list(&list) },
+            Err(bdk::Error::NoRecipients)
+        ));
+    }
+
+    // Stores a transaction built from the wallet as if it had been broadcast
+    fn commit_unconfirmed(
+        wallet: &bdk::Wallet<Tree>,
+        psbt: &PartiallySignedTransaction,
+        details: TransactionDetails,
+    ) {
+        let tx = psbt.clone().extract_tx();
+        let mut database = wallet.database().clone();
+
+        for input in &tx.input {
+            let mut utxo = database.get_utxo(&input.previous_output).unwrap().unwrap();
+            utxo.is_spent = true;
+            database.set_utxo(&utxo).unwrap();
+        }
+
+        for (vout, output) in tx.output.iter().enumerate() {
+            if let Some((keychain, _)) = database
+                .get_path_from_script_pubkey(&output.script_pubkey)
+                .unwrap()
+            {
+                database
+                    .set_utxo(&LocalUtxo {
+                        outpoint: OutPoint::new(tx.txid(), vout as u32),
+                        txout: output.clone(),
+                        keychain,
+                        is_spent: false,
+                    })
+                    .unwrap();
+            }
+        }
+
+        database
+            .set_tx(&TransactionDetails {
+                transaction: Some(tx),
+                confirmation_time: None,
+                ..details
+            })
+            .unwrap();
+    }
+
+    fn get_alice_output(psbt: &PartiallySignedTransaction) -> u64 {
+        let alice = Address::from_str(ALICE).unwrap().script_pubkey();
+        psbt.unsigned_tx
+            .output
+            .iter()
+            .find(|output| output.script_pubkey == alice)
+            .unwrap()
+            .value
+    }
+
+    #[test]
+    fn test_fee_bump_with_change() {
+        let (wallet, _) = get_funded_wallet(&[100_000]);
+        let wallet = wallet.lock().unwrap();
+        let alice = Address::from_str(ALICE).unwrap().script_pubkey();
+
+        let (psbt, details) = build_tx(
+            &[(alice, 30_000)],
+            None,
+            0.00001,
+            None,
+            &wallet,
+            &vec![],
+            &vec![],
+        )
+        .unwrap();
+        let txid = details.txid;
+        let original_fee = details.fee.unwrap();
+        commit_unconfirmed(&wallet, &psbt, details);
+
+        // The change pays for it, nothing else changes
+        let (bumped, bumped_details, inputs_added) =
+            build_fee_bump(txid, 0.00005, &wallet, &vec![], &vec![]).unwrap();
+        assert!(!inputs_added);
+        assert_eq!(bumped.unsigned_tx.input.len(), 1);
+        assert_eq!(bumped.unsigned_tx.output.len(), 2);
+        assert_eq!(get_alice_output(&bumped), 30_000);
+        assert!(bumped_details.fee.unwrap() > 4 * original_fee);
+        assert_eq!(
+            bumped_details.received,
+            100_000 - 30_000 - bumped_details.fee.unwrap()
+        );
+
+        // Below the original rate
+        let error = build_fee_bump(txid, 0.000005, &wallet, &vec![], &vec![])
+            .err()
+            .unwrap();
+        assert!(matches!(error, bdk::Error::FeeRateTooLow { .. }));
+    }
+
+    #[test]
+    fn test_fee_bump_change_too_small() {
+        let (wallet, _) = get_funded_wallet(&[100_000]);
+        let wallet = wallet.lock().unwrap();
+        let alice = Address::from_str(ALICE).unwrap().script_pubkey();
+
+        let (psbt, details) = build_tx(
+            &[(alice, 98_000)],
+            None,
+            0.00001,
+            None,
+            &wallet,
+            &vec![],
+            &vec![],
+        )
+        .unwrap();
+        let txid = details.txid;
+        commit_unconfirmed(&wallet, &psbt, details);
+
+        let error = build_fee_bump(txid, 0.0005, &wallet, &vec![], &vec![])
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .starts_with(&format!("Generic error: {FEE_BUMP_CHANGE_TOO_SMALL}")));
+    }
+
+    #[test]
+    fn test_fee_bump_adds_inputs() {
+        let (wallet, outpoints) = get_funded_wallet(&[100_000, 50_000]);
+        let wallet = wallet.lock().unwrap();
+        let alice = Address::from_str(ALICE).unwrap().script_pubkey();
+
+        let (psbt, details) = build_tx(
+            &[(alice, 98_000)],
+            None,
+            0.00001,
+            None,
+            &wallet,
+            &vec![],
+            &vec![outpoints[1]],
+        )
+        .unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 1);
+        let txid = details.txid;
+        commit_unconfirmed(&wallet, &psbt, details);
+
+        // The change can't absorb this, so the other coin gets pulled in
+        let (bumped, _, inputs_added) =
+            build_fee_bump(txid, 0.0005, &wallet, &vec![], &vec![]).unwrap();
+        assert!(inputs_added);
+        assert_eq!(bumped.unsigned_tx.input.len(), 2);
+        assert_eq!(get_alice_output(&bumped), 98_000);
+    }
+
+    #[test]
+    fn test_fee_bump_without_change() {
+        let (wallet, outpoints) = get_funded_wallet(&[100_000, 50_000]);
+        let wallet = wallet.lock().unwrap();
+        let alice = Address::from_str(ALICE).unwrap().script_pubkey();
+
+        // Send max from the first coin
+        let (psbt, details) = build_tx(
+            &[],
+            Some(alice),
+            0.00001,
+            None,
+            &wallet,
+            &vec![],
+            &vec![outpoints[1]],
+        )
+        .unwrap();
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        let txid = details.txid;
+        let sent_to_alice = get_alice_output(&psbt);
+        commit_unconfirmed(&wallet, &psbt, details);
+
+        // Not even the other coin helps when it can't be spent
+        let error = build_fee_bump(txid, 0.00005, &wallet, &vec![], &vec![outpoints[1]])
+            .err()
+            .unwrap();
+        assert!(matches!(error, bdk::Error::InsufficientFunds { .. }));
+
+        // Otherwise it's added, and the rest of it comes back as change
+        let (bumped, bumped_details, inputs_added) =
+            build_fee_bump(txid, 0.00005, &wallet, &vec![], &vec![]).unwrap();
+        assert!(inputs_added);
+        assert_eq!(bumped.unsigned_tx.input.len(), 2);
+        assert_eq!(bumped.unsigned_tx.output.len(), 2);
+        assert_eq!(get_alice_output(&bumped), sent_to_alice);
+        assert_eq!(bumped_details.sent, 150_000);
+        assert_eq!(
+            bumped_details.received,
+            150_000 - sent_to_alice - bumped_details.fee.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_spendable_value() {
+        let (wallet, outpoints) = get_funded_wallet(&[100_000, 50_000, 20_000]);
+        let wallet = wallet.lock().unwrap();
+
+        assert_eq!(
+            get_spendable_value(&wallet, &vec![], &vec![]).unwrap(),
+            170_000
+        );
+        assert_eq!(
+            get_spendable_value(&wallet, &vec![outpoints[0]], &vec![]).unwrap(),
+            100_000
+        );
+        assert_eq!(
+            get_spendable_value(&wallet, &vec![], &vec![outpoints[1]]).unwrap(),
+            120_000
+        );
+
+        // Excluding wins over picking
+        assert_eq!(
+            get_spendable_value(&wallet, &outpoints[..2].to_vec(), &vec![outpoints[1]]).unwrap(),
+            100_000
+        );
+    }
+
+    #[test]
+    fn test_get_max_fee_rate() {
+        let (wallet, outpoints) = get_funded_wallet(&[100_000, 50_000]);
+        let wallet = wallet.lock().unwrap();
+        let alice = Address::from_str(ALICE).unwrap().script_pubkey();
+
+        let rate =
+            get_max_fee_rate(&[(alice.clone(), 140_000)], &wallet, &vec![], &vec![]).unwrap();
+        assert!(rate > 1.0);
+
+        // More than the coins hold is an error, not an underflow
+        let error = get_max_fee_rate(
+            &[(alice.clone(), 140_000)],
+            &wallet,
+            &vec![],
+            &vec![outpoints[1]],
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            error,
+            bdk::Error::InsufficientFunds {
+                needed: 140_000,
+                available: 100_000
+            }
+        ));
+
+        assert!(matches!(
+            get_max_fee_rate(&[(alice, 200_000)], &wallet, &vec![], &vec![]),
+            Err(bdk::Error::InsufficientFunds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_get_max_spendable() {
+        let (wallet, outpoints) = get_funded_wallet(&[100_000, 50_000]);
+        let wallet = wallet.lock().unwrap();
+        let alice = Address::from_str(ALICE).unwrap();
+
+        let everything =
+            get_max_spendable(alice.clone(), 0.00001, &wallet, &vec![], &vec![]).unwrap();
+        assert!(everything < 150_000 && everything > 149_000);
+
+        // Only the picked coin is drained
+        let picked = get_max_spendable(
+            alice.clone(),
+            0.00001,
+            &wallet,
+            &vec![outpoints[1]],
+            &vec![],
+        )
+        .unwrap();
+        assert!(picked < 50_000 && picked > 49_000);
+
+        let (psbt, _) = build_tx(
+            &[],
+            Some(alice.script_pubkey()),
+            0.00001,
+            None,
+            &wallet,
+            &vec![outpoints[1]],
+            &vec![],
+        )
+        .unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 1);
+        assert_eq!(psbt.unsigned_tx.input[0].previous_output, outpoints[1]);
+
+        let excluded =
+            get_max_spendable(alice, 0.00001, &wallet, &vec![], &vec![outpoints[0]]).unwrap();
+        assert_eq!(excluded, picked);
+    }
+
+    #[test]
+    fn test_get_wallet_arc_outlives_drop() {
+        let wallet = Arc::new(Mutex::new(get_test_wallet(bdk::bitcoin::Network::Testnet)));
+        let wallet = Arc::into_raw(wallet) as *mut Mutex<bdk::Wallet<Tree>>;
+        let arc = unsafe { get_wallet_arc(wallet) }.unwrap();
+        let guard = lock_wallet(&arc).unwrap();
+
+        // What wallet_drop does while a call is still running
+        unsafe { drop(Arc::from_raw(wallet)) };
+        assert!(guard.get_address(AddressIndex::New).is_ok());
+    }
+}